@@ -101,7 +101,7 @@ impl Shim for Service {
             Some(Arc::new(ShimExecutor::default())),
         )?;
 
-        runc.delete(&self.id, Some(&DeleteOpts { force: true }))
+        runc.delete(&self.id, Some(&DeleteOpts::new().force(true)))
             .await
             .unwrap_or_else(|e| warn!("failed to remove runc container: {}", e));
         let mut resp = DeleteResponse::new();
@@ -177,11 +177,25 @@ async fn process_exits(
                     }
 
                     // pid belongs to container common process
-                    for (_exec_id, p) in cont.processes.iter_mut() {
+                    for (exec_id, p) in cont.processes.iter_mut() {
                         // set exit for exec process
                         if p.pid == pid {
                             p.set_exited(exit_code).await;
-                            // TODO: publish event
+
+                            // publish event
+                            let ts = convert_to_timestamp(p.exited_at);
+                            let event = TaskExit {
+                                container_id: cont.id.to_string(),
+                                id: exec_id.to_string(),
+                                pid: pid as u32,
+                                exit_status: p.exit_code as u32,
+                                exited_at: Some(ts).into(),
+                                ..Default::default()
+                            };
+                            let topic = event.topic();
+                            tx.send((topic.to_string(), Box::new(event)))
+                                .await
+                                .unwrap_or_else(|e| warn!("send {} to publisher: {}", topic, e));
                             break;
                         }
                     }
@@ -219,3 +233,105 @@ async fn should_kill_all_on_exit(bundle_path: &str) -> bool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use containerd_shim::asynchronous::monitor::monitor_notify_by_pid;
+    use tokio::sync::oneshot::error::TryRecvError;
+
+    use super::*;
+    use crate::asynchronous::runc::tests::{exec_request, fake_container};
+
+    /// Process the exits of `container` like the shim does, the events are sent to the receiver.
+    async fn process_container_exits(
+        container: RuncContainer,
+    ) -> (
+        TaskService<RuncFactory, RuncContainer>,
+        Receiver<(String, Box<dyn MessageDyn>)>,
+    ) {
+        let (tx, rx) = channel(128);
+        let task = TaskService::new("default", Arc::new(ExitSignal::default()), tx.clone());
+        task.containers
+            .lock()
+            .await
+            .insert(container.id.to_string(), container);
+        let s = monitor_subscribe(Topic::Pid).await.unwrap();
+        process_exits(s, &task, tx).await;
+        (task, rx)
+    }
+
+    async fn next_exit(rx: &mut Receiver<(String, Box<dyn MessageDyn>)>) -> TaskExit {
+        let (topic, event) = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("no event published")
+            .unwrap();
+        assert_eq!(topic, TaskExit::default().topic());
+        event.downcast_ref::<TaskExit>().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_exec_exit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut container = fake_container(dir.path(), 4343);
+        container.init.pid = 4340;
+        container
+            .exec(exec_request("exec-1", &["false"]))
+            .await
+            .unwrap();
+        container.start(Some("exec-1")).await.unwrap();
+        let (task, mut rx) = process_container_exits(container).await;
+
+        monitor_notify_by_pid(4343, 3).await.unwrap();
+        let exit = next_exit(&mut rx).await;
+        assert_eq!(exit.container_id, "fake");
+        assert_eq!(exit.id, "exec-1");
+        assert_eq!(exit.pid, 4343);
+        assert_eq!(exit.exit_status, 3);
+        assert!(exit.exited_at.is_some());
+
+        let containers = task.containers.lock().await;
+        let container = &containers["fake"];
+        let (_, code, exited_at) = container.get_exit_info(Some("exec-1")).await.unwrap();
+        assert_eq!(code, 3);
+        assert!(exited_at.is_some());
+        // The init is still running.
+        assert!(container.init.exited_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_init_exit() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut container = fake_container(dir.path(), 4353);
+        let mut child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg("exit 3")
+            .spawn()
+            .unwrap();
+        let pid = child.id().unwrap() as i32;
+        container.init.pid = pid;
+        let mut wait = container.wait_channel(None).await.unwrap();
+        let (task, mut rx) = process_container_exits(container).await;
+
+        // What the reaper does once the init is gone.
+        let status = child.wait().await.unwrap();
+        monitor_notify_by_pid(pid, status.code().unwrap())
+            .await
+            .unwrap();
+
+        let exit = next_exit(&mut rx).await;
+        assert_eq!(exit.container_id, "fake");
+        assert_eq!(exit.id, "fake");
+        assert_eq!(exit.pid, pid as u32);
+        assert_eq!(exit.exit_status, 3);
+
+        let containers = task.containers.lock().await;
+        let (exit_pid, code, exited_at) = containers["fake"].get_exit_info(None).await.unwrap();
+        assert_eq!(exit_pid, pid);
+        assert_eq!(code, 3);
+        assert!(exited_at.is_some());
+        // Waiters are released once the exit is recorded.
+        assert_eq!(wait.try_recv(), Err(TryRecvError::Closed));
+    }
+}
@@ -22,7 +22,7 @@ use std::{
     },
     path::{Path, PathBuf},
     process::ExitStatus,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 
 use async_trait::async_trait;
@@ -38,26 +38,24 @@ use containerd_shim::{
     io_error,
     monitor::{ExitEvent, Subject, Topic},
     other, other_error,
-    protos::{
-        api::ProcessInfo,
-        cgroups::metrics::Metrics,
-        protobuf::{CodedInputStream, Message},
+    protos::{api::ProcessInfo, cgroups::metrics::Metrics, protobuf::Message},
+    util::{
+        asyncify, mkdir, mount_rootfs, read_file_to_str, read_spec, write_options, write_runtime,
     },
-    util::{asyncify, mkdir, mount_rootfs, read_file_to_str, write_options, write_runtime},
     Console, Error, ExitSignal, Result,
 };
 use log::{debug, error};
 use nix::{sys::signal::kill, unistd::Pid};
 use oci_spec::runtime::{LinuxResources, Process};
-use runc::{Command, Runc, Spawner};
+use runc::{io::Io, Command, Runc, Spawner};
 use tokio::{
     fs::{File, OpenOptions},
     io::{AsyncRead, AsyncReadExt, AsyncWrite},
 };
 
 use crate::common::{
-    check_kill_error, create_io, create_runc, get_spec_from_request, receive_socket, CreateConfig,
-    ProcessIO, ShimExecutor, INIT_PID_FILE,
+    cgroup_path, check_kill_error, create_io, create_runc, get_spec_from_request, host_io_ids,
+    parse_runtime_options, receive_socket, CreateConfig, ProcessIO, ShimExecutor, INIT_PID_FILE,
 };
 
 pub type ExecProcess = ProcessTemplate<RuncExecLifecycle>;
@@ -76,11 +74,7 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
         req: &CreateTaskRequest,
     ) -> containerd_shim::Result<RuncContainer> {
         let bundle = req.bundle();
-        let mut opts = Options::new();
-        if let Some(any) = req.options.as_ref() {
-            let mut input = CodedInputStream::from_bytes(any.value.as_ref());
-            opts.merge_from(&mut input)?;
-        }
+        let mut opts = parse_runtime_options(req.options.as_ref())?;
         if opts.compute_size() > 0 {
             debug!("create options: {:?}", &opts);
         }
@@ -88,6 +82,11 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
         write_options(bundle, &opts).await?;
         write_runtime(bundle, runtime).await?;
 
+        let spec = read_spec(bundle).await?;
+        let (io_uid, io_gid) = host_io_ids(&spec, opts.io_uid, opts.io_gid)?;
+        opts.io_uid = io_uid;
+        opts.io_gid = io_gid;
+
         let rootfs_vec = req.rootfs().to_vec();
         let rootfs = if !rootfs_vec.is_empty() {
             let tmp_rootfs = Path::new(bundle).join("rootfs");
@@ -112,14 +111,20 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
         let id = req.id();
         let stdio = Stdio::new(req.stdin(), req.stdout(), req.stderr(), req.terminal());
 
+        let cgroup = cgroup_path(&spec, opts.systemd_cgroup, id);
         let mut init = InitProcess::new(
             id,
             stdio,
-            RuncInitLifecycle::new(runc.clone(), opts.clone(), bundle),
+            RuncInitLifecycle::new(runc.clone(), opts.clone(), bundle, cgroup),
         );
 
         let config = CreateConfig::default();
         self.do_create(&mut init, config).await?;
+        debug!(
+            "container {} created in cgroup {}",
+            id,
+            init.lifecycle.cgroup()
+        );
         let container = RuncContainer {
             id: id.to_string(),
             bundle: bundle.to_string(),
@@ -173,6 +178,7 @@ impl RuncFactory {
             }
             return Err(other!("failed to create runc container: {}", e));
         }
+        *init.lifecycle.io.lock().unwrap() = pio.as_ref().and_then(|p| p.io.clone());
         copy_io_or_console(init, socket, pio, init.lifecycle.exit_signal.clone()).await?;
         let pid = read_file_to_str(pid_path).await?.parse::<i32>()?;
         init.pid = pid;
@@ -213,6 +219,7 @@ impl ProcessFactory<ExecProcess> for RuncExecFactory {
                 io_gid: self.io_gid,
                 spec: p,
                 exit_signal: Default::default(),
+                io: Default::default(),
             }),
         })
     }
@@ -223,6 +230,9 @@ pub struct RuncInitLifecycle {
     opts: Options,
     bundle: String,
     exit_signal: Arc<ExitSignal>,
+    io: Mutex<Option<Arc<dyn Io>>>,
+    /// Cgroup of the container below the cgroup mount, see [`cgroup_path`].
+    cgroup: String,
 }
 
 #[async_trait]
@@ -246,7 +256,11 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
             .kill(
                 p.id.as_str(),
                 signal,
-                Some(&runc::options::KillOpts { all }),
+                Some(
+                    &runc::options::KillOpts::new()
+                        .all(all)
+                        .ignore_not_running(false),
+                ),
             )
             .await
             .map_err(|e| check_kill_error(e.to_string()))
@@ -256,7 +270,7 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
         self.runtime
             .delete(
                 p.id.as_str(),
-                Some(&runc::options::DeleteOpts { force: true }),
+                Some(&runc::options::DeleteOpts::new().force(true)),
             )
             .await
             .or_else(|e| {
@@ -271,20 +285,36 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
         Ok(())
     }
 
-    #[cfg(target_os = "linux")]
-    async fn update(&self, p: &mut InitProcess, resources: &LinuxResources) -> Result<()> {
-        if p.pid <= 0 {
-            return Err(other!(
-                "failed to update resources because init process is {}",
-                p.pid
-            ));
+    async fn pause(&self, p: &mut InitProcess) -> Result<()> {
+        self.runtime
+            .pause(p.id.as_str())
+            .await
+            .map_err(other_error!(e, "failed pause"))?;
+        p.state = Status::PAUSED;
+        Ok(())
+    }
+
+    async fn resume(&self, p: &mut InitProcess) -> Result<()> {
+        self.runtime
+            .resume(p.id.as_str())
+            .await
+            .map_err(other_error!(e, "failed resume"))?;
+        p.state = Status::RUNNING;
+        Ok(())
+    }
+
+    async fn close_io(&self, _p: &mut InitProcess) -> Result<()> {
+        if let Some(io) = self.io.lock().unwrap().as_ref() {
+            io.close_stdin();
         }
-        containerd_shim::cgroup::update_resources(p.pid as u32, resources)
+        Ok(())
     }
 
-    #[cfg(not(target_os = "linux"))]
-    async fn update(&self, _p: &mut InitProcess, _resources: &LinuxResources) -> Result<()> {
-        Err(Error::Unimplemented("update resource".to_string()))
+    async fn update(&self, p: &mut InitProcess, resources: &LinuxResources) -> Result<()> {
+        self.runtime
+            .update(p.id.as_str(), resources)
+            .await
+            .map_err(other_error!(e, "failed update"))
     }
 
     #[cfg(target_os = "linux")]
@@ -320,7 +350,7 @@ impl ProcessLifecycle<InitProcess> for RuncInitLifecycle {
 }
 
 impl RuncInitLifecycle {
-    pub fn new(runtime: Runc, opts: Options, bundle: &str) -> Self {
+    pub fn new(runtime: Runc, opts: Options, bundle: &str, cgroup: String) -> Self {
         let work_dir = Path::new(bundle).join("work");
         let mut opts = opts;
         if opts.criu_path().is_empty() {
@@ -331,8 +361,14 @@ impl RuncInitLifecycle {
             opts,
             bundle: bundle.to_string(),
             exit_signal: Default::default(),
+            io: Default::default(),
+            cgroup,
         }
     }
+
+    pub fn cgroup(&self) -> &str {
+        &self.cgroup
+    }
 }
 
 pub struct RuncExecLifecycle {
@@ -343,6 +379,7 @@ pub struct RuncExecLifecycle {
     io_gid: u32,
     spec: Process,
     exit_signal: Arc<ExitSignal>,
+    io: Mutex<Option<Arc<dyn Io>>>,
 }
 
 #[async_trait]
@@ -354,6 +391,7 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
             pid_file: Some(pid_path.to_owned()),
             console_socket: None,
             detach: true,
+            ..Default::default()
         };
         let (socket, pio) = if p.stdio.terminal {
             let s = ConsoleSocket::new().await?;
@@ -375,6 +413,7 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
             }
             return Err(other!("failed to start runc exec: {}", e));
         }
+        *self.io.lock().unwrap() = pio.as_ref().and_then(|p| p.io.clone());
         copy_io_or_console(p, socket, pio, p.lifecycle.exit_signal.clone()).await?;
         let pid = read_file_to_str(pid_path).await?.parse::<i32>()?;
         p.pid = pid;
@@ -409,6 +448,21 @@ impl ProcessLifecycle<ExecProcess> for RuncExecLifecycle {
         Ok(())
     }
 
+    async fn pause(&self, _p: &mut ExecProcess) -> Result<()> {
+        Err(Error::Unimplemented("exec pause".to_string()))
+    }
+
+    async fn resume(&self, _p: &mut ExecProcess) -> Result<()> {
+        Err(Error::Unimplemented("exec resume".to_string()))
+    }
+
+    async fn close_io(&self, _p: &mut ExecProcess) -> Result<()> {
+        if let Some(io) = self.io.lock().unwrap().as_ref() {
+            io.close_stdin();
+        }
+        Ok(())
+    }
+
     async fn update(&self, _p: &mut ExecProcess, _resources: &LinuxResources) -> Result<()> {
         Err(Error::Unimplemented("exec update".to_string()))
     }
@@ -675,3 +729,100 @@ async fn wait_pid(pid: i32, s: Subscription) -> i32 {
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use ::runc::options::GlobalOpts;
+    use containerd_shim::{
+        asynchronous::container::Container, protos::protobuf::well_known_types::any::Any,
+    };
+
+    use super::*;
+
+    /// A container in `bundle`, the fake runc gives its exec processes the pid `pid`.
+    pub(crate) fn fake_container(bundle: &Path, pid: i32) -> RuncContainer {
+        let path = bundle.join("runc");
+        fs::write(
+            &path,
+            format!(
+                r#"#!/bin/sh
+prev=
+for a; do
+    if [ "$prev" = --pid-file ]; then echo {} > "$a"; fi
+    prev=$a
+done
+"#,
+                pid
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        let runc = GlobalOpts::new().command(path).build().unwrap();
+        let bundle = bundle.to_str().unwrap();
+        let lifecycle =
+            RuncInitLifecycle::new(runc.clone(), Options::new(), bundle, "/fake".to_string());
+        RuncContainer {
+            id: "fake".to_string(),
+            bundle: bundle.to_string(),
+            init: InitProcess::new("fake", Stdio::new("", "", "", false), lifecycle),
+            process_factory: RuncExecFactory {
+                runtime: runc,
+                bundle: bundle.to_string(),
+                io_uid: 0,
+                io_gid: 0,
+            },
+            processes: Default::default(),
+        }
+    }
+
+    /// A request to exec `args` in the container of [`fake_container`].
+    pub(crate) fn exec_request(exec_id: &str, args: &[&str]) -> ExecProcessRequest {
+        let mut process = Process::default();
+        process.set_args(Some(args.iter().map(|a| a.to_string()).collect()));
+        let spec = Any {
+            type_url: "types.containerd.io/opencontainers/runtime-spec/1/Process".to_string(),
+            value: serde_json::to_vec(&process).unwrap(),
+            ..Default::default()
+        };
+        ExecProcessRequest {
+            id: "fake".to_string(),
+            exec_id: exec_id.to_string(),
+            spec: Some(spec).into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_exec() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut container = fake_container(dir.path(), 4242);
+
+        container
+            .exec(exec_request("exec-1", &["ls", "-l"]))
+            .await
+            .unwrap();
+        let p = &container.processes["exec-1"];
+        assert_eq!(p.id, "exec-1");
+        assert_eq!(p.state, Status::CREATED);
+        assert_eq!(p.lifecycle.container_id, "fake");
+        assert_eq!(
+            p.lifecycle.spec.args().as_deref(),
+            Some(&["ls".to_string(), "-l".to_string()][..])
+        );
+
+        assert_eq!(container.start(Some("exec-1")).await.unwrap(), 4242);
+        assert_eq!(container.processes["exec-1"].state, Status::RUNNING);
+        let state = container.state(Some("exec-1")).await.unwrap();
+        assert_eq!(state.id, "exec-1");
+        assert_eq!(state.pid, 4242);
+        assert!(container.has_pid(4242).await);
+
+        // A request without a spec isn't registered.
+        let mut req = exec_request("exec-2", &["ls"]);
+        req.spec = Default::default();
+        assert!(container.exec(req).await.is_err());
+        assert!(!container.processes.contains_key("exec-2"));
+    }
+}
@@ -20,6 +20,7 @@ use containerd_shim::{
     api::{ExecProcessRequest, Options},
     io::Stdio,
     io_error, other, other_error,
+    protos::protobuf::{well_known_types::any::Any, Message, MessageFull},
     util::IntoOption,
     Error,
 };
@@ -31,7 +32,7 @@ use nix::{
         termios::tcgetattr,
     },
 };
-use oci_spec::runtime::{LinuxNamespaceType, Spec};
+use oci_spec::runtime::{LinuxIdMapping, LinuxNamespaceType, Spec};
 use runc::{
     io::{Io, NullIo, FIFO},
     options::GlobalOpts,
@@ -119,6 +120,25 @@ pub fn get_spec_from_request(
     }
 }
 
+/// Decode the runtime [`Options`] carried by the `options` of a `CreateTaskRequest`.
+///
+/// Without options, or with an empty type URL, the default options are used.
+pub fn parse_runtime_options(any: Option<&Any>) -> containerd_shim::Result<Options> {
+    let any = match any {
+        Some(any) if !any.type_url.is_empty() => any,
+        _ => return Ok(Options::new()),
+    };
+    // The type URL may be prefixed, e.g. "type.googleapis.com/containerd.runc.v1.Options".
+    let type_name = any.type_url.rsplit('/').next().unwrap_or_default();
+    if type_name != Options::descriptor().full_name() {
+        return Err(Error::InvalidArgument(format!(
+            "unsupported runtime options type {}",
+            any.type_url
+        )));
+    }
+    Ok(Options::parse_from_bytes(&any.value)?)
+}
+
 pub fn check_kill_error(emsg: String) -> Error {
     let emsg = emsg.to_lowercase();
     if emsg.contains("process already finished")
@@ -157,11 +177,14 @@ pub fn create_runc(
     .join(namespace);
 
     let log = bundle.as_ref().join("log.json");
+    // The shim may not be able to write to XDG_RUNTIME_DIR or the temp dir, e.g. with a
+    // read-only rootfs, but it always owns the bundle.
     let mut gopts = GlobalOpts::default()
         .command(runtime)
         .root(root)
         .log(log)
         .log_json()
+        .spec_dir(bundle.as_ref())
         .systemd_cgroup(opts.systemd_cgroup);
     if let Some(s) = spawner {
         gopts.custom_spawner(s);
@@ -224,3 +247,241 @@ pub fn has_shared_pid_namespace(spec: &Spec) -> bool {
         },
     }
 }
+
+/// Path of the container's cgroup below the cgroup mount, as runc derives it from
+/// `linux.cgroupsPath` of the spec, e.g. `/system.slice/containerd-<id>.scope` for
+/// `system.slice:containerd:<id>` with the systemd driver.
+///
+/// Without a `cgroupsPath` runc uses `/<id>`, or `/system.slice/runc-<id>.scope` with systemd.
+/// containerd usually sets it, e.g. to `/<namespace>/<id>`.
+pub fn cgroup_path(spec: &Spec, systemd_cgroup: bool, id: &str) -> String {
+    let path = match spec.linux() {
+        Some(linux) => linux
+            .cgroups_path()
+            .as_ref()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        None => String::new(),
+    };
+    if !systemd_cgroup {
+        return match path.as_str() {
+            "" => format!("/{}", id),
+            p if p.starts_with('/') => path,
+            p => format!("/{}", p),
+        };
+    }
+
+    let parts: Vec<&str> = path.split(':').collect();
+    let (slice, prefix, name) = match parts[..] {
+        [""] => ("", "runc", id),
+        [slice, prefix, name] => (slice, prefix, name),
+        // runc refuses it, there's no cgroup to report
+        _ => return path,
+    };
+    let slice = if slice.is_empty() {
+        "system.slice"
+    } else {
+        slice
+    };
+    let unit = if name.ends_with(".slice") {
+        name.to_string()
+    } else if prefix.is_empty() {
+        format!("{}.scope", name)
+    } else {
+        format!("{}-{}.scope", prefix, name)
+    };
+    format!("{}/{}", expand_slice(slice), unit)
+}
+
+/// Path of a systemd slice, its parents are named by the dash separated prefixes of its name,
+/// e.g. `/a.slice/a-b.slice` for `a-b.slice`.
+fn expand_slice(slice: &str) -> String {
+    let name = slice.trim_end_matches(".slice");
+    // the root slice
+    if name == "-" {
+        return String::new();
+    }
+    let mut path = String::new();
+    let mut prefix = String::new();
+    for part in name.split('-') {
+        if !prefix.is_empty() {
+            prefix.push('-');
+        }
+        prefix.push_str(part);
+        path.push_str(&format!("/{}.slice", prefix));
+    }
+    path
+}
+
+/// Translate the configured io uid/gid from container ids to host ids, according to the
+/// uid/gid mappings of the container's user namespace.
+///
+/// The pipes for process io are owned on the host side, so they must be chowned with the host ids,
+/// otherwise the process can't open its stdio when running in a user namespace.
+/// Ids without a matching mapping are returned unchanged, a mapping past the largest host id
+/// is an error.
+pub fn host_io_ids(spec: &Spec, io_uid: u32, io_gid: u32) -> containerd_shim::Result<(u32, u32)> {
+    match spec.linux() {
+        None => Ok((io_uid, io_gid)),
+        Some(linux) => Ok((
+            map_to_host_id(linux.uid_mappings().as_deref(), io_uid)?,
+            map_to_host_id(linux.gid_mappings().as_deref(), io_gid)?,
+        )),
+    }
+}
+
+fn map_to_host_id(mappings: Option<&[LinuxIdMapping]>, id: u32) -> containerd_shim::Result<u32> {
+    let mapping = mappings.and_then(|mappings| {
+        mappings.iter().find_map(|m| {
+            let offset = id.checked_sub(m.container_id())?;
+            if offset < m.size() {
+                Some((m, offset))
+            } else {
+                None
+            }
+        })
+    });
+    match mapping {
+        None => Ok(id),
+        Some((m, offset)) => m.host_id().checked_add(offset).ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "id {} maps past the largest host id from host id {}",
+                id,
+                m.host_id()
+            ))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_shim::util::convert_to_any;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_runtime_options() {
+        assert_eq!(parse_runtime_options(None).unwrap(), Options::new());
+
+        let opts = Options {
+            binary_name: "/usr/local/bin/crun".to_string(),
+            root: "/run/containerd/crun".to_string(),
+            systemd_cgroup: true,
+            ..Default::default()
+        };
+        let any = convert_to_any(Box::new(opts.clone())).unwrap();
+        assert_eq!(parse_runtime_options(Some(&any)).unwrap(), opts);
+
+        let mut prefixed = any.clone();
+        prefixed.type_url = format!("type.googleapis.com/{}", any.type_url);
+        assert_eq!(parse_runtime_options(Some(&prefixed)).unwrap(), opts);
+
+        let mut untyped = any.clone();
+        untyped.type_url = String::new();
+        assert_eq!(
+            parse_runtime_options(Some(&untyped)).unwrap(),
+            Options::new()
+        );
+
+        let mut unknown = any;
+        unknown.type_url = "containerd.runhcs.v1.Options".to_string();
+        assert!(matches!(
+            parse_runtime_options(Some(&unknown)),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_cgroup_path() {
+        let spec = |path: &str| -> Spec {
+            serde_json::from_str(&format!(
+                r#"{{"ociVersion": "1.0.2", "linux": {{"cgroupsPath": "{}"}}}}"#,
+                path
+            ))
+            .unwrap()
+        };
+        let none: Spec = serde_json::from_str(r#"{"ociVersion": "1.0.2"}"#).unwrap();
+
+        // cgroupfs
+        assert_eq!(
+            cgroup_path(&spec("/default/fake"), false, "fake"),
+            "/default/fake"
+        );
+        assert_eq!(
+            cgroup_path(&spec("default/fake"), false, "fake"),
+            "/default/fake"
+        );
+        assert_eq!(cgroup_path(&none, false, "fake"), "/fake");
+
+        // systemd
+        assert_eq!(
+            cgroup_path(&spec("system.slice:containerd:fake"), true, "fake"),
+            "/system.slice/containerd-fake.scope"
+        );
+        assert_eq!(
+            cgroup_path(
+                &spec("kubepods-besteffort-pod1.slice:cri-containerd:fake"),
+                true,
+                "fake"
+            ),
+            "/kubepods.slice/kubepods-besteffort.slice/kubepods-besteffort-pod1.slice/cri-containerd-fake.scope"
+        );
+        assert_eq!(
+            cgroup_path(&spec(":containerd:fake"), true, "fake"),
+            "/system.slice/containerd-fake.scope"
+        );
+        assert_eq!(
+            cgroup_path(&spec("-.slice::machine.slice"), true, "fake"),
+            "/machine.slice"
+        );
+        assert_eq!(
+            cgroup_path(&none, true, "fake"),
+            "/system.slice/runc-fake.scope"
+        );
+    }
+
+    #[test]
+    fn test_host_io_ids() {
+        let spec: Spec = serde_json::from_str(
+            r#"
+            {
+                "ociVersion": "1.0.2",
+                "linux": {
+                    "uidMappings": [
+                        { "containerID": 0, "hostID": 100000, "size": 1000 },
+                        { "containerID": 1000, "hostID": 1000, "size": 1 }
+                    ],
+                    "gidMappings": [
+                        { "containerID": 0, "hostID": 200000, "size": 65536 }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(host_io_ids(&spec, 0, 0).unwrap(), (100000, 200000));
+        assert_eq!(host_io_ids(&spec, 10, 20).unwrap(), (100010, 200020));
+        assert_eq!(host_io_ids(&spec, 1000, 1000).unwrap(), (1000, 201000));
+        // unmapped ids are left as they are
+        assert_eq!(host_io_ids(&spec, 2000, 70000).unwrap(), (2000, 70000));
+
+        let spec: Spec = serde_json::from_str(r#"{ "ociVersion": "1.0.2" }"#).unwrap();
+        assert_eq!(host_io_ids(&spec, 10, 20).unwrap(), (10, 20));
+
+        let spec: Spec = serde_json::from_str(
+            r#"
+            {
+                "ociVersion": "1.0.2",
+                "linux": {
+                    "uidMappings": [
+                        { "containerID": 0, "hostID": 4294967000, "size": 1000 }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            host_io_ids(&spec, 500, 0),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
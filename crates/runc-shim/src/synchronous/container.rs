@@ -31,7 +31,7 @@ use shim::{
     console::ConsoleSocket,
     error::{Error, Result},
     io::Stdio,
-    io_error, ioctl_set_winsz, other, other_error,
+    io_error, ioctl_set_winsz, other_error,
     protos::cgroups::metrics::Metrics,
     util::{convert_to_timestamp, read_pid_from_file},
     Console,
@@ -65,6 +65,7 @@ pub trait Process {
     fn copy_io(&self) -> Result<()>;
     fn set_pid_from_file(&mut self, pid_path: &Path) -> Result<()>;
     fn resize_pty(&mut self, height: u32, width: u32) -> Result<()>;
+    fn close_io(&mut self) -> Result<()>;
 }
 
 pub trait Container {
@@ -75,12 +76,17 @@ pub trait Container {
     fn get_exit_info(&self, exec_id: Option<&str>) -> Result<(i32, i32, Option<OffsetDateTime>)>;
     fn delete(&mut self, exec_id_opt: Option<&str>) -> Result<(i32, i32, Option<OffsetDateTime>)>;
     fn exec(&mut self, req: ExecProcessRequest) -> Result<()>;
+    fn pause(&mut self) -> Result<()>;
+    fn resume(&mut self) -> Result<()>;
     fn resize_pty(&mut self, exec_id: Option<&str>, height: u32, width: u32) -> Result<()>;
+    fn close_io(&mut self, exec_id: Option<&str>) -> Result<()>;
     fn pid(&self) -> i32;
     fn stats(&self) -> Result<Metrics>;
     fn update(&mut self, resources: &LinuxResources) -> Result<()>;
     fn pids(&self) -> Result<PidsResponse>;
     fn id(&self) -> String;
+    /// Path of the container's cgroup below the cgroup mount.
+    fn cgroup(&self) -> &str;
 }
 
 pub struct CommonContainer<T, E> {
@@ -129,9 +135,14 @@ where
         Ok(resp)
     }
 
-    #[allow(unused)]
     pub fn exec(&mut self, req: ExecProcessRequest) -> Result<()> {
         let exec_id = req.exec_id.to_string();
+        if self.processes.contains_key(&exec_id) {
+            return Err(Error::AlreadyExistsError(format!(
+                "exec process {}",
+                exec_id
+            )));
+        }
         let exec_process = E::try_from(req).map_err(other_error!(e, "convert ExecProcess"))?;
         self.processes.insert(exec_id, exec_process);
         Ok(())
@@ -155,16 +166,13 @@ where
     }
 
     pub fn resize_pty(&mut self, exec_id: Option<&str>, height: u32, width: u32) -> Result<()> {
-        match exec_id {
-            Some(exec_id) => {
-                let process = self.processes.get_mut(exec_id).ok_or_else(|| {
-                    Error::NotFoundError("can not find the exec by id".to_string())
-                })?;
-                process.resize_pty(height, width)?;
-                Ok(())
-            }
-            None => Ok(()),
-        }
+        let process = self.get_mut_process(exec_id)?;
+        process.resize_pty(height, width)
+    }
+
+    pub fn close_io(&mut self, exec_id: Option<&str>) -> Result<()> {
+        let process = self.get_mut_process(exec_id)?;
+        process.close_io()
     }
 }
 
@@ -320,7 +328,17 @@ impl Process for CommonProcess {
                     .map(|_x| ())
                     .map_err(Into::into)
             },
-            None => Err(other!("there is no console")),
+            None => Err(Error::FailedPreconditionError(format!(
+                "process {} has no console",
+                self.id
+            ))),
         }
     }
+
+    fn close_io(&mut self) -> Result<()> {
+        if let Some(io) = self.io.as_ref().and_then(|pio| pio.io.as_ref()) {
+            io.close_stdin();
+        }
+        Ok(())
+    }
 }
@@ -44,10 +44,7 @@ use shim::{
     mount::mount_rootfs,
     other, other_error,
     protos::{
-        api::ProcessInfo,
-        cgroups::metrics::Metrics,
-        protobuf::{CodedInputStream, Message},
-        shim::oci::ProcessDetails,
+        api::ProcessInfo, cgroups::metrics::Metrics, protobuf::Message, shim::oci::ProcessDetails,
     },
     util::{convert_to_any, read_spec_from_file, write_options, write_runtime, IntoOption},
     Console,
@@ -56,7 +53,10 @@ use time::OffsetDateTime;
 
 use crate::{
     common,
-    common::{create_io, has_shared_pid_namespace, CreateConfig, ShimExecutor, INIT_PID_FILE},
+    common::{
+        create_io, has_shared_pid_namespace, host_io_ids, parse_runtime_options, CreateConfig,
+        ShimExecutor, INIT_PID_FILE,
+    },
     synchronous::container::{
         CommonContainer, CommonProcess, Container, ContainerFactory, Process,
     },
@@ -68,11 +68,7 @@ pub(crate) struct RuncFactory {}
 impl ContainerFactory<RuncContainer> for RuncFactory {
     fn create(&self, ns: &str, req: &CreateTaskRequest) -> Result<RuncContainer> {
         let bundle = req.bundle.as_str();
-        let mut opts = Options::new();
-        if let Some(any) = req.options.as_ref() {
-            let mut input = CodedInputStream::from_bytes(any.value.as_ref());
-            opts.merge_from(&mut input)?;
-        }
+        let mut opts = parse_runtime_options(req.options.as_ref())?;
         if opts.compute_size() > 0 {
             debug!("create options: {:?}", &opts);
         }
@@ -124,8 +120,10 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
             .to_str()
             .ok_or_else(|| other!("failed to get work_dir str"))?;
         init.work_dir = work_dir.to_string();
-        init.io_uid = opts.io_uid();
-        init.io_gid = opts.io_gid();
+        let spec = read_spec_from_file(bundle)?;
+        let (io_uid, io_gid) = host_io_ids(&spec, opts.io_uid(), opts.io_gid())?;
+        init.io_uid = io_uid;
+        init.io_gid = io_gid;
         init.no_pivot_root = opts.no_pivot_root();
         init.no_new_key_ring = opts.no_new_keyring();
         init.criu_work_path = if opts.criu_path().is_empty() {
@@ -134,9 +132,12 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
             opts.criu_path().to_string()
         };
 
+        let cgroup = common::cgroup_path(&spec, opts.systemd_cgroup, id);
+
         let config = CreateConfig::default();
         init.create(&config)?;
         let container = RuncContainer {
+            cgroup,
             common: CommonContainer {
                 id: id.to_string(),
                 bundle: bundle.to_string(),
@@ -150,6 +151,8 @@ impl ContainerFactory<RuncContainer> for RuncFactory {
 
 pub(crate) struct RuncContainer {
     pub(crate) common: CommonContainer<InitProcess, ExecProcess>,
+    /// Cgroup of the container below the cgroup mount, see [`common::cgroup_path`].
+    cgroup: String,
 }
 
 impl Container for RuncContainer {
@@ -170,6 +173,7 @@ impl Container for RuncContainer {
                     pid_file: Some(pid_path.to_owned()),
                     console_socket: None,
                     detach: true,
+                    ..Default::default()
                 };
                 let terminal = process.common.stdio.terminal;
                 let socket = if terminal {
@@ -242,7 +246,11 @@ impl Container for RuncContainer {
                 .kill(
                     self.id().as_str(),
                     signal,
-                    Some(&runc::options::KillOpts { all }),
+                    Some(
+                        &runc::options::KillOpts::new()
+                            .all(all)
+                            .ignore_not_running(false),
+                    ),
                 )
                 .map_err(|e| common::check_kill_error(format!("{}", e))),
         }
@@ -270,7 +278,7 @@ impl Container for RuncContainer {
                     .runtime
                     .delete(
                         self.id().as_str(),
-                        Some(&runc::options::DeleteOpts { force: true }),
+                        Some(&runc::options::DeleteOpts::new().force(true)),
                     )
                     .or_else(|e| {
                         if !e.to_string().to_lowercase().contains("does not exist") {
@@ -286,9 +294,30 @@ impl Container for RuncContainer {
     }
 
     fn exec(&mut self, req: ExecProcessRequest) -> Result<()> {
+        self.common.exec(req).map_err(|e| match e {
+            Error::AlreadyExistsError(_) => e,
+            e => other!("failed exec: {}", e),
+        })
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.common
+            .init
+            .runtime
+            .pause(self.common.id.as_str())
+            .map_err(other_error!(e, "failed pause"))?;
+        self.common.init.set_status(Status::PAUSED);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
         self.common
-            .exec(req)
-            .map_err(other_error!(e, "failed exec"))
+            .init
+            .runtime
+            .resume(self.common.id.as_str())
+            .map_err(other_error!(e, "failed resume"))?;
+        self.common.init.set_status(Status::RUNNING);
+        Ok(())
     }
 
     fn resize_pty(&mut self, exec_id: Option<&str>, height: u32, width: u32) -> Result<()> {
@@ -297,6 +326,10 @@ impl Container for RuncContainer {
             .map_err(other_error!(e, "failed resize pty"))
     }
 
+    fn close_io(&mut self, exec_id: Option<&str>) -> Result<()> {
+        self.common.close_io(exec_id)
+    }
+
     fn pid(&self) -> i32 {
         self.common.init.pid()
     }
@@ -312,15 +345,12 @@ impl Container for RuncContainer {
         Err(Error::Unimplemented("stats".to_string()))
     }
 
-    #[cfg(target_os = "linux")]
-    fn update(&mut self, resources: &LinuxResources) -> Result<()> {
-        let pid = self.common.init.pid() as u32;
-        containerd_shim::cgroup::update_resources(pid, resources)
-    }
-
-    #[cfg(not(target_os = "linux"))]
     fn update(&mut self, resources: &LinuxResources) -> Result<()> {
-        Err(Error::Unimplemented("update".to_string()))
+        self.common
+            .init
+            .runtime
+            .update(self.common.id.as_str(), resources)
+            .map_err(other_error!(e, "failed update"))
     }
 
     fn pids(&self) -> Result<PidsResponse> {
@@ -358,6 +388,10 @@ impl Container for RuncContainer {
     fn id(&self) -> String {
         self.common.id.to_string()
     }
+
+    fn cgroup(&self) -> &str {
+        &self.cgroup
+    }
 }
 
 impl RuncContainer {
@@ -538,6 +572,10 @@ impl Process for InitProcess {
     fn resize_pty(&mut self, height: u32, width: u32) -> Result<()> {
         self.common.resize_pty(height, width)
     }
+
+    fn close_io(&mut self) -> Result<()> {
+        self.common.close_io()
+    }
 }
 
 pub(crate) struct ExecProcess {
@@ -613,6 +651,10 @@ impl Process for ExecProcess {
     fn resize_pty(&mut self, height: u32, width: u32) -> Result<()> {
         self.common.resize_pty(height, width)
     }
+
+    fn close_io(&mut self) -> Result<()> {
+        self.common.close_io()
+    }
 }
 
 impl TryFrom<ExecProcessRequest> for ExecProcess {
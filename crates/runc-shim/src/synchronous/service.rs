@@ -107,7 +107,7 @@ impl Shim for Service {
             &opts,
             Some(Arc::new(ShimExecutor::default())),
         )?;
-        runc.delete(&self.id, Some(&DeleteOpts { force: true }))
+        runc.delete(&self.id, Some(&DeleteOpts::new().force(true)))
             .unwrap_or_else(|e| warn!("failed to remove runc container: {}", e));
         let mut resp = DeleteResponse::new();
         // sigkill
@@ -185,11 +185,27 @@ impl Service {
                         }
 
                         // pid belongs to container common process
-                        for (_exec_id, p) in cont.common.processes.iter_mut() {
+                        let container_id = cont.id();
+                        for (exec_id, p) in cont.common.processes.iter_mut() {
                             // set exit for exec process
                             if p.pid() == pid {
                                 p.set_exited(exit_code);
-                                // TODO: publish event
+
+                                // publish event
+                                let ts = convert_to_timestamp(p.exited_at());
+                                let event = TaskExit {
+                                    container_id: container_id.to_string(),
+                                    id: exec_id.to_string(),
+                                    pid: pid as u32,
+                                    exit_status: p.exit_code() as u32,
+                                    exited_at: Some(ts).into(),
+                                    ..Default::default()
+                                };
+                                let topic = event.topic();
+                                tx.send((topic.to_string(), Box::new(event)))
+                                    .unwrap_or_else(|e| {
+                                        warn!("send {} to publisher: {}", topic, e)
+                                    });
                                 break;
                             }
                         }
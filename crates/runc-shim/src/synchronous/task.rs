@@ -28,7 +28,10 @@ use shim::{
     event::Event,
     other_error,
     protos::{
-        events::task::{TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskIO, TaskStart},
+        events::task::{
+            TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskIO, TaskPaused,
+            TaskResumed, TaskStart,
+        },
         protobuf::MessageDyn,
     },
     util::{convert_to_any, convert_to_timestamp, IntoOption},
@@ -108,6 +111,7 @@ where
         let mut resp = CreateTaskResponse::new();
         let pid = container.pid() as u32;
         resp.pid = pid;
+        let cgroup = container.cgroup().to_string();
 
         containers.insert(id.to_string(), container);
 
@@ -128,7 +132,10 @@ where
             ..Default::default()
         });
 
-        info!("Create request for {} returns pid {}", id, pid);
+        info!(
+            "Create request for {} returns pid {} in cgroup {}",
+            id, pid, cgroup
+        );
         Ok(resp)
     }
 
@@ -245,6 +252,34 @@ where
         Ok(Empty::new())
     }
 
+    fn pause(&self, _ctx: &TtrpcContext, req: PauseRequest) -> TtrpcResult<Empty> {
+        info!("Pause request for {:?}", req);
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers.get_mut(req.id()).ok_or_else(|| {
+            Error::NotFoundError(format!("can not find container by id {}", req.id()))
+        })?;
+        container.pause()?;
+        self.send_event(TaskPaused {
+            container_id: req.id.to_string(),
+            ..Default::default()
+        });
+        Ok(Empty::new())
+    }
+
+    fn resume(&self, _ctx: &TtrpcContext, req: ResumeRequest) -> TtrpcResult<Empty> {
+        info!("Resume request for {:?}", req);
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers.get_mut(req.id()).ok_or_else(|| {
+            Error::NotFoundError(format!("can not find container by id {}", req.id()))
+        })?;
+        container.resume()?;
+        self.send_event(TaskResumed {
+            container_id: req.id.to_string(),
+            ..Default::default()
+        });
+        Ok(Empty::new())
+    }
+
     fn resize_pty(&self, _ctx: &TtrpcContext, req: ResizePtyRequest) -> TtrpcResult<Empty> {
         debug!(
             "Resize pty request for container {}, exec_id: {}",
@@ -262,8 +297,19 @@ where
         Ok(Empty::new())
     }
 
-    fn close_io(&self, _ctx: &TtrpcContext, _req: CloseIORequest) -> TtrpcResult<Empty> {
-        // unnecessary close io here since fd was closed automatically after object was destroyed.
+    fn close_io(&self, _ctx: &TtrpcContext, req: CloseIORequest) -> TtrpcResult<Empty> {
+        debug!(
+            "Close io request for container {}, exec_id: {}",
+            &req.id, &req.exec_id
+        );
+        if !req.stdin {
+            return Ok(Empty::new());
+        }
+        let mut containers = self.containers.lock().unwrap();
+        let container = containers
+            .get_mut(req.id())
+            .ok_or_else(|| Error::Other(format!("can not find container by id {}", req.id())))?;
+        container.close_io(req.exec_id.as_str().none_if(|&x| x.is_empty()))?;
         Ok(Empty::new())
     }
 
@@ -0,0 +1,332 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Receiving side of `--console-socket`.
+//!
+//! For a container whose process has `terminal: true`, runc allocates the pty itself and sends
+//! the master end over the unix socket passed with `--console-socket`:
+//!
+//! ```ignore
+//! let socket = ConsoleSocket::bind(dir.join("pty.sock"))?;
+//! let opts = CreateOpts::new().console_socket(socket.path());
+//! runc.create(id, bundle, Some(&opts))?;
+//! let console = socket.receive()?;
+//! ```
+
+use std::{
+    fs::File,
+    io::{IoSliceMut, Read, Write},
+    os::unix::{
+        io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use nix::{
+    cmsg_space,
+    sys::socket::{recvmsg, ControlMessageOwned, MsgFlags, UnixAddr},
+};
+
+use crate::error::Error;
+
+nix::ioctl_write_ptr_bad!(ioctl_set_winsz, libc::TIOCSWINSZ, libc::winsize);
+
+/// Unix socket runc connects to in order to hand over the pty master.
+#[derive(Debug)]
+pub struct ConsoleSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ConsoleSocket {
+    /// Bind a listening socket at `path`, which must not exist yet.
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path).map_err(Error::UnixSocketBindFailed)?;
+        Ok(Self { listener, path })
+    }
+
+    /// Path to pass to [`CreateOpts::console_socket`](crate::options::CreateOpts::console_socket)
+    /// or [`ExecOpts::console_socket`](crate::options::ExecOpts::console_socket).
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Wait for runc to connect and send the pty master.
+    #[cfg(not(feature = "async"))]
+    pub fn receive(&self) -> Result<Console, Error> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .map_err(Error::UnixSocketConnectionFailed)?;
+        receive_fd(&stream)
+    }
+
+    /// Wait for runc to connect and send the pty master.
+    ///
+    /// Dropping the future stops waiting, e.g. when runc fails before connecting.
+    #[cfg(feature = "async")]
+    pub async fn receive(&self) -> Result<Console, Error> {
+        use tokio::io::Interest;
+
+        let listener = self
+            .listener
+            .try_clone()
+            .and_then(|listener| {
+                listener.set_nonblocking(true)?;
+                tokio::net::UnixListener::from_std(listener)
+            })
+            .map_err(Error::UnixSocketConnectionFailed)?;
+        let (stream, _) = listener
+            .accept()
+            .await
+            .map_err(Error::UnixSocketConnectionFailed)?;
+        loop {
+            stream
+                .readable()
+                .await
+                .map_err(Error::UnixSocketConnectionFailed)?;
+            // Readiness may be spurious, WouldBlock clears it so the wait starts over.
+            let received = stream.try_io(Interest::READABLE, || match receive_fd(&stream) {
+                Err(Error::UnixSocketConnectionFailed(e)) => Err(e),
+                r => Ok(r),
+            });
+            match received {
+                Ok(r) => return r,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(Error::UnixSocketConnectionFailed(e)),
+            }
+        }
+    }
+}
+
+impl Drop for ConsoleSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Receive a single fd sent with SCM_RIGHTS, runc sends the pty path as payload.
+///
+/// Fails with [`std::io::ErrorKind::WouldBlock`] if `stream` is non-blocking and nothing was
+/// sent yet.
+fn receive_fd(stream: &impl AsRawFd) -> Result<Console, Error> {
+    let mut buf = [0u8; 4096];
+    let mut iov = [IoSliceMut::new(&mut buf)];
+    let mut space = cmsg_space!([RawFd; 2]);
+    // The fds can't be received close-on-exec elsewhere, runc only runs on Linux anyway.
+    #[cfg(target_os = "linux")]
+    let flags = MsgFlags::MSG_CMSG_CLOEXEC;
+    #[cfg(not(target_os = "linux"))]
+    let flags = MsgFlags::empty();
+    let msg = recvmsg::<UnixAddr>(stream.as_raw_fd(), &mut iov, Some(&mut space), flags).map_err(
+        |e| match e {
+            nix::errno::Errno::EAGAIN => {
+                Error::UnixSocketConnectionFailed(std::io::ErrorKind::WouldBlock.into())
+            }
+            _ => Error::UnixSocketReceiveMessageFailed,
+        },
+    )?;
+    if msg.bytes == 0 {
+        return Err(Error::UnixSocketClosed);
+    }
+
+    let mut fds = vec![];
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::ScmRights(received) = cmsg {
+            fds.extend(received);
+        }
+    }
+    // Take ownership of everything received so extra fds are closed, but keep only the first.
+    let mut files: Vec<File> = fds
+        .into_iter()
+        .map(|fd| unsafe { File::from_raw_fd(fd) })
+        .collect();
+    if files.is_empty() {
+        return Err(Error::UnixSocketReceiveMessageFailed);
+    }
+    Ok(Console {
+        file: files.swap_remove(0),
+    })
+}
+
+/// Master end of a container's pty.
+#[derive(Debug)]
+pub struct Console {
+    file: File,
+}
+
+impl Console {
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    pub fn into_file(self) -> File {
+        self.file
+    }
+
+    /// Set the window size of the terminal, the container's process gets a SIGWINCH.
+    pub fn resize(&self, height: u16, width: u16) -> std::io::Result<()> {
+        let ws = libc::winsize {
+            ws_row: height,
+            ws_col: width,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // Safe because the fd is owned by us and `ws` outlives the call.
+        unsafe { ioctl_set_winsz(self.file.as_raw_fd(), &ws) }?;
+        Ok(())
+    }
+}
+
+impl Read for Console {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for Console {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl AsRawFd for Console {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for Console {
+    fn into_raw_fd(self) -> RawFd {
+        self.file.into_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{IoSlice, Read, Write};
+
+    use nix::sys::socket::{sendmsg, ControlMessage};
+
+    use super::*;
+
+    /// Does what runc does with the pty master, sending a pipe's write end instead.
+    fn send_fd(path: &Path, fd: RawFd) {
+        send_fd_over(&UnixStream::connect(path).unwrap(), fd);
+    }
+
+    fn send_fd_over(stream: &UnixStream, fd: RawFd) {
+        let payload = b"/dev/pts/0";
+        let fds = [fd];
+        sendmsg::<UnixAddr>(
+            stream.as_raw_fd(),
+            &[IoSlice::new(payload)],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )
+        .unwrap();
+    }
+
+    fn check_console(console: Console, mut reader: os_pipe::PipeReader) {
+        let mut file = console.into_file();
+        file.write_all(b"hello").unwrap();
+        drop(file);
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hello");
+    }
+
+    #[test]
+    fn test_receive_pty_over_socketpair() {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        // Safe because openpty() returned fresh fds owned by nobody else.
+        let (master, mut slave) =
+            unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        send_fd_over(&sender, master.as_raw_fd());
+        drop(master);
+
+        let mut console = receive_fd(&receiver).unwrap();
+        console.resize(24, 80).unwrap();
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        // Safe because `ws` is a valid winsize to fill.
+        let ret = unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCGWINSZ, &mut ws) };
+        assert_eq!(ret, 0);
+        assert_eq!((ws.ws_row, ws.ws_col), (24, 80));
+
+        console.write_all(b"ls\n").unwrap();
+        let mut buf = [0u8; 3];
+        slave.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ls\n");
+    }
+
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_receive_console() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = ConsoleSocket::bind(dir.path().join("pty.sock")).unwrap();
+        let (reader, writer) = os_pipe::pipe().unwrap();
+
+        let path = socket.path().to_path_buf();
+        let sender = std::thread::spawn(move || send_fd(&path, writer.as_raw_fd()));
+        let console = socket.receive().unwrap();
+        sender.join().unwrap();
+
+        check_console(console, reader);
+        drop(socket);
+        assert!(!dir.path().join("pty.sock").exists());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_receive_console() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = ConsoleSocket::bind(dir.path().join("pty.sock")).unwrap();
+        let (reader, writer) = os_pipe::pipe().unwrap();
+
+        let path = socket.path().to_path_buf();
+        let sender = std::thread::spawn(move || send_fd(&path, writer.as_raw_fd()));
+        let console = socket.receive().await.unwrap();
+        sender.join().unwrap();
+
+        check_console(console, reader);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_receive_cancelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = ConsoleSocket::bind(dir.path().join("pty.sock")).unwrap();
+        let received =
+            tokio::time::timeout(std::time::Duration::from_millis(100), socket.receive()).await;
+        assert!(received.is_err());
+
+        // The socket is still usable once a wait was given up.
+        let (reader, writer) = os_pipe::pipe().unwrap();
+        let path = socket.path().to_path_buf();
+        let sender = std::thread::spawn(move || send_fd(&path, writer.as_raw_fd()));
+        let console = socket.receive().await.unwrap();
+        sender.join().unwrap();
+        check_console(console, reader);
+    }
+}
@@ -33,28 +33,302 @@
  * limitations under the License.
  */
 
-use std::collections::HashMap;
+use std::{collections::HashMap, io::Read};
 
-use serde::{Deserialize, Serialize};
-use time::{serde::timestamp, OffsetDateTime};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+use time::{format_description::well_known::Rfc3339, serde::timestamp, OffsetDateTime};
+
+use crate::error::Error;
 
 /// Information for runc container
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Container {
+    #[serde(rename = "ociVersion", default)]
+    pub oci_version: String,
     pub id: String,
     pub pid: usize,
     pub status: String,
     pub bundle: String,
     pub rootfs: String,
-    #[serde(with = "timestamp")]
+    /// runc reports an RFC 3339 time, a unix timestamp is accepted too
+    #[serde(
+        serialize_with = "timestamp::serialize",
+        deserialize_with = "deserialize_created"
+    )]
     pub created: OffsetDateTime,
+    #[serde(default)]
+    pub annotations: HashMap<String, String>,
+    /// User owning the container, empty unless running rootless
+    #[serde(default)]
+    pub owner: String,
+}
+
+impl Container {
+    pub fn container_status(&self) -> ContainerStatus {
+        ContainerStatus::from(self.status.as_str())
+    }
+}
+
+fn deserialize_created<'de, D>(deserializer: D) -> Result<OffsetDateTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Created {
+        Timestamp(i64),
+        Rfc3339(String),
+    }
+
+    match Created::deserialize(deserializer)? {
+        Created::Timestamp(ts) => OffsetDateTime::from_unix_timestamp(ts).map_err(D::Error::custom),
+        Created::Rfc3339(s) => OffsetDateTime::parse(&s, &Rfc3339).map_err(D::Error::custom),
+    }
+}
+
+/// Status of a container as reported by runc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerStatus {
+    Creating,
+    Created,
+    Running,
+    Paused,
+    Stopped,
+    Unknown,
+}
+
+impl From<&str> for ContainerStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "creating" => Self::Creating,
+            "created" => Self::Created,
+            "running" => Self::Running,
+            "paused" => Self::Paused,
+            "stopped" => Self::Stopped,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Filter for [`Runc::list_filtered`](crate::Runc::list_filtered), containers have to
+/// match all the conditions which were set.
+#[derive(Debug, Clone, Default)]
+pub struct ListFilter {
+    status: Option<ContainerStatus>,
+    annotations: HashMap<String, String>,
+    created_before: Option<OffsetDateTime>,
+    created_after: Option<OffsetDateTime>,
+}
+
+impl ListFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn status(mut self, status: ContainerStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn annotation(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.annotations.insert(key.into(), value.into());
+        self
+    }
+
+    /// Keep containers created before `time`, e.g. `OffsetDateTime::now_utc() - age` to keep
+    /// the ones older than `age`.
+    pub fn created_before(mut self, time: OffsetDateTime) -> Self {
+        self.created_before = Some(time);
+        self
+    }
+
+    /// Keep containers created after `time`.
+    pub fn created_after(mut self, time: OffsetDateTime) -> Self {
+        self.created_after = Some(time);
+        self
+    }
+
+    pub fn matches(&self, container: &Container) -> bool {
+        if let Some(status) = self.status {
+            if container.container_status() != status {
+                return false;
+            }
+        }
+        if self
+            .created_before
+            .map_or(false, |t| container.created >= t)
+            || self.created_after.map_or(false, |t| container.created <= t)
+        {
+            return false;
+        }
+        self.annotations
+            .iter()
+            .all(|(k, v)| container.annotations.get(k) == Some(v))
+    }
+}
+
+/// State of the container passed by runc on the stdin of hooks, see
+/// <https://github.com/opencontainers/runtime-spec/blob/main/runtime.md#state>
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HookState {
+    pub oci_version: String,
+    pub id: String,
+    pub status: String,
+    /// Absent once the container process is gone, e.g. for `poststop` hooks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    pub bundle: String,
+    #[serde(default)]
     pub annotations: HashMap<String, String>,
 }
 
+impl HookState {
+    /// Parse the state a hook receives, typically `HookState::from_reader(std::io::stdin())`.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, Error> {
+        serde_json::from_reader(reader).map_err(Error::JsonDeserializationFailed)
+    }
+}
+
+/// A row of the `ps` table printed by `runc ps --format table`.
+///
+/// Columns missing from the table (e.g. with custom `ps` options) are left empty.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct ProcessInfo {
+    pub uid: String,
+    pub pid: usize,
+    pub ppid: Option<usize>,
+    pub c: String,
+    pub stime: String,
+    pub tty: String,
+    pub time: String,
+    pub cmd: String,
+}
+
+/// Parse the output of `ps`, the last column (the command) may contain spaces.
+pub(crate) fn parse_ps_table(output: &str) -> Result<Vec<ProcessInfo>, Error> {
+    let mut lines = output.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or(Error::TopShortResponseError)?
+        .split_whitespace()
+        .collect();
+    let pid_index = header
+        .iter()
+        .position(|h| *h == "PID")
+        .ok_or(Error::TopMissingPidHeader)?;
+
+    let mut processes = vec![];
+    for line in lines {
+        let fields = split_columns(line, header.len());
+        let pid = fields
+            .get(pid_index)
+            .and_then(|p| p.parse().ok())
+            .ok_or(Error::TopShortResponseError)?;
+        let mut info = ProcessInfo {
+            pid,
+            ..Default::default()
+        };
+        for (name, value) in header.iter().zip(fields) {
+            let value = value.to_string();
+            match *name {
+                "UID" | "USER" => info.uid = value,
+                "PPID" => info.ppid = value.parse().ok(),
+                "C" => info.c = value,
+                "STIME" | "START" => info.stime = value,
+                "TTY" | "TT" => info.tty = value,
+                "TIME" => info.time = value,
+                "CMD" | "COMMAND" => info.cmd = value,
+                _ => {}
+            }
+        }
+        processes.push(info);
+    }
+    Ok(processes)
+}
+
+/// Split a line into at most `n` whitespace separated columns, the last one keeps its spaces.
+fn split_columns(line: &str, n: usize) -> Vec<&str> {
+    let mut columns = Vec::with_capacity(n);
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        if columns.len() + 1 == n {
+            columns.push(rest);
+            break;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        columns.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    columns
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Output of `runc list --format=json` with a running and a stopped container.
+    const LIST_OUTPUT: &str = r#"[{"ociVersion":"1.0.2-dev","id":"web","pid":12345,"status":"running","bundle":"/run/containerd/io.containerd.runtime.v2.task/default/web","rootfs":"/run/containerd/io.containerd.runtime.v2.task/default/web/rootfs","created":"2022-09-05T08:12:37.716316263Z","annotations":{"io.kubernetes.cri.container-type":"container"},"owner":""},{"ociVersion":"1.0.2-dev","id":"db","pid":0,"status":"stopped","bundle":"/home/user/db","rootfs":"/home/user/db/rootfs","created":"2022-09-05T08:10:01.5Z","owner":"user"}]"#;
+
+    #[test]
+    fn test_list_output() {
+        let containers: Vec<Container> = serde_json::from_str(LIST_OUTPUT).unwrap();
+        assert_eq!(containers.len(), 2);
+
+        let web = &containers[0];
+        assert_eq!(web.oci_version, "1.0.2-dev");
+        assert_eq!(web.pid, 12345);
+        assert_eq!(web.container_status(), ContainerStatus::Running);
+        assert_eq!(web.created.unix_timestamp(), 1662365557);
+        assert_eq!(
+            web.annotations["io.kubernetes.cri.container-type"],
+            "container"
+        );
+        assert_eq!(web.owner, "");
+
+        let db = &containers[1];
+        assert_eq!(db.container_status(), ContainerStatus::Stopped);
+        assert!(db.annotations.is_empty());
+        assert_eq!(db.owner, "user");
+
+        let running = ListFilter::new().status(ContainerStatus::Running);
+        assert!(running.matches(web));
+        assert!(!running.matches(db));
+        let cri = ListFilter::new().annotation("io.kubernetes.cri.container-type", "container");
+        assert!(cri.matches(web));
+        assert!(!cri.matches(db));
+        assert!(ListFilter::new().matches(db));
+    }
+
+    #[test]
+    fn test_list_filter_created() {
+        let containers: Vec<Container> = serde_json::from_str(LIST_OUTPUT).unwrap();
+        let (web, db) = (&containers[0], &containers[1]);
+        let between = OffsetDateTime::parse("2022-09-05T08:11:00Z", &Rfc3339).unwrap();
+
+        let older = ListFilter::new().created_before(between);
+        assert!(!older.matches(web));
+        assert!(older.matches(db));
+        let newer = ListFilter::new().created_after(between);
+        assert!(newer.matches(web));
+        assert!(!newer.matches(db));
+        let stopped_newer = newer.status(ContainerStatus::Stopped);
+        assert!(!stopped_newer.matches(web));
+        assert!(!stopped_newer.matches(db));
+    }
+
+    #[test]
+    fn test_timestamp_created() {
+        let container: Container = serde_json::from_str(
+            r#"{"id":"fake-id","pid":1,"status":"paused","bundle":"/bundle","rootfs":"/rootfs","created":100}"#,
+        )
+        .unwrap();
+        assert_eq!(container.created.unix_timestamp(), 100);
+        assert_eq!(container.container_status(), ContainerStatus::Paused);
+        assert_eq!(serde_json::to_value(&container).unwrap()["created"], 100);
+    }
+
     #[test]
     fn serde_test() {
         let j = r#"
@@ -83,4 +357,91 @@ mod tests {
         assert_eq!(c.annotations.get("foo"), Some(&"bar".to_string()));
         assert_eq!(c.annotations.get("bar"), None);
     }
+
+    #[test]
+    fn test_hook_state() {
+        let j = r#"{
+            "ociVersion": "1.0.2",
+            "id": "fake",
+            "status": "creating",
+            "pid": 4242,
+            "bundle": "/path/to/bundle",
+            "annotations": {"foo": "bar"}
+        }"#;
+        let state = HookState::from_reader(j.as_bytes()).unwrap();
+        assert_eq!(state.oci_version, "1.0.2");
+        assert_eq!(state.id, "fake");
+        assert_eq!(state.status, "creating");
+        assert_eq!(state.pid, Some(4242));
+        assert_eq!(state.bundle, "/path/to/bundle");
+        assert_eq!(
+            state.annotations.get("foo").map(String::as_str),
+            Some("bar")
+        );
+
+        // poststop hooks get neither a pid nor, from older runc, annotations.
+        let j = r#"{"ociVersion":"1.0.2","id":"fake","status":"stopped","bundle":"/b"}"#;
+        let state = HookState::from_reader(j.as_bytes()).unwrap();
+        assert_eq!(state.pid, None);
+        assert!(state.annotations.is_empty());
+
+        assert!(matches!(
+            HookState::from_reader("{".as_bytes()),
+            Err(Error::JsonDeserializationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_ps_table() {
+        // Captured from `runc ps --format table <id> -ef`.
+        let output = "UID          PID    PPID  C STIME TTY          TIME CMD
+root       25306   25284  0 07:15 ?        00:00:00 sh -c sleep 1000
+root       25361   25306  0 07:15 pts/0    00:00:00 sleep 1000
+";
+        let processes = parse_ps_table(output).unwrap();
+        assert_eq!(
+            processes,
+            vec![
+                ProcessInfo {
+                    uid: "root".to_string(),
+                    pid: 25306,
+                    ppid: Some(25284),
+                    c: "0".to_string(),
+                    stime: "07:15".to_string(),
+                    tty: "?".to_string(),
+                    time: "00:00:00".to_string(),
+                    cmd: "sh -c sleep 1000".to_string(),
+                },
+                ProcessInfo {
+                    uid: "root".to_string(),
+                    pid: 25361,
+                    ppid: Some(25306),
+                    c: "0".to_string(),
+                    stime: "07:15".to_string(),
+                    tty: "pts/0".to_string(),
+                    time: "00:00:00".to_string(),
+                    cmd: "sleep 1000".to_string(),
+                },
+            ]
+        );
+
+        let json = serde_json::to_value(&processes[0]).unwrap();
+        assert_eq!(json["PID"], 25306);
+        assert_eq!(json["CMD"], "sh -c sleep 1000");
+
+        // Custom ps options only fill in the columns they print.
+        let processes = parse_ps_table("  PID COMMAND\n    1 sleep 1000\n").unwrap();
+        assert_eq!(processes[0].pid, 1);
+        assert_eq!(processes[0].cmd, "sleep 1000");
+        assert_eq!(processes[0].ppid, None);
+
+        assert!(matches!(
+            parse_ps_table("UID CMD\nroot sh\n"),
+            Err(Error::TopMissingPidHeader)
+        ));
+        assert!(matches!(
+            parse_ps_table(""),
+            Err(Error::TopShortResponseError)
+        ));
+    }
 }
@@ -0,0 +1,172 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Export of the changes a container made to its overlay rootfs.
+
+use std::{
+    io::{self, Read},
+    path::Path,
+    process::{Child, ChildStdout, Command, Stdio},
+};
+
+use crate::error::Error;
+
+/// Tar archive of an overlay upperdir, streamed from `tar` while it's read.
+///
+/// Reading fails at the end of the archive if `tar` didn't succeed, the process is killed
+/// if the reader is dropped before.
+#[derive(Debug)]
+pub struct DiffReader {
+    child: Child,
+    stdout: ChildStdout,
+    done: bool,
+}
+
+impl Read for DiffReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            self.done = true;
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("tar failed: {}", status),
+                ));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for DiffReader {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Archive the content of `upperdir`, which must be an absolute path to a directory.
+///
+/// Paths in the archive are relative to `upperdir`. Overlay whiteouts, character devices
+/// with 0/0 device number, are archived as they are.
+pub fn tar_upperdir(upperdir: &Path) -> Result<DiffReader, Error> {
+    validate_upperdir(upperdir)?;
+    let mut child = Command::new("tar")
+        .arg("--numeric-owner")
+        .arg("--xattrs")
+        .arg("-C")
+        .arg(upperdir)
+        .args(["-cf", "-", "."])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(Error::ProcessSpawnFailed)?;
+    let stdout = child.stdout.take().expect("tar stdout is piped");
+    Ok(DiffReader {
+        child,
+        stdout,
+        done: false,
+    })
+}
+
+fn validate_upperdir(upperdir: &Path) -> Result<(), Error> {
+    let invalid = |msg: &str| {
+        Error::InvalidPath(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("upperdir {}: {}", upperdir.display(), msg),
+        ))
+    };
+    if !upperdir.is_absolute() {
+        return Err(invalid("not an absolute path"));
+    }
+    if upperdir.parent().is_none() {
+        return Err(invalid("refusing to archive /"));
+    }
+    let metadata = std::fs::symlink_metadata(upperdir).map_err(Error::InvalidPath)?;
+    if !metadata.is_dir() {
+        return Err(invalid("not a directory"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_tar_upperdir() {
+        let upper = tempfile::tempdir().unwrap();
+        fs::create_dir_all(upper.path().join("etc")).unwrap();
+        fs::write(upper.path().join("etc/hostname"), "changed\n").unwrap();
+        fs::write(upper.path().join("new-file"), "hello").unwrap();
+
+        let mut archive = vec![];
+        tar_upperdir(upper.path())
+            .unwrap()
+            .read_to_end(&mut archive)
+            .unwrap();
+
+        let out = tempfile::tempdir().unwrap();
+        let mut tar = Command::new("tar")
+            .arg("-C")
+            .arg(out.path())
+            .arg("-xf")
+            .arg("-")
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::io::Write::write_all(tar.stdin.as_mut().unwrap(), &archive).unwrap();
+        drop(tar.stdin.take());
+        assert!(tar.wait().unwrap().success());
+
+        assert_eq!(
+            fs::read_to_string(out.path().join("etc/hostname")).unwrap(),
+            "changed\n"
+        );
+        assert_eq!(
+            fs::read_to_string(out.path().join("new-file")).unwrap(),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn test_invalid_upperdir() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+
+        for path in [
+            Path::new("relative/upper"),
+            Path::new("/"),
+            file.as_path(),
+            dir.path().join("missing").as_path(),
+        ] {
+            match tar_upperdir(path) {
+                Err(Error::InvalidPath(_)) => {}
+                r => panic!("unexpected result for {}: {:?}", path.display(), r),
+            }
+        }
+    }
+}
@@ -37,6 +37,8 @@ use std::{env, io, process::ExitStatus};
 
 use thiserror::Error;
 
+use crate::logs::LogEntry;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Unable to extract test files: {0}")]
@@ -45,9 +47,18 @@ pub enum Error {
     #[error("Invalid path: {0}")]
     InvalidPath(io::Error),
 
+    #[error("Invalid container id: {0:?}")]
+    InvalidIdError(String),
+
     #[error(transparent)]
     JsonDeserializationFailed(#[from] serde_json::error::Error),
 
+    #[error("Runc {command} printed no output")]
+    EmptyOutput {
+        /// Arguments runc was run with, e.g. `state <id>`
+        command: String,
+    },
+
     #[error("Missing container statistics")]
     MissingContainerStats,
 
@@ -57,19 +68,58 @@ pub enum Error {
     #[error("Error occured in runc: {0}")]
     InvalidCommand(io::Error),
 
-    #[error("Runc command failed: status={status}, stdout=\"{stdout}\", stderr=\"{stderr}\"")]
+    #[error(
+        "Runc command failed: status={status}, stdout=\"{stdout}\", stderr=\"{stderr}\"{}",
+        last_log_message(.log_messages)
+    )]
     CommandFailed {
         status: ExitStatus,
         stdout: String,
         stderr: String,
+        /// Error entries runc wrote to its log, only collected with a JSON log file.
+        log_messages: Vec<LogEntry>,
     },
 
     #[error("Runc IO unavailable: {0}")]
     UnavailableIO(io::Error),
 
+    /// The runc process was killed after `timeout`, the output is what it wrote until then.
     #[cfg(feature = "async")]
-    #[error("Runc command timed out: {0}")]
-    CommandTimeout(tokio::time::error::Elapsed),
+    #[error(
+        "Runc {subcommand} timed out after {timeout:?}: stdout=\"{stdout}\", stderr=\"{stderr}\""
+    )]
+    CommandTimeout {
+        /// Subcommand which timed out, e.g. `restore`
+        subcommand: String,
+        timeout: std::time::Duration,
+        stdout: String,
+        stderr: String,
+    },
+
+    #[error("Invalid spec: {0}")]
+    InvalidSpec(String),
+
+    /// [`GlobalOpts::max_concurrent_commands`](crate::options::GlobalOpts::max_concurrent_commands)
+    /// was 0, no command could ever run.
+    #[cfg(feature = "async")]
+    #[error("At least one concurrent runc command must be allowed")]
+    NoConcurrentCommands,
+
+    #[cfg(feature = "async")]
+    #[error("Interval {0:?} is shorter than the 1ms runc supports")]
+    InvalidInterval(std::time::Duration),
+
+    #[error("Process has a terminal but no console socket was given")]
+    MissingConsoleSocket,
+
+    #[error("Unknown signal: {0:?}")]
+    InvalidSignal(String),
+
+    #[error("Rootfs of container {0} isn't mounted read-only")]
+    RootfsNotReadonly(String),
+
+    #[error("Container {0} still exists after it was deleted")]
+    DeleteVerificationFailed(String),
 
     #[error("Unable to parse runc version")]
     InvalidVersion,
@@ -77,11 +127,26 @@ pub enum Error {
     #[error("Unable to locate the runc")]
     NotFound,
 
+    #[error("Runc binary {0:?} not found")]
+    BinaryNotFound(std::path::PathBuf),
+
+    #[error("Runc binary {0:?} isn't executable")]
+    BinaryNotExecutable(std::path::PathBuf),
+
+    #[error("Runc version {found} is older than the required {required}")]
+    VersionTooOld { found: String, required: String },
+
+    #[error("Criu binary {0} doesn't exist or isn't executable")]
+    InvalidCriuPath(std::path::PathBuf),
+
     #[error("Error occurs with fs: {0}")]
     FileSystemError(io::Error),
 
-    #[error("Failed to spec file: {0}")]
-    SpecFileCreationFailed(io::Error),
+    #[error("Failed to create spec file {path:?}: {source}")]
+    SpecFileCreationFailed {
+        path: std::path::PathBuf,
+        source: io::Error,
+    },
 
     #[error(transparent)]
     SpecFileCleanupFailed(io::Error),
@@ -110,6 +175,9 @@ pub enum Error {
     #[error("Failed to handle environment variable: {0}")]
     EnvError(env::VarError),
 
+    #[error("Command is not supported by this runc binary: {0}")]
+    Unsupported(String),
+
     #[error("Sorry, this part of api is not implemented: {0}")]
     Unimplemented(String),
 
@@ -122,3 +190,320 @@ pub enum Error {
     #[error("Failed to create dir: {0}")]
     CreateDir(nix::Error),
 }
+
+/// The last error runc logged, often more telling than its output.
+fn last_log_message(log_messages: &[LogEntry]) -> String {
+    log_messages
+        .last()
+        .map(|e| format!(", log=\"{}\"", e.msg))
+        .unwrap_or_default()
+}
+
+/// Class of a failed runc command, parsed from the messages of [`Error::CommandFailed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandErrorKind {
+    /// The container doesn't exist, or its state file is missing
+    NotFound,
+    /// A container with the id already exists, e.g. when creating it
+    AlreadyExists,
+    /// The container still has running processes, e.g. when deleting it
+    NotStopped,
+    /// The process to signal has already exited (ESRCH), e.g. when killing a container
+    /// which is exiting on its own
+    NotRunning,
+    /// runc lacks the privileges for the operation (EACCES or EPERM)
+    PermissionDenied,
+    Other,
+}
+
+impl Error {
+    /// Classify a failed runc command, any other error is [`CommandErrorKind::Other`].
+    pub fn command_error_kind(&self) -> CommandErrorKind {
+        let (stdout, stderr, log_messages) = match self {
+            Error::CommandFailed {
+                stdout,
+                stderr,
+                log_messages,
+                ..
+            } => (stdout, stderr, log_messages),
+            _ => return CommandErrorKind::Other,
+        };
+        let mut messages = std::iter::once(stdout.as_str())
+            .chain(std::iter::once(stderr.as_str()))
+            .chain(log_messages.iter().map(|e| e.msg.as_str()));
+        messages
+            .find_map(|msg| {
+                if is_not_exist(msg) {
+                    Some(CommandErrorKind::NotFound)
+                } else if is_already_exists(msg) {
+                    Some(CommandErrorKind::AlreadyExists)
+                } else if msg.contains("not stopped") {
+                    Some(CommandErrorKind::NotStopped)
+                } else if is_not_running(msg) {
+                    Some(CommandErrorKind::NotRunning)
+                } else if is_permission_denied(msg) {
+                    Some(CommandErrorKind::PermissionDenied)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(CommandErrorKind::Other)
+    }
+
+    /// Exit code of a failed runc command, [`None`] for other errors or if runc was killed
+    /// by a signal.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Error::CommandFailed { status, .. } => status.code(),
+            _ => None,
+        }
+    }
+
+    /// See [`CommandErrorKind::NotFound`].
+    pub fn is_not_found(&self) -> bool {
+        self.command_error_kind() == CommandErrorKind::NotFound
+    }
+
+    /// See [`CommandErrorKind::AlreadyExists`].
+    pub fn is_already_exists(&self) -> bool {
+        self.command_error_kind() == CommandErrorKind::AlreadyExists
+    }
+
+    /// See [`CommandErrorKind::NotRunning`].
+    pub fn is_not_running(&self) -> bool {
+        self.command_error_kind() == CommandErrorKind::NotRunning
+    }
+
+    /// See [`CommandErrorKind::PermissionDenied`].
+    pub fn is_permission_denied(&self) -> bool {
+        self.command_error_kind() == CommandErrorKind::PermissionDenied
+    }
+}
+
+/// Messages of runc and crun when the container is unknown, older runc versions and crun
+/// report the missing state file instead.
+fn is_not_exist(msg: &str) -> bool {
+    if msg.contains("does not exist") {
+        return true;
+    }
+    let msg = msg.to_lowercase();
+    msg.contains("no such file or directory")
+        && (msg.contains("state.json") || msg.contains("/status") || is_stat_state_dir(&msg))
+}
+
+/// Whether `msg` is a failed stat of a container directory, `<root>/<id>`, in the default
+/// state root of runc or crun, e.g. `stat /run/runc/fake-id: no such file or directory`.
+fn is_stat_state_dir(msg: &str) -> bool {
+    let path = match msg.strip_prefix("stat ").and_then(|m| m.split(':').next()) {
+        Some(path) => std::path::Path::new(path),
+        None => return false,
+    };
+    path.parent()
+        .and_then(|root| root.file_name())
+        .map_or(false, |root| root == "runc" || root == "crun")
+}
+
+/// Messages of runc and crun when the id is taken.
+fn is_already_exists(msg: &str) -> bool {
+    msg.contains("with id exists") || msg.contains("already exists")
+}
+
+/// Messages of runc and crun when the signaled process is gone.
+fn is_not_running(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    msg.contains("process already finished")
+        || msg.contains("container not running")
+        || msg.contains("no such process")
+}
+
+fn is_permission_denied(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    msg.contains("permission denied") || msg.contains("operation not permitted")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    fn failed(stderr: &str, log: &str) -> Error {
+        Error::CommandFailed {
+            status: ExitStatus::from_raw(256),
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+            log_messages: vec![LogEntry {
+                level: "error".to_string(),
+                msg: log.to_string(),
+                time: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_command_error_kind() {
+        assert_eq!(
+            failed("container fake-id does not exist", "").command_error_kind(),
+            CommandErrorKind::NotFound
+        );
+        assert_eq!(
+            failed(
+                "",
+                "cannot delete container fake-id that is not stopped: running"
+            )
+            .command_error_kind(),
+            CommandErrorKind::NotStopped
+        );
+        // older runc
+        assert_eq!(
+            failed(
+                "open /run/runc/fake-id/state.json: no such file or directory",
+                ""
+            )
+            .command_error_kind(),
+            CommandErrorKind::NotFound
+        );
+        // crun
+        assert_eq!(
+            failed(
+                "error opening file `/run/crun/fake-id/status`: No such file or directory\n",
+                ""
+            )
+            .command_error_kind(),
+            CommandErrorKind::NotFound
+        );
+        assert_eq!(
+            failed(
+                "stat /run/user/1000/runc/fake-id: no such file or directory",
+                ""
+            )
+            .command_error_kind(),
+            CommandErrorKind::NotFound
+        );
+        // a missing file which isn't the container state
+        assert_eq!(
+            failed("open /bundle/config.json: no such file or directory", "").command_error_kind(),
+            CommandErrorKind::Other
+        );
+        assert_eq!(
+            failed("stat /bundle/rootfs: no such file or directory", "").command_error_kind(),
+            CommandErrorKind::Other
+        );
+        // runc
+        assert_eq!(
+            failed("", "os: process already finished").command_error_kind(),
+            CommandErrorKind::NotRunning
+        );
+        assert_eq!(
+            failed("container not running\n", "").command_error_kind(),
+            CommandErrorKind::NotRunning
+        );
+        // crun
+        assert_eq!(
+            failed("kill container: No such process\n", "").command_error_kind(),
+            CommandErrorKind::NotRunning
+        );
+        assert_eq!(
+            failed("exec format error", "").command_error_kind(),
+            CommandErrorKind::Other
+        );
+        assert_eq!(
+            Error::NotFound.command_error_kind(),
+            CommandErrorKind::Other
+        );
+    }
+
+    #[test]
+    fn test_error_classes() {
+        let cases = [
+            // runc 1.0
+            (
+                "container with id exists: fake-id",
+                CommandErrorKind::AlreadyExists,
+            ),
+            (
+                "cannot kill container fake-id: container does not exist",
+                CommandErrorKind::NotFound,
+            ),
+            (
+                "container_linux.go:380: starting container process caused: \
+                 process_linux.go:545: container init caused: rootfs_linux.go:76: \
+                 mounting \"proc\" to rootfs at \"/proc\" caused: permission denied",
+                CommandErrorKind::PermissionDenied,
+            ),
+            // runc 1.1
+            (
+                "container fake-id does not exist",
+                CommandErrorKind::NotFound,
+            ),
+            ("container not running", CommandErrorKind::NotRunning),
+            (
+                "unable to apply cgroup configuration: mkdir /sys/fs/cgroup/fake-id: \
+                 operation not permitted",
+                CommandErrorKind::PermissionDenied,
+            ),
+            // crun
+            (
+                "error opening file `/run/crun/fake-id/status`: No such file or directory",
+                CommandErrorKind::NotFound,
+            ),
+            (
+                "container `fake-id` already exists",
+                CommandErrorKind::AlreadyExists,
+            ),
+            (
+                "kill container: No such process",
+                CommandErrorKind::NotRunning,
+            ),
+            (
+                "mkdir `/run/crun/fake-id`: Permission denied",
+                CommandErrorKind::PermissionDenied,
+            ),
+            ("exec format error", CommandErrorKind::Other),
+        ];
+        for (stderr, kind) in cases {
+            let e = failed(stderr, "");
+            assert_eq!(e.command_error_kind(), kind, "{}", stderr);
+            assert_eq!(e.is_not_found(), kind == CommandErrorKind::NotFound);
+            assert_eq!(
+                e.is_already_exists(),
+                kind == CommandErrorKind::AlreadyExists
+            );
+            assert_eq!(e.is_not_running(), kind == CommandErrorKind::NotRunning);
+            assert_eq!(
+                e.is_permission_denied(),
+                kind == CommandErrorKind::PermissionDenied
+            );
+        }
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(failed("", "").exit_code(), Some(1));
+        let killed = Error::CommandFailed {
+            status: ExitStatus::from_raw(libc::SIGKILL),
+            stdout: String::new(),
+            stderr: String::new(),
+            log_messages: vec![],
+        };
+        assert_eq!(killed.exit_code(), None);
+        assert_eq!(Error::NotFound.exit_code(), None);
+    }
+
+    #[test]
+    fn test_command_failed_display() {
+        assert_eq!(
+            failed("", "container with id exists: fake-id").to_string(),
+            "Runc command failed: status=exit status: 1, stdout=\"\", stderr=\"\", \
+             log=\"container with id exists: fake-id\""
+        );
+        let mut e = failed("boom", "");
+        if let Error::CommandFailed { log_messages, .. } = &mut e {
+            log_messages.clear();
+        }
+        assert_eq!(
+            e.to_string(),
+            "Runc command failed: status=exit status: 1, stdout=\"\", stderr=\"boom\""
+        );
+    }
+}
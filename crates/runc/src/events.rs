@@ -34,8 +34,25 @@
  */
 
 use std::collections::HashMap;
+#[cfg(feature = "async")]
+use std::path::Path;
+#[cfg(all(feature = "async", target_os = "linux"))]
+use std::{
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+};
 
-use serde::{Deserialize, Serialize};
+#[cfg(feature = "async")]
+use futures::{stream::BoxStream, StreamExt};
+#[cfg(all(feature = "async", target_os = "linux"))]
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent};
+use serde::{Deserialize, Deserializer, Serialize};
+#[cfg(all(feature = "async", target_os = "linux"))]
+use tokio::io::unix::AsyncFd;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::error::Error;
 
 /// Event type generated by runc
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,6 +62,9 @@ pub enum EventType {
     Stats,
     /// Out of memory
     Oom,
+    /// Any other event, e.g. from a newer runc
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,18 +76,249 @@ pub struct Event {
     pub stats: Option<Stats>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Event {
+    /// Parse a line written by `runc events`.
+    pub fn from_line(line: &str) -> Result<Self, Error> {
+        serde_json::from_str(line).map_err(Error::JsonDeserializationFailed)
+    }
+
+    pub fn is_oom(&self) -> bool {
+        matches!(self.event_type, EventType::Oom)
+    }
+}
+
+/// Filter the OOM events out of the lines written by `runc events`, ending with the output.
+#[cfg(feature = "async")]
+pub fn oom_stream<R>(reader: R) -> BoxStream<'static, Result<Event, Error>>
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+{
+    event_stream(
+        reader,
+        |event| if event.is_oom() { Some(event) } else { None },
+    )
+}
+
+/// Filter the statistics out of the lines written by `runc events`, ending with the output.
+#[cfg(feature = "async")]
+pub fn stats_stream<R>(reader: R) -> BoxStream<'static, Result<Stats, Error>>
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+{
+    event_stream(reader, |event| match event.event_type {
+        EventType::Stats => event.stats,
+        _ => None,
+    })
+}
+
+/// Parse the lines written by `runc events`, yielding what `select` picks from each event.
+#[cfg(feature = "async")]
+fn event_stream<R, T, F>(reader: R, select: F) -> BoxStream<'static, Result<T, Error>>
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+    T: Send + 'static,
+    F: Fn(Event) -> Option<T> + Send + 'static,
+{
+    futures::stream::unfold(Some((reader.lines(), select)), |state| async move {
+        let (mut lines, select) = state?;
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => match Event::from_line(&line).map(&select) {
+                    Ok(Some(item)) => return Some((Ok(item), Some((lines, select)))),
+                    Ok(None) => continue,
+                    Err(e) => return Some((Err(e), Some((lines, select)))),
+                },
+                Ok(None) => return None,
+                Err(e) => return Some((Err(Error::UnavailableIO(e)), None)),
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Parse the content of `memory.events` of a cgroup v2 directory.
+pub fn parse_memory_events(content: &str) -> MemoryEvents {
+    let mut events = MemoryEvents::default();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let (key, value) = match (fields.next(), fields.next().and_then(|v| v.parse().ok())) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue,
+        };
+        match key {
+            "low" => events.low = value,
+            "high" => events.high = value,
+            "max" => events.max = value,
+            "oom" => events.oom = value,
+            "oom_kill" => events.oom_kill = value,
+            _ => {}
+        }
+    }
+    events
+}
+
+/// Watch `memory.events` of the cgroup v2 directory `cgroup`, yielding the `oom_kill` counter
+/// each time it increases.
+///
+/// Unlike `runc events`, this doesn't miss OOM kills happening between two polls. The stream
+/// ends when the cgroup is removed.
+#[cfg(all(feature = "async", target_os = "linux"))]
+pub fn watch_oom_kills(
+    cgroup: impl AsRef<Path>,
+) -> Result<BoxStream<'static, Result<u64, Error>>, Error> {
+    let path = cgroup.as_ref().join("memory.events");
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)
+        .map_err(|e| Error::FileSystemError(e.into()))?;
+    // Owned right away so the fd is closed on errors.
+    let inotify = InotifyFd(inotify);
+    inotify
+        .0
+        .add_watch(&path, AddWatchFlags::IN_MODIFY)
+        .map_err(|e| Error::FileSystemError(e.into()))?;
+    let last = read_memory_events(&path)?.oom_kill;
+    let fd = AsyncFd::new(inotify).map_err(Error::FileSystemError)?;
+
+    Ok(
+        futures::stream::unfold(Some((fd, path, last)), |state| async move {
+            let (fd, path, mut last) = state?;
+            loop {
+                let events = match read_inotify(&fd).await {
+                    Ok(events) => events,
+                    Err(e) => return Some((Err(Error::FileSystemError(e)), None)),
+                };
+                if events
+                    .iter()
+                    .any(|e| e.mask.contains(AddWatchFlags::IN_IGNORED))
+                {
+                    return None;
+                }
+                match read_memory_events(&path) {
+                    Ok(events) if events.oom_kill > last => {
+                        last = events.oom_kill;
+                        return Some((Ok(last), Some((fd, path, last))));
+                    }
+                    Ok(_) => continue,
+                    // The cgroup is being removed.
+                    Err(_) => return None,
+                }
+            }
+        })
+        .boxed(),
+    )
+}
+
+/// Watching relies on inotify, which is only available on Linux.
+#[cfg(all(feature = "async", not(target_os = "linux")))]
+pub fn watch_oom_kills(
+    _cgroup: impl AsRef<Path>,
+) -> Result<BoxStream<'static, Result<u64, Error>>, Error> {
+    Err(Error::Unimplemented("watch_oom_kills".to_string()))
+}
+
+#[cfg(all(feature = "async", target_os = "linux"))]
+fn read_memory_events(path: &Path) -> Result<MemoryEvents, Error> {
+    let content = std::fs::read_to_string(path).map_err(Error::FileSystemError)?;
+    Ok(parse_memory_events(&content))
+}
+
+#[cfg(all(feature = "async", target_os = "linux"))]
+async fn read_inotify(fd: &AsyncFd<InotifyFd>) -> io::Result<Vec<InotifyEvent>> {
+    loop {
+        let mut guard = fd.readable().await?;
+        if let Ok(result) = guard.try_io(|fd| fd.get_ref().0.read_events().map_err(io::Error::from))
+        {
+            return result;
+        }
+    }
+}
+
+/// Closes the inotify instance, which nix leaves to the caller.
+#[cfg(all(feature = "async", target_os = "linux"))]
+struct InotifyFd(Inotify);
+
+#[cfg(all(feature = "async", target_os = "linux"))]
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[cfg(all(feature = "async", target_os = "linux"))]
+impl Drop for InotifyFd {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.0.as_raw_fd());
+    }
+}
+
+/// Statistics of a container's cgroup.
+///
+/// runc reports them in this layout on cgroup v1 and v2 hosts alike, mapping the unified
+/// hierarchy onto the v1 sections. A section it leaves out or reports as `null` is defaulted,
+/// e.g. `hugetlb` on a v2 host without the controller.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Stats {
+    #[serde(deserialize_with = "null_as_default")]
     pub cpu: Cpu,
+    #[serde(deserialize_with = "null_as_default")]
     pub memory: Memory,
+    #[serde(deserialize_with = "null_as_default")]
     pub pids: Pids,
-    #[serde(rename = "blkio")]
+    #[serde(rename = "blkio", deserialize_with = "null_as_default")]
     pub block_io: BlkIO,
-    #[serde(rename = "hugetlb")]
-    pub huge_tlb: HugeTLB,
+    /// Hugetlb statistics keyed by page size, e.g. `2MB`
+    #[serde(rename = "hugetlb", deserialize_with = "null_as_default")]
+    pub huge_tlb: HashMap<String, HugeTLB>,
+    /// Counters of the interfaces in the container's network namespace, runc reports `null`
+    /// when it has none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_interfaces: Option<Vec<NetworkStat>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Stats {
+    /// Memory usage in bytes, excluding swap.
+    pub fn memory_usage_bytes(&self) -> Option<u64> {
+        self.memory.usage.as_ref().and_then(|usage| usage.usage)
+    }
+
+    /// Memory limit in bytes, `None` if unlimited or not reported.
+    pub fn memory_limit_bytes(&self) -> Option<u64> {
+        self.memory.usage.as_ref().map(|usage| usage.limit)
+    }
+
+    /// Total CPU time consumed in nanoseconds.
+    pub fn cpu_usage_nanos(&self) -> Option<u64> {
+        self.cpu.usage.as_ref().and_then(|usage| usage.total)
+    }
+
+    /// Bytes read from all block devices.
+    pub fn io_read_bytes(&self) -> Option<u64> {
+        self.block_io.service_bytes("Read")
+    }
+
+    /// Bytes written to all block devices.
+    pub fn io_write_bytes(&self) -> Option<u64> {
+        self.block_io.service_bytes("Write")
+    }
+
+    /// Per interface network counters, empty if runc didn't report any.
+    pub fn network(&self) -> &[NetworkStat] {
+        self.network_interfaces.as_deref().unwrap_or(&[])
+    }
+}
+
+/// Deserialize `null` like a missing field, as Go marshals nil maps and slices.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct HugeTLB {
     pub usage: Option<u64>,
     pub max: Option<u64>,
@@ -83,7 +334,7 @@ pub struct BlkIOEntry {
     pub value: Option<u64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BlkIO {
     /// Number of bytes transferred to and from the disk
     #[serde(rename = "ioServiceBytesRecursive")]
@@ -111,7 +362,19 @@ pub struct BlkIO {
     pub sectors_recursive: Option<Vec<BlkIOEntry>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl BlkIO {
+    fn service_bytes(&self, op: &str) -> Option<u64> {
+        self.io_service_bytes_recursive.as_ref().map(|entries| {
+            entries
+                .iter()
+                .filter(|entry| entry.op.as_deref() == Some(op))
+                .filter_map(|entry| entry.value)
+                .sum()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Pids {
     /// Number of pids in the cgroup
     pub current: Option<u64>,
@@ -132,11 +395,13 @@ pub struct Throttling {
 }
 
 /// Each members represents time in nanoseconds
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CpuUsage {
     /// Total CPU time consumed
     pub total: Option<u64>,
     /// Total CPU time consumed per core
+    #[serde(rename = "percpu")]
     pub per_cpu: Option<Vec<u64>>,
     /// Total CPU time consumed in kernel mode
     pub kernel: u64,
@@ -144,13 +409,14 @@ pub struct CpuUsage {
     pub user: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Cpu {
-    pub usage: Option<u64>,
+    pub usage: Option<CpuUsage>,
     pub throttling: Option<Throttling>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MemoryEntry {
     /// Memory limit in bytes
     pub limit: u64,
@@ -163,7 +429,7 @@ pub struct MemoryEntry {
     pub fail_count: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Memory {
     /// Memory usage for cache
     pub cache: Option<u64>,
@@ -179,3 +445,218 @@ pub struct Memory {
     /// Raw stats of memory
     pub raw: Option<HashMap<String, u64>>,
 }
+
+/// Number of times each memory boundary was hit.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryEvents {
+    pub low: u64,
+    pub high: u64,
+    pub max: u64,
+    pub oom: u64,
+    pub oom_kill: u64,
+}
+
+/// Counters of one network interface, from `/sys/class/net/<name>/statistics`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkStat {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1_EVENT: &str = r#"{"type":"stats","id":"v1","data":{
+        "cpu":{"usage":{"total":123456789,"percpu":[100000000,23456789],"kernel":3000000,"user":12000000},"throttling":{}},
+        "memory":{"cache":4096,
+            "usage":{"limit":536870912,"usage":1048576,"max":2097152,"failcnt":0},
+            "swap":{"limit":9223372036854771712,"usage":1048576,"max":2097152,"failcnt":0},
+            "kernel":{"limit":9223372036854771712,"usage":65536,"max":131072,"failcnt":0},
+            "kernelTCP":{"limit":9223372036854771712,"failcnt":0},
+            "raw":{"cache":4096,"rss":1044480}},
+        "pids":{"current":3},
+        "blkio":{"ioServiceBytesRecursive":[
+            {"major":8,"minor":0,"op":"Read","value":4096},
+            {"major":8,"minor":0,"op":"Write","value":8192},
+            {"major":8,"minor":0,"op":"Total","value":12288}]},
+        "hugetlb":{"2MB":{"failcnt":0},"1GB":{"failcnt":0}},
+        "intel_rdt":{},
+        "network_interfaces":null}}"#;
+
+    /// Written by runc 1.1 on a cgroup v2 host, in the v1 layout.
+    const V2_RUNC_EVENT: &str = r#"{"type":"stats","id":"v2-runc","data":{
+        "cpu":{"usage":{"total":250000000,"kernel":50000000,"user":200000000},
+            "throttling":{"periods":10,"throttledPeriods":2,"throttledTime":1000000}},
+        "memory":{"usage":{"limit":536870912,"usage":2097152,"failcnt":0},
+            "swap":{"limit":536870912,"usage":2097152,"failcnt":0},
+            "kernel":{"limit":0,"failcnt":0},
+            "raw":{"anon":1572864,"file":524288,"oom_kill":0}},
+        "pids":{"current":2,"limit":100},
+        "blkio":{"ioServiceBytesRecursive":[
+            {"major":8,"minor":0,"op":"Read","value":4096},
+            {"major":8,"minor":0,"op":"Write","value":8192}],
+            "ioServicedRecursive":[
+            {"major":8,"minor":0,"op":"Read","value":1},
+            {"major":8,"minor":0,"op":"Write","value":2}]},
+        "hugetlb":null,
+        "intel_rdt":{},
+        "network_interfaces":null}}"#;
+
+    fn stats(event: &str) -> Stats {
+        let event: Event = serde_json::from_str(event).unwrap();
+        event.stats.unwrap()
+    }
+
+    #[test]
+    fn test_v1_stats() {
+        let stats = stats(V1_EVENT);
+        assert_eq!(stats.memory_usage_bytes(), Some(1048576));
+        assert_eq!(stats.memory_limit_bytes(), Some(536870912));
+        assert_eq!(stats.cpu_usage_nanos(), Some(123456789));
+        assert_eq!(stats.io_read_bytes(), Some(4096));
+        assert_eq!(stats.io_write_bytes(), Some(8192));
+        assert_eq!(stats.pids.current, Some(3));
+        assert_eq!(stats.huge_tlb.len(), 2);
+        assert!(stats.network().is_empty());
+    }
+
+    #[test]
+    fn test_network_stats() {
+        let stats = stats(
+            r#"{"type":"stats","id":"net","data":{"network_interfaces":[
+                {"name":"eth0","rx_bytes":2048,"rx_packets":16,"rx_errors":0,"rx_dropped":1,
+                    "tx_bytes":1024,"tx_packets":8,"tx_errors":0,"tx_dropped":0},
+                {"name":"lo","rx_bytes":512,"tx_bytes":512}]}}"#,
+        );
+        let network = stats.network();
+        assert_eq!(network.len(), 2);
+        assert_eq!(
+            network[0],
+            NetworkStat {
+                name: "eth0".to_string(),
+                rx_bytes: 2048,
+                rx_packets: 16,
+                rx_errors: 0,
+                rx_dropped: 1,
+                tx_bytes: 1024,
+                tx_packets: 8,
+                tx_errors: 0,
+                tx_dropped: 0,
+            }
+        );
+        assert_eq!(network[1].name, "lo");
+        assert_eq!(network[1].tx_bytes, 512);
+        assert_eq!(network[1].tx_packets, 0);
+    }
+
+    #[test]
+    fn test_v2_runc_stats() {
+        let stats = stats(V2_RUNC_EVENT);
+        assert_eq!(stats.memory_usage_bytes(), Some(2097152));
+        assert_eq!(stats.memory_limit_bytes(), Some(536870912));
+        assert_eq!(stats.cpu_usage_nanos(), Some(250000000));
+        assert_eq!(stats.pids.limit, Some(100));
+        assert_eq!(stats.io_read_bytes(), Some(4096));
+        assert_eq!(stats.io_write_bytes(), Some(8192));
+        assert!(stats.huge_tlb.is_empty());
+        assert!(stats.memory.kernel_tcp.is_none());
+        assert_eq!(stats.cpu.throttling.unwrap().throtted_periods, Some(2));
+        assert_eq!(
+            stats.memory.raw.unwrap().get("anon").copied(),
+            Some(1572864)
+        );
+
+        // Sections runc reports as null are defaulted.
+        let stats: Stats =
+            serde_json::from_str(r#"{"cpu":null,"memory":{"usage":{"usage":1}},"blkio":null}"#)
+                .unwrap();
+        assert_eq!(stats.memory_usage_bytes(), Some(1));
+        assert!(stats.cpu_usage_nanos().is_none());
+        assert!(stats.io_read_bytes().is_none());
+    }
+
+    #[test]
+    fn test_parse_memory_events() {
+        let events =
+            parse_memory_events("low 0\nhigh 12\nmax 4\noom 2\noom_kill 1\noom_group_kill 0\n");
+        assert_eq!(events.high, 12);
+        assert_eq!(events.max, 4);
+        assert_eq!(events.oom, 2);
+        assert_eq!(events.oom_kill, 1);
+        assert_eq!(parse_memory_events("").oom_kill, 0);
+    }
+
+    #[test]
+    fn test_event_types() {
+        assert!(Event::from_line(r#"{"type":"oom","id":"fake-id"}"#)
+            .unwrap()
+            .is_oom());
+        let event = Event::from_line(r#"{"type":"intelrdt","id":"fake-id"}"#).unwrap();
+        assert!(matches!(event.event_type, EventType::Unknown));
+        assert!(Event::from_line("not json").is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_oom_stream() {
+        let lines = format!(
+            "{}\n{}\n\n{}\n{}\n",
+            r#"{"type":"stats","id":"fake-id","data":{}}"#,
+            r#"{"type":"oom","id":"fake-id"}"#,
+            r#"{"type":"intelrdt","id":"fake-id"}"#,
+            r#"{"type":"oom","id":"fake-id"}"#,
+        );
+        let events: Vec<_> = oom_stream(std::io::Cursor::new(lines.into_bytes()))
+            .collect()
+            .await;
+        assert_eq!(events.len(), 2);
+        for event in events {
+            let event = event.unwrap();
+            assert!(event.is_oom());
+            assert_eq!(event.id, "fake-id");
+        }
+    }
+
+    #[cfg(all(feature = "async", target_os = "linux"))]
+    #[tokio::test]
+    async fn test_watch_oom_kills() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("memory.events");
+        let write = |oom_kill: u64| {
+            std::fs::write(
+                &path,
+                format!("low 0\nhigh 0\nmax 0\noom {0}\noom_kill {0}\n", oom_kill),
+            )
+            .unwrap()
+        };
+        write(1);
+
+        let mut kills = watch_oom_kills(dir.path()).unwrap();
+        write(1);
+        write(3);
+        let next = tokio::time::timeout(std::time::Duration::from_secs(5), kills.next());
+        assert_eq!(next.await.unwrap().unwrap().unwrap(), 3);
+
+        std::fs::remove_file(&path).unwrap();
+        let next = tokio::time::timeout(std::time::Duration::from_secs(5), kills.next());
+        assert!(next.await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_empty_stats() {
+        let stats = stats(r#"{"type":"stats","id":"unknown","data":{}}"#);
+        assert_eq!(stats.memory_usage_bytes(), None);
+        assert_eq!(stats.cpu_usage_nanos(), None);
+        assert_eq!(stats.io_read_bytes(), None);
+    }
+}
@@ -0,0 +1,206 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Features supported by the runtime, as reported by `runc features` (runc 1.1+).
+///
+/// The schema grows from release to release, so every field is defaulted when absent.
+/// See <https://github.com/opencontainers/runtime-spec/blob/main/features.md>
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Features {
+    /// Minimum OCI runtime spec version recognized by the runtime.
+    pub oci_version_min: Option<String>,
+    /// Maximum OCI runtime spec version recognized by the runtime.
+    pub oci_version_max: Option<String>,
+    /// Known hook names.
+    pub hooks: Option<Vec<String>>,
+    /// Known mount options, including the ones unsupported by the kernel.
+    pub mount_options: Option<Vec<String>>,
+    /// Linux specific features.
+    pub linux: Option<LinuxFeatures>,
+    /// Implementation specific information, e.g. `org.opencontainers.runc.version`.
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LinuxFeatures {
+    /// Known namespace types.
+    pub namespaces: Option<Vec<String>>,
+    /// Known capabilities.
+    pub capabilities: Option<Vec<String>>,
+    pub cgroup: Option<CgroupFeatures>,
+    pub seccomp: Option<SeccompFeatures>,
+    pub apparmor: Option<EnabledFeature>,
+    pub selinux: Option<EnabledFeature>,
+    pub intel_rdt: Option<EnabledFeature>,
+    pub mount_extensions: Option<MountExtensions>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CgroupFeatures {
+    /// Whether cgroup v1 is supported.
+    pub v1: Option<bool>,
+    /// Whether cgroup v2 is supported.
+    pub v2: Option<bool>,
+    /// Whether the systemd cgroup driver is supported.
+    pub systemd: Option<bool>,
+    /// Whether the rootless systemd cgroup driver is supported.
+    pub systemd_user: Option<bool>,
+    /// Whether the rdma cgroup controller is supported.
+    pub rdma: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SeccompFeatures {
+    /// Whether seccomp support is compiled in.
+    pub enabled: Option<bool>,
+    /// Known seccomp actions, e.g. `SCMP_ACT_ALLOW`.
+    pub actions: Option<Vec<String>>,
+    /// Known seccomp operators, e.g. `SCMP_CMP_EQ`.
+    pub operators: Option<Vec<String>>,
+    /// Known seccomp architectures, e.g. `SCMP_ARCH_X86_64`.
+    pub archs: Option<Vec<String>>,
+    /// Known seccomp filter flags.
+    pub known_flags: Option<Vec<String>>,
+    /// Seccomp filter flags supported by the runtime and the kernel.
+    pub supported_flags: Option<Vec<String>>,
+}
+
+/// Feature which can only be enabled or disabled, e.g. AppArmor and SELinux.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnabledFeature {
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MountExtensions {
+    /// Whether idmapped mounts are supported.
+    pub idmap: Option<EnabledFeature>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Captured from runc 1.1.4.
+    const RUNC_1_1_4_FEATURES: &str = r#"
+        {
+            "ociVersionMin": "1.0.0",
+            "ociVersionMax": "1.0.2-dev",
+            "hooks": [
+                "prestart",
+                "createRuntime",
+                "createContainer",
+                "startContainer",
+                "poststart",
+                "poststop"
+            ],
+            "mountOptions": [
+                "acl", "async", "atime", "bind", "defaults", "dev", "diratime", "dirsync",
+                "exec", "iversion", "lazytime", "loud", "mand", "noacl", "noatime", "nodev",
+                "nodiratime", "noexec", "noiversion", "nolazytime", "nomand", "norelatime",
+                "nostrictatime", "nosuid", "nosymfollow", "private", "ratime", "rbind", "rdev",
+                "rdiratime", "relatime", "remount", "rexec", "rnoatime", "rnodev",
+                "rnodiratime", "rnoexec", "rnorelatime", "rnostrictatime", "rnosuid",
+                "rnosymfollow", "ro", "rprivate", "rrelatime", "rro", "rrw", "rshared",
+                "rslave", "rstrictatime", "rsuid", "rsymfollow", "runbindable", "rw", "shared",
+                "silent", "slave", "strictatime", "suid", "symfollow", "sync", "tmpcopyup",
+                "unbindable"
+            ],
+            "linux": {
+                "namespaces": ["cgroup", "ipc", "mount", "network", "pid", "user", "uts"],
+                "capabilities": ["CAP_CHOWN", "CAP_DAC_OVERRIDE", "CAP_KILL", "CAP_SYS_ADMIN"],
+                "cgroup": {
+                    "v1": true,
+                    "v2": true,
+                    "systemd": true,
+                    "systemdUser": true
+                },
+                "seccomp": {
+                    "enabled": true,
+                    "actions": [
+                        "SCMP_ACT_ALLOW", "SCMP_ACT_ERRNO", "SCMP_ACT_KILL",
+                        "SCMP_ACT_KILL_PROCESS", "SCMP_ACT_KILL_THREAD", "SCMP_ACT_LOG",
+                        "SCMP_ACT_NOTIFY", "SCMP_ACT_TRACE", "SCMP_ACT_TRAP"
+                    ],
+                    "operators": [
+                        "SCMP_CMP_EQ", "SCMP_CMP_GE", "SCMP_CMP_GT", "SCMP_CMP_LE",
+                        "SCMP_CMP_LT", "SCMP_CMP_MASKED_EQ", "SCMP_CMP_NE"
+                    ],
+                    "archs": ["SCMP_ARCH_AARCH64", "SCMP_ARCH_X86", "SCMP_ARCH_X86_64"]
+                },
+                "apparmor": {
+                    "enabled": true
+                },
+                "selinux": {
+                    "enabled": true
+                }
+            },
+            "annotations": {
+                "io.github.seccomp.libseccomp.version": "2.5.4",
+                "org.opencontainers.runc.checkpoint.enabled": "true",
+                "org.opencontainers.runc.commit": "v1.1.4-0-g5fd4c4d1",
+                "org.opencontainers.runc.version": "1.1.4"
+            }
+        }"#;
+
+    #[test]
+    fn serde_test() {
+        let f: Features = serde_json::from_str(RUNC_1_1_4_FEATURES).unwrap();
+        assert_eq!(f.oci_version_min.as_deref(), Some("1.0.0"));
+        assert_eq!(f.oci_version_max.as_deref(), Some("1.0.2-dev"));
+        assert_eq!(f.hooks.unwrap().len(), 6);
+        assert!(f.mount_options.unwrap().contains(&"rro".to_string()));
+
+        let linux = f.linux.unwrap();
+        assert!(linux.namespaces.unwrap().contains(&"user".to_string()));
+        let cgroup = linux.cgroup.unwrap();
+        assert_eq!(cgroup.v2, Some(true));
+        assert_eq!(cgroup.systemd_user, Some(true));
+        assert_eq!(cgroup.rdma, None);
+        let seccomp = linux.seccomp.unwrap();
+        assert_eq!(seccomp.enabled, Some(true));
+        assert!(seccomp
+            .actions
+            .unwrap()
+            .contains(&"SCMP_ACT_NOTIFY".to_string()));
+        assert!(seccomp.known_flags.is_none());
+        assert_eq!(linux.apparmor.unwrap().enabled, Some(true));
+        assert!(linux.mount_extensions.is_none());
+
+        let annotations = f.annotations.unwrap();
+        assert_eq!(
+            annotations.get("org.opencontainers.runc.version"),
+            Some(&"1.1.4".to_string())
+        );
+    }
+
+    #[test]
+    fn serde_empty_test() {
+        let f: Features = serde_json::from_str("{}").unwrap();
+        assert!(f.oci_version_min.is_none());
+        assert!(f.linux.is_none());
+    }
+}
@@ -15,16 +15,22 @@
 */
 #[cfg(not(feature = "async"))]
 use std::io::{Read, Write};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::FromRawFd;
+#[cfg(feature = "async")]
+use std::os::unix::io::IntoRawFd;
 use std::{
     fmt::Debug,
     fs::{File, OpenOptions},
     io::Result,
     os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::{Path, PathBuf},
     process::Stdio,
     sync::Mutex,
 };
 
-use log::debug;
+#[cfg(target_os = "linux")]
+use nix::fcntl::OFlag;
 use nix::unistd::{Gid, Uid};
 use os_pipe::{PipeReader, PipeWriter};
 #[cfg(feature = "async")]
@@ -71,10 +77,15 @@ pub trait Io: Debug + Send + Sync {
 
     /// Set IO for passed command.
     /// Read side of stdin, write side of stdout and write side of stderr should be provided to command.
+    /// Implementations hand it duplicated fds, the command closes them when dropped.
     fn set(&self, cmd: &mut Command) -> Result<()>;
 
     /// Only close write side (should be stdout/err "from" runc process)
     fn close_after_start(&self);
+
+    /// Close write side of stdin, so that the process reading it gets EOF.
+    /// Stdout and stderr are left intact.
+    fn close_stdin(&self) {}
 }
 
 #[derive(Debug, Clone)]
@@ -100,8 +111,8 @@ impl Default for IOOption {
 /// When one side of the pipe is closed, the state will be represented with [`None`].
 #[derive(Debug)]
 pub struct Pipe {
-    rd: PipeReader,
-    wr: PipeWriter,
+    rd: Mutex<Option<PipeReader>>,
+    wr: Mutex<Option<PipeWriter>>,
 }
 
 #[derive(Debug)]
@@ -112,9 +123,62 @@ pub struct PipedIo {
 }
 
 impl Pipe {
+    /// Create a new pipe with `O_CLOEXEC` set on both ends.
+    ///
+    /// This keeps the fds from leaking into unrelated children forked between pipe creation and
+    /// the runc spawn. The ends passed to runc are dup2'ed onto its stdio by [`Command`], which
+    /// clears the flag on the duplicated fds, so they still survive into the intended child.
+    #[cfg(target_os = "linux")]
+    fn new() -> std::io::Result<Self> {
+        let (rd, wr) = nix::unistd::pipe2(OFlag::O_CLOEXEC)?;
+        // Safe because both fds are freshly created and owned by nobody else.
+        let (rd, wr) = unsafe { (PipeReader::from_raw_fd(rd), PipeWriter::from_raw_fd(wr)) };
+        Ok(Self {
+            rd: Mutex::new(Some(rd)),
+            wr: Mutex::new(Some(wr)),
+        })
+    }
+
+    // pipe2() is unavailable on some platforms, os_pipe sets CLOEXEC with fcntl() there.
+    #[cfg(not(target_os = "linux"))]
     fn new() -> std::io::Result<Self> {
         let (rd, wr) = os_pipe::pipe()?;
-        Ok(Self { rd, wr })
+        Ok(Self {
+            rd: Mutex::new(Some(rd)),
+            wr: Mutex::new(Some(wr)),
+        })
+    }
+
+    /// Duplicate the read end, which fails once it's closed.
+    fn reader(&self) -> std::io::Result<PipeReader> {
+        match self.rd.lock().unwrap().as_ref() {
+            Some(rd) => rd.try_clone(),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "read end of the pipe is closed",
+            )),
+        }
+    }
+
+    /// Close the read end, duplicates handed out keep the pipe open until they are dropped.
+    fn close_reader(&self) {
+        self.rd.lock().unwrap().take();
+    }
+
+    /// Duplicate the write end, which fails once it's closed.
+    fn writer(&self) -> std::io::Result<PipeWriter> {
+        match self.wr.lock().unwrap().as_ref() {
+            Some(wr) => wr.try_clone(),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "write end of the pipe is closed",
+            )),
+        }
+    }
+
+    /// Close the write end, duplicates handed out keep the pipe open until they are dropped.
+    fn close_writer(&self) {
+        self.wr.lock().unwrap().take();
     }
 }
 
@@ -141,22 +205,109 @@ impl PipedIo {
         let uid = Some(Uid::from_raw(uid));
         let gid = Some(Gid::from_raw(gid));
         if stdin {
-            let rd = pipe.rd.try_clone()?;
+            let rd = pipe.reader()?;
             nix::unistd::fchown(rd.as_raw_fd(), uid, gid)?;
         } else {
-            let wr = pipe.wr.try_clone()?;
+            let wr = pipe.writer()?;
             nix::unistd::fchown(wr.as_raw_fd(), uid, gid)?;
         }
         Ok(Some(pipe))
     }
+
+    /// Copy stdout into `w` until EOF, on a new thread returning `w` once done.
+    ///
+    /// EOF is only reached after the child exited and [`Io::close_after_start`] was called.
+    #[cfg(not(feature = "async"))]
+    pub fn copy_stdout<W>(&self, w: W) -> Result<std::thread::JoinHandle<Result<W>>>
+    where
+        W: Write + Send + 'static,
+    {
+        let r = self.stdout().ok_or_else(|| not_piped("stdout"))?;
+        Ok(std::thread::spawn(move || copy_until_eof(r, w)))
+    }
+
+    /// Copy stderr into `w` until EOF, see [`PipedIo::copy_stdout`].
+    #[cfg(not(feature = "async"))]
+    pub fn copy_stderr<W>(&self, w: W) -> Result<std::thread::JoinHandle<Result<W>>>
+    where
+        W: Write + Send + 'static,
+    {
+        let r = self.stderr().ok_or_else(|| not_piped("stderr"))?;
+        Ok(std::thread::spawn(move || copy_until_eof(r, w)))
+    }
+
+    /// Copy stdout into `w` until EOF, on a new task returning `w` once done.
+    ///
+    /// EOF is only reached after the child exited and [`Io::close_after_start`] was called.
+    #[cfg(feature = "async")]
+    pub fn copy_stdout<W>(&self, w: W) -> Result<tokio::task::JoinHandle<Result<W>>>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let r = self.stdout().ok_or_else(|| not_piped("stdout"))?;
+        Ok(tokio::spawn(copy_until_eof(r, w)))
+    }
+
+    /// Copy stderr into `w` until EOF, see [`PipedIo::copy_stdout`].
+    #[cfg(feature = "async")]
+    pub fn copy_stderr<W>(&self, w: W) -> Result<tokio::task::JoinHandle<Result<W>>>
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let r = self.stderr().ok_or_else(|| not_piped("stderr"))?;
+        Ok(tokio::spawn(copy_until_eof(r, w)))
+    }
+}
+
+fn not_piped(name: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{} is not piped", name),
+    )
+}
+
+#[cfg(not(feature = "async"))]
+fn copy_until_eof<W: Write>(mut r: Box<dyn Read + Send>, mut w: W) -> Result<W> {
+    std::io::copy(&mut r, &mut w)?;
+    w.flush()?;
+    Ok(w)
+}
+
+#[cfg(feature = "async")]
+async fn copy_until_eof<W: AsyncWrite + Unpin>(
+    mut r: Box<dyn AsyncRead + Send + Sync + Unpin>,
+    mut w: W,
+) -> Result<W> {
+    use tokio::io::AsyncWriteExt;
+
+    tokio::io::copy(&mut r, &mut w).await?;
+    w.flush().await?;
+    Ok(w)
+}
+
+/// Turn the write end of a pipe into an async writer, `f` is closed if it can't be.
+#[cfg(feature = "async")]
+fn async_writer(f: impl AsRawFd + IntoRawFd) -> Option<Box<dyn AsyncWrite + Send + Sync + Unpin>> {
+    let w = tokio_pipe::PipeWrite::from_raw_fd_checked(f.as_raw_fd()).ok()?;
+    // The fd is owned by `w` now.
+    let _ = f.into_raw_fd();
+    Some(Box::new(w))
+}
+
+/// Turn the read end of a pipe into an async reader, `f` is closed if it can't be.
+#[cfg(feature = "async")]
+fn async_reader(f: impl AsRawFd + IntoRawFd) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
+    let r = tokio_pipe::PipeRead::from_raw_fd_checked(f.as_raw_fd()).ok()?;
+    // The fd is owned by `r` now.
+    let _ = f.into_raw_fd();
+    Some(Box::new(r))
 }
 
 impl Io for PipedIo {
     #[cfg(not(feature = "async"))]
     fn stdin(&self) -> Option<Box<dyn Write + Send + Sync>> {
         self.stdin.as_ref().and_then(|pipe| {
-            pipe.wr
-                .try_clone()
+            pipe.writer()
                 .map(|x| Box::new(x) as Box<dyn Write + Send + Sync>)
                 .ok()
         })
@@ -164,19 +315,15 @@ impl Io for PipedIo {
 
     #[cfg(feature = "async")]
     fn stdin(&self) -> Option<Box<dyn AsyncWrite + Send + Sync + Unpin>> {
-        self.stdin.as_ref().and_then(|pipe| {
-            let fd = pipe.wr.as_raw_fd();
-            tokio_pipe::PipeWrite::from_raw_fd_checked(fd)
-                .map(|x| Box::new(x) as Box<dyn AsyncWrite + Send + Sync + Unpin>)
-                .ok()
-        })
+        self.stdin
+            .as_ref()
+            .and_then(|pipe| async_writer(pipe.writer().ok()?))
     }
 
     #[cfg(not(feature = "async"))]
     fn stdout(&self) -> Option<Box<dyn Read + Send>> {
         self.stdout.as_ref().and_then(|pipe| {
-            pipe.rd
-                .try_clone()
+            pipe.reader()
                 .map(|x| Box::new(x) as Box<dyn Read + Send>)
                 .ok()
         })
@@ -184,19 +331,15 @@ impl Io for PipedIo {
 
     #[cfg(feature = "async")]
     fn stdout(&self) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
-        self.stdout.as_ref().and_then(|pipe| {
-            let fd = pipe.rd.as_raw_fd();
-            tokio_pipe::PipeRead::from_raw_fd_checked(fd)
-                .map(|x| Box::new(x) as Box<dyn AsyncRead + Send + Sync + Unpin>)
-                .ok()
-        })
+        self.stdout
+            .as_ref()
+            .and_then(|pipe| async_reader(pipe.reader().ok()?))
     }
 
     #[cfg(not(feature = "async"))]
     fn stderr(&self) -> Option<Box<dyn Read + Send>> {
         self.stderr.as_ref().and_then(|pipe| {
-            pipe.rd
-                .try_clone()
+            pipe.reader()
                 .map(|x| Box::new(x) as Box<dyn Read + Send>)
                 .ok()
         })
@@ -204,46 +347,72 @@ impl Io for PipedIo {
 
     #[cfg(feature = "async")]
     fn stderr(&self) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
-        self.stderr.as_ref().and_then(|pipe| {
-            let fd = pipe.rd.as_raw_fd();
-            tokio_pipe::PipeRead::from_raw_fd_checked(fd)
-                .map(|x| Box::new(x) as Box<dyn AsyncRead + Send + Sync + Unpin>)
-                .ok()
-        })
+        self.stderr
+            .as_ref()
+            .and_then(|pipe| async_reader(pipe.reader().ok()?))
     }
 
-    // Note that this internally use [`std::fs::File`]'s `try_clone()`.
-    // Thus, the files passed to commands will be not closed after command exit.
+    // The command gets duplicates of the pipe ends, so dropping it leaves ours open.
     fn set(&self, cmd: &mut Command) -> std::io::Result<()> {
         if let Some(p) = self.stdin.as_ref() {
-            let pr = p.rd.try_clone()?;
+            let pr = p.reader()?;
             cmd.stdin(pr);
         }
 
         if let Some(p) = self.stdout.as_ref() {
-            let pw = p.wr.try_clone()?;
+            let pw = p.writer()?;
             cmd.stdout(pw);
         }
 
         if let Some(p) = self.stderr.as_ref() {
-            let pw = p.wr.try_clone()?;
-            cmd.stdout(pw);
+            let pw = p.writer()?;
+            cmd.stderr(pw);
         }
 
         Ok(())
     }
 
     fn close_after_start(&self) {
-        if let Some(p) = self.stdout.as_ref() {
-            nix::unistd::close(p.wr.as_raw_fd()).unwrap_or_else(|e| debug!("close stdout: {}", e));
+        // The child holds its own copy, without ours writes to stdin fail with EPIPE once the
+        // container closes it instead of blocking when the pipe is full.
+        if let Some(p) = self.stdin.as_ref() {
+            p.close_reader();
         }
 
-        if let Some(p) = self.stderr.as_ref() {
-            nix::unistd::close(p.wr.as_raw_fd()).unwrap_or_else(|e| debug!("close stderr: {}", e));
+        for p in self.stdout.iter().chain(&self.stderr) {
+            p.close_writer();
+        }
+    }
+
+    fn close_stdin(&self) {
+        if let Some(p) = self.stdin.as_ref() {
+            p.close_writer();
         }
     }
 }
 
+/// Ignore SIGPIPE unless a handler is installed, so writing to the stdin of a container which
+/// closed it fails with [`std::io::ErrorKind::BrokenPipe`] instead of killing the process.
+///
+/// Rust binaries ignore it already, programs linking the crate otherwise should call this
+/// before writing to the stdin of [`PipedIo`] or [`FifoIo`], which leave the disposition alone.
+/// Children spawned with [`Command`] get the default disposition back.
+pub fn ignore_sigpipe() -> std::io::Result<()> {
+    // Safe because sigaction() only reads the current disposition when `act` is null.
+    unsafe {
+        let mut old: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(libc::SIGPIPE, std::ptr::null(), &mut old) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if old.sa_sigaction == libc::SIG_DFL
+            && libc::signal(libc::SIGPIPE, libc::SIG_IGN) == libc::SIG_ERR
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
 /// IO driver to direct output/error messages to /dev/null.
 ///
 /// With this Io driver, all methods of [crate::Runc] can't capture the output/error messages.
@@ -355,6 +524,206 @@ impl Io for FIFO {
     fn close_after_start(&self) {}
 }
 
+/// A named pipe, one end is kept and the other handed to runc.
+#[derive(Debug)]
+struct Fifo {
+    path: PathBuf,
+    ours: Mutex<Option<File>>,
+    theirs: Mutex<Option<File>>,
+}
+
+impl Fifo {
+    /// Create the fifo at `path`, runc reads from it if `stdin` and writes to it otherwise.
+    fn new(path: PathBuf, stdin: bool) -> Result<Self> {
+        nix::unistd::mkfifo(&path, nix::sys::stat::Mode::S_IRWXU)?;
+        let (rd, wr) = match Self::open(&path) {
+            Ok(ends) => ends,
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                return Err(e);
+            }
+        };
+        let (ours, theirs) = if stdin { (wr, rd) } else { (rd, wr) };
+        Ok(Self {
+            path,
+            ours: Mutex::new(Some(ours)),
+            theirs: Mutex::new(Some(theirs)),
+        })
+    }
+
+    fn open(path: &Path) -> Result<(File, File)> {
+        // Opening a fifo blocks until its other end is opened, unless O_NONBLOCK is set. Then
+        // the write end can only be opened once there's a reader, hence the order.
+        let rd = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+        let wr = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+        // Only the open needed it, runc expects blocking stdio.
+        for f in [&rd, &wr] {
+            let flags = nix::fcntl::fcntl(f.as_raw_fd(), nix::fcntl::FcntlArg::F_GETFL)?;
+            let flags =
+                nix::fcntl::OFlag::from_bits_truncate(flags) & !nix::fcntl::OFlag::O_NONBLOCK;
+            nix::fcntl::fcntl(f.as_raw_fd(), nix::fcntl::FcntlArg::F_SETFL(flags))?;
+        }
+        Ok((rd, wr))
+    }
+
+    fn ours(&self) -> Option<File> {
+        let ours = self.ours.lock().unwrap();
+        ours.as_ref().and_then(|f| f.try_clone().ok())
+    }
+
+    fn theirs(&self) -> Result<Option<File>> {
+        let theirs = self.theirs.lock().unwrap();
+        theirs.as_ref().map(|f| f.try_clone()).transpose()
+    }
+}
+
+/// IO driver creating named pipes for the stdio of runc, in the way of containerd.
+///
+/// Unlike [`FIFO`] which opens existing fifos, the fifos are created by [`FifoIo::new`] and
+/// removed by [`FifoIo::close`]. Both ends are opened, so neither side blocks waiting for
+/// the other.
+#[derive(Debug)]
+pub struct FifoIo {
+    stdin: Option<Fifo>,
+    stdout: Option<Fifo>,
+    stderr: Option<Fifo>,
+}
+
+impl FifoIo {
+    /// Create the fifos enabled by `opts` as `<id>-stdin`, `<id>-stdout` and `<id>-stderr`
+    /// in `dir`.
+    pub fn new(dir: impl AsRef<Path>, id: &str, opts: &IOOption) -> Result<Self> {
+        let mut io = Self {
+            stdin: None,
+            stdout: None,
+            stderr: None,
+        };
+        let dir = dir.as_ref();
+        let create = |enabled: bool, name: &str| -> Result<Option<Fifo>> {
+            if !enabled {
+                return Ok(None);
+            }
+            Fifo::new(dir.join(format!("{}-{}", id, name)), name == "stdin").map(Some)
+        };
+        let created = create(opts.open_stdin, "stdin").and_then(|stdin| {
+            io.stdin = stdin;
+            io.stdout = create(opts.open_stdout, "stdout")?;
+            io.stderr = create(opts.open_stderr, "stderr")?;
+            Ok(())
+        });
+        if let Err(e) = created {
+            let _ = io.close();
+            return Err(e);
+        }
+        Ok(io)
+    }
+
+    pub fn stdin_path(&self) -> Option<&Path> {
+        self.stdin.as_ref().map(|f| f.path.as_path())
+    }
+
+    pub fn stdout_path(&self) -> Option<&Path> {
+        self.stdout.as_ref().map(|f| f.path.as_path())
+    }
+
+    pub fn stderr_path(&self) -> Option<&Path> {
+        self.stderr.as_ref().map(|f| f.path.as_path())
+    }
+
+    fn fifos(&self) -> impl Iterator<Item = &Fifo> {
+        self.stdin.iter().chain(&self.stdout).chain(&self.stderr)
+    }
+
+    /// Remove the fifos, opened ends keep working until dropped.
+    pub fn close(&self) -> Result<()> {
+        for fifo in self.fifos() {
+            match std::fs::remove_file(&fifo.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Io for FifoIo {
+    #[cfg(not(feature = "async"))]
+    fn stdin(&self) -> Option<Box<dyn Write + Send + Sync>> {
+        let f = self.stdin.as_ref()?.ours()?;
+        Some(Box::new(f))
+    }
+
+    #[cfg(feature = "async")]
+    fn stdin(&self) -> Option<Box<dyn AsyncWrite + Send + Sync + Unpin>> {
+        async_writer(self.stdin.as_ref()?.ours()?)
+    }
+
+    #[cfg(not(feature = "async"))]
+    fn stdout(&self) -> Option<Box<dyn Read + Send>> {
+        let f = self.stdout.as_ref()?.ours()?;
+        Some(Box::new(f))
+    }
+
+    #[cfg(feature = "async")]
+    fn stdout(&self) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
+        async_reader(self.stdout.as_ref()?.ours()?)
+    }
+
+    #[cfg(not(feature = "async"))]
+    fn stderr(&self) -> Option<Box<dyn Read + Send>> {
+        let f = self.stderr.as_ref()?.ours()?;
+        Some(Box::new(f))
+    }
+
+    #[cfg(feature = "async")]
+    fn stderr(&self) -> Option<Box<dyn AsyncRead + Send + Sync + Unpin>> {
+        async_reader(self.stderr.as_ref()?.ours()?)
+    }
+
+    fn set(&self, cmd: &mut Command) -> Result<()> {
+        if let Some(f) = self.stdin.as_ref().map(Fifo::theirs).transpose()?.flatten() {
+            cmd.stdin(f);
+        }
+        if let Some(f) = self
+            .stdout
+            .as_ref()
+            .map(Fifo::theirs)
+            .transpose()?
+            .flatten()
+        {
+            cmd.stdout(f);
+        }
+        if let Some(f) = self
+            .stderr
+            .as_ref()
+            .map(Fifo::theirs)
+            .transpose()?
+            .flatten()
+        {
+            cmd.stderr(f);
+        }
+        Ok(())
+    }
+
+    fn close_after_start(&self) {
+        for fifo in self.fifos() {
+            fifo.theirs.lock().unwrap().take();
+        }
+    }
+
+    fn close_stdin(&self) {
+        if let Some(fifo) = self.stdin.as_ref() {
+            fifo.ours.lock().unwrap().take();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -390,14 +759,14 @@ mod tests {
 
         io.stdin
             .as_ref()
-            .map(|v| v.rd.try_clone().unwrap().read(&mut buf).unwrap());
+            .map(|v| v.reader().unwrap().read(&mut buf).unwrap());
         assert_eq!(&buf, &[0xfau8]);
 
         let mut stdout = io.stdout().unwrap();
         buf[0] = 0xce;
         io.stdout
             .as_ref()
-            .map(|v| v.wr.try_clone().unwrap().write(&buf).unwrap());
+            .map(|v| v.writer().unwrap().write(&buf).unwrap());
         buf[0] = 0x0;
         stdout.read_exact(&mut buf).unwrap();
         assert_eq!(&buf, &[0xceu8]);
@@ -406,7 +775,7 @@ mod tests {
         buf[0] = 0xa5;
         io.stderr
             .as_ref()
-            .map(|v| v.wr.try_clone().unwrap().write(&buf).unwrap());
+            .map(|v| v.writer().unwrap().write(&buf).unwrap());
         buf[0] = 0x0;
         stderr.read_exact(&mut buf).unwrap();
         assert_eq!(&buf, &[0xa5u8]);
@@ -416,6 +785,242 @@ mod tests {
         stderr.read_exact(&mut buf).unwrap_err();
     }
 
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_close_stdin() {
+        let opts = IOOption::default();
+        let uid = nix::unistd::getuid();
+        let gid = nix::unistd::getgid();
+        let io = PipedIo::new(uid.as_raw(), gid.as_raw(), &opts).unwrap();
+
+        let mut cmd = Command::new("cat");
+        io.set(&mut cmd).unwrap();
+        let mut child = cmd.spawn().unwrap();
+        io.close_after_start();
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(child.try_wait().unwrap().is_none());
+
+        io.close_stdin();
+        // Closing it again must not close an fd reusing the number.
+        let spare = File::open("/dev/null").unwrap();
+        io.close_stdin();
+        assert!(nix::fcntl::fcntl(spare.as_raw_fd(), nix::fcntl::FcntlArg::F_GETFD).is_ok());
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let status = loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                break status;
+            }
+            assert!(std::time::Instant::now() < deadline, "cat never saw EOF");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert!(status.success());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_set_keeps_pipes_open() {
+        let opts = IOOption {
+            open_stdin: false,
+            ..Default::default()
+        };
+        let uid = nix::unistd::getuid();
+        let gid = nix::unistd::getgid();
+        let io = PipedIo::new(uid.as_raw(), gid.as_raw(), &opts).unwrap();
+
+        {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", "echo out; echo err >&2"]);
+            io.set(&mut cmd).unwrap();
+            assert!(cmd.status().unwrap().success());
+        }
+
+        let mut buf = [0u8; 4];
+        io.stdout().unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"out\n");
+        io.stderr().unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"err\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_set_keeps_pipes_open() {
+        use tokio::io::AsyncReadExt;
+
+        let opts = IOOption {
+            open_stdin: false,
+            ..Default::default()
+        };
+        let uid = nix::unistd::getuid();
+        let gid = nix::unistd::getgid();
+        let io = PipedIo::new(uid.as_raw(), gid.as_raw(), &opts).unwrap();
+
+        {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", "echo out; echo err >&2"]);
+            io.set(&mut cmd).unwrap();
+            assert!(cmd.status().await.unwrap().success());
+        }
+
+        // Dropping a reader must not close the pipe end kept by the Io.
+        drop(io.stdout().unwrap());
+        let mut buf = [0u8; 4];
+        io.stdout().unwrap().read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"out\n");
+        io.stderr().unwrap().read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"err\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_copy_output() {
+        let opts = IOOption {
+            open_stdin: false,
+            ..Default::default()
+        };
+        let uid = nix::unistd::getuid();
+        let gid = nix::unistd::getgid();
+        let io = PipedIo::new(uid.as_raw(), gid.as_raw(), &opts).unwrap();
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo hello; echo oops >&2; echo world"]);
+        io.set(&mut cmd).unwrap();
+        let mut child = cmd.spawn().unwrap();
+        drop(cmd);
+        let stdout = io.copy_stdout(Vec::new()).unwrap();
+        let stderr = io.copy_stderr(Vec::new()).unwrap();
+        io.close_after_start();
+
+        assert!(child.wait().unwrap().success());
+        assert_eq!(stdout.join().unwrap().unwrap(), b"hello\nworld\n");
+        assert_eq!(stderr.join().unwrap().unwrap(), b"oops\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_copy_output() {
+        let opts = IOOption {
+            open_stdin: false,
+            ..Default::default()
+        };
+        let uid = nix::unistd::getuid();
+        let gid = nix::unistd::getgid();
+        let io = PipedIo::new(uid.as_raw(), gid.as_raw(), &opts).unwrap();
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo hello; echo oops >&2; echo world"]);
+        io.set(&mut cmd).unwrap();
+        let mut child = cmd.spawn().unwrap();
+        drop(cmd);
+        let stdout = io.copy_stdout(Vec::new()).unwrap();
+        let stderr = io.copy_stderr(Vec::new()).unwrap();
+        io.close_after_start();
+
+        assert!(child.wait().await.unwrap().success());
+        assert_eq!(stdout.await.unwrap().unwrap(), b"hello\nworld\n");
+        assert_eq!(stderr.await.unwrap().unwrap(), b"oops\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_fifo_io() {
+        use std::os::unix::fs::FileTypeExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let opts = IOOption {
+            open_stderr: false,
+            ..Default::default()
+        };
+        let io = FifoIo::new(dir.path(), "test", &opts).unwrap();
+        assert!(io.stderr_path().is_none());
+        let stdin_path = io.stdin_path().unwrap().to_path_buf();
+        assert_eq!(stdin_path, dir.path().join("test-stdin"));
+        let file_type = std::fs::metadata(&stdin_path).unwrap().file_type();
+        assert!(file_type.is_fifo());
+
+        let mut cmd = Command::new("cat");
+        io.set(&mut cmd).unwrap();
+        let mut child = cmd.spawn().unwrap();
+        drop(cmd);
+        io.close_after_start();
+
+        io.stdin().unwrap().write_all(b"through a fifo\n").unwrap();
+        io.close_stdin();
+        assert!(child.wait().unwrap().success());
+
+        let mut out = String::new();
+        io.stdout().unwrap().read_to_string(&mut out).unwrap();
+        assert_eq!(out, "through a fifo\n");
+
+        io.close().unwrap();
+        assert!(!stdin_path.exists());
+        assert!(!dir.path().join("test-stdout").exists());
+    }
+
+    /// Runs [`write_closed_stdin`] in a fresh process, the test harness ignores SIGPIPE already.
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_write_closed_stdin() {
+        if std::env::var_os(SIGPIPE_CHILD_ENV).is_some() {
+            return write_closed_stdin();
+        }
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "io::tests::test_write_closed_stdin",
+                "--test-threads=1",
+            ])
+            .env(SIGPIPE_CHILD_ENV, "1")
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "child died: {:?}", status);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "async"))]
+    const SIGPIPE_CHILD_ENV: &str = "RUNC_TEST_SIGPIPE_CHILD";
+
+    #[cfg(target_os = "linux")]
+    #[cfg(not(feature = "async"))]
+    fn write_closed_stdin() {
+        unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+        ignore_sigpipe().unwrap();
+        let opts = IOOption::default();
+        let uid = nix::unistd::getuid();
+        let gid = nix::unistd::getgid();
+        let io = PipedIo::new(uid.as_raw(), gid.as_raw(), &opts).unwrap();
+        // No child holds the read end, as if the container had closed its stdin.
+        io.close_after_start();
+
+        let err = io.stdin().unwrap().write_all(b"hello").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pipe_cloexec() {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+        let pipe = Pipe::new().unwrap();
+        let rd = pipe.rd.lock().unwrap();
+        let wr = pipe.wr.lock().unwrap();
+        for fd in [
+            rd.as_ref().unwrap().as_raw_fd(),
+            wr.as_ref().unwrap().as_raw_fd(),
+        ] {
+            let flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD).unwrap());
+            assert!(flags.contains(FdFlag::FD_CLOEXEC));
+        }
+    }
+
     #[test]
     fn test_null_io() {
         let io = NullIo::new().unwrap();
@@ -35,38 +35,101 @@
 
 //! A crate for consuming the runc binary in your Rust applications, similar to
 //! [go-runc](https://github.com/containerd/go-runc) for Go.
+#[cfg(not(feature = "async"))]
+use std::os::unix::process::CommandExt;
+#[cfg(feature = "async")]
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{
     fmt::{self, Debug, Display},
+    fs::File,
+    os::unix::io::{AsRawFd, RawFd},
     path::{Path, PathBuf},
     process::{ExitStatus, Stdio},
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 #[cfg(feature = "async")]
 use async_trait::async_trait;
 #[cfg(feature = "async")]
-use log::debug;
+use futures::StreamExt;
+use log::{debug, warn};
 use oci_spec::runtime::{LinuxResources, Process};
+#[cfg(not(feature = "async"))]
+use tempfile::NamedTempFile;
+#[cfg(feature = "async")]
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    task::JoinHandle,
+};
 
-use crate::{container::Container, error::Error, options::*, utils::write_value_to_temp_file};
+use crate::{
+    container::{parse_ps_table, Container, ContainerStatus, ListFilter, ProcessInfo},
+    error::{CommandErrorKind, Error},
+    features::Features,
+    options::*,
+    utils::{write_value_to_temp_file, CgroupVersion, RootlessDecision},
+};
 
+pub mod console;
 pub mod container;
+pub mod diff;
 pub mod error;
 pub mod events;
+pub mod features;
 pub mod io;
+pub mod logs;
 #[cfg(feature = "async")]
 pub mod monitor;
 pub mod options;
+pub mod specs;
 pub mod utils;
 
 pub type Result<T> = std::result::Result<T, crate::error::Error>;
 
+/// Interval to poll the container state while waiting for it to stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Number of times the state is checked before giving up on a deleted container.
+const DELETE_VERIFY_ATTEMPTS: usize = 10;
+
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+
+/// Variables still passed to runc when the environment is cleared.
+const KEPT_ENV: [&str; 2] = ["PATH", "XDG_RUNTIME_DIR"];
+
+/// Time the output of a timed out command is still read for after it has been killed.
+#[cfg(feature = "async")]
+const TIMEOUT_OUTPUT_GRACE: Duration = Duration::from_millis(100);
+
+/// Default cap of the stdout and stderr kept from each command.
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 16 << 20;
+
+/// Maximum number of `runc events --stats` run at the same time by `stats_many`.
+///
+/// Each of them still waits for [`GlobalOpts::max_concurrent_commands`] if that's lower.
+#[cfg(feature = "async")]
+const STATS_CONCURRENCY: usize = 8;
+
 /// Response is for (pid, exit status, outputs).
 #[derive(Debug, Clone)]
 pub struct Response {
     pub pid: u32,
     pub status: ExitStatus,
+    /// Stdout, followed by stderr for commands which combine their outputs.
     pub output: String,
+    pub stdout: String,
+    pub stderr: String,
+    /// Whether stdout or stderr was cut at the client's output cap.
+    pub truncated: bool,
+}
+
+/// Statistics and processes of a container, taken together by [`Runc::snapshot`].
+#[derive(Debug)]
+pub struct ContainerSnapshot {
+    pub stats: events::Stats,
+    /// The processes as listed by `ps -ef`, or why they couldn't be listed.
+    pub processes: std::result::Result<Vec<ProcessInfo>, Error>,
 }
 
 #[derive(Debug, Clone)]
@@ -76,6 +139,60 @@ pub struct Version {
     pub commit: Option<String>,
 }
 
+impl Version {
+    /// Version with only the runc version set, e.g. to pass to [`GlobalOpts::verify_version`].
+    pub fn new(runc_version: impl Into<String>) -> Self {
+        Self {
+            runc_version: Some(runc_version.into()),
+            spec_version: None,
+            commit: None,
+        }
+    }
+
+    /// Parse the output of `runc --version`.
+    pub fn parse(output: &str) -> Result<Self> {
+        let mut version = Self {
+            runc_version: None,
+            spec_version: None,
+            commit: None,
+        };
+        for line in output.lines() {
+            if let Some(v) = line.strip_prefix("runc version ") {
+                version.runc_version = Some(v.trim().to_string());
+            } else if let Some(v) = line.strip_prefix("spec: ") {
+                version.spec_version = Some(v.trim().to_string());
+            } else if let Some(v) = line.strip_prefix("commit: ") {
+                version.commit = Some(v.trim().to_string());
+            }
+        }
+        if version.runc_version.is_none() {
+            return Err(Error::InvalidVersion);
+        }
+        Ok(version)
+    }
+
+    /// Compare the numeric parts of the runc versions, e.g. `1.1.0-rc.1` is taken as `1.1.0`.
+    pub fn at_least(&self, min: &Version) -> bool {
+        fn numbers(v: &Version) -> Vec<u64> {
+            let v = v.runc_version.as_deref().unwrap_or_default();
+            let v = v.trim_start_matches('v');
+            let release = v
+                .split(|c: char| c == '-' || c == '+')
+                .next()
+                .unwrap_or_default();
+            release.split('.').map(|n| n.parse().unwrap_or(0)).collect()
+        }
+        let (found, min) = (numbers(self), numbers(min));
+        for i in 0..found.len().max(min.len()) {
+            let (f, m) = (found.get(i).unwrap_or(&0), min.get(i).unwrap_or(&0));
+            if f != m {
+                return f > m;
+            }
+        }
+        true
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum LogFormat {
     Json,
@@ -103,16 +220,102 @@ pub type Command = std::process::Command;
 #[cfg(feature = "async")]
 pub type Command = tokio::process::Command;
 
+/// Client of the runc binary, built with [`GlobalOpts::build`].
+///
+/// Its methods block unless the `async` feature is enabled, then they're async. Clones share the
+/// spawner and the detected cgroup version, and run commands with identical global flags.
+///
+/// There's no conversion between a blocking and an async client, the `async` feature picks
+/// which one is compiled so both never exist in the same build.
 #[derive(Debug, Clone)]
 pub struct Runc {
     command: PathBuf,
     args: Vec<String>,
     spawner: Arc<dyn Spawner + Send + Sync>,
+    timeout: Option<Duration>,
+    rlimit_nofile: Option<(u64, u64)>,
+    /// Log file written in JSON format, errors found there are attached to failed commands.
+    json_log: Option<PathBuf>,
+    keep_spec_files: bool,
+    spec_dir: Option<PathBuf>,
+    validate_bundles: bool,
+    env: Vec<(String, String)>,
+    env_clear: bool,
+    /// Detected on first use, the cgroup mount doesn't change while we're running.
+    cgroup_version: Arc<Mutex<Option<CgroupVersion>>>,
+    rootless_decision: Option<RootlessDecision>,
+    max_output_bytes: usize,
+    /// Start runc in a process group of its own.
+    set_pgid: bool,
+    command_hook: Option<CommandHook>,
+    /// Permits to run a command, shared by the clones of the client.
+    #[cfg(feature = "async")]
+    command_limit: Option<Arc<tokio::sync::Semaphore>>,
+    /// Number of commands running, shared by the clones of the client.
+    #[cfg(feature = "async")]
+    in_flight: Arc<AtomicUsize>,
 }
 
 impl Runc {
+    /// Global options passed to runc before the subcommand, e.g. `--root` and `--log`.
+    ///
+    /// They're derived once by [`GlobalOpts::build`], some commands override
+    /// `--systemd-cgroup`.
+    pub fn global_args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Entries of the JSON log runc writes, see [`GlobalOpts::log_json`].
+    ///
+    /// Only the tail of a large log is read. Without a JSON log, or before runc wrote to it,
+    /// there are no entries. The file is read blocking, even with the async client.
+    pub fn read_log(&self) -> Vec<logs::LogEntry> {
+        self.json_log
+            .as_deref()
+            .map(logs::read_log)
+            .unwrap_or_default()
+    }
+
+    /// Outcome of the rootless auto detection, [`None`] if the mode was set explicitly.
+    pub fn rootless_decision(&self) -> Option<RootlessDecision> {
+        self.rootless_decision
+    }
+
+    /// Check the `config.json` of `bundle` has the fields runc requires
+    pub fn validate_bundle(&self, bundle: impl AsRef<Path>) -> Result<()> {
+        specs::validate_bundle(bundle.as_ref())
+    }
+
+    /// Archive the writable layer of an overlay-backed container, its overlay `upperdir`
+    ///
+    /// The archive is produced by `tar` as it's read, reading blocks even with the async
+    /// client.
+    pub fn export_diff(&self, id: &str, upperdir: &Path) -> Result<diff::DiffReader> {
+        utils::validate_container_id(id)?;
+        diff::tar_upperdir(upperdir)
+    }
+
+    /// Version of the cgroup hierarchy on this host, detected from `/sys/fs/cgroup`.
+    pub fn cgroup_version(&self) -> CgroupVersion {
+        *self
+            .cgroup_version
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| utils::cgroup_version(CGROUP_MOUNT))
+    }
+
     fn command(&self, args: &[String]) -> Result<Command> {
-        let args = [&self.args, args].concat();
+        self.command_with_cgroup(args, None)
+    }
+
+    /// Like [`Runc::command`], `--systemd-cgroup` is set as `systemd_cgroup` says unless it's
+    /// [`None`], overriding the client's setting.
+    #[cfg(target_os = "linux")]
+    fn command_with_cgroup(
+        &self,
+        args: &[String],
+        systemd_cgroup: Option<bool>,
+    ) -> Result<Command> {
         let mut cmd = Command::new(&self.command);
 
         // Default to piped stdio, and they may be override by command options.
@@ -120,34 +323,298 @@ impl Runc {
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
 
+        if self.env_clear {
+            cmd.env_clear();
+            for key in KEPT_ENV {
+                if let Some(value) = std::env::var_os(key) {
+                    cmd.env(key, value);
+                }
+            }
+        }
+        // The global options are derived once when the client is built.
+        match systemd_cgroup {
+            None => {
+                cmd.args(&self.args);
+            }
+            Some(systemd_cgroup) => {
+                cmd.args(self.args.iter().filter(|arg| *arg != SYSTEMD_CGROUP));
+                if systemd_cgroup {
+                    cmd.arg(SYSTEMD_CGROUP);
+                }
+            }
+        }
         // NOTIFY_SOCKET introduces a special behavior in runc but should only be set if invoked from systemd
-        cmd.args(&args).env_remove("NOTIFY_SOCKET");
+        cmd.args(args).env_remove("NOTIFY_SOCKET");
+        cmd.envs(self.env.iter().map(|(k, v)| (k, v)));
+
+        if self.set_pgid {
+            // Safe because setpgid() is async-signal-safe.
+            unsafe {
+                cmd.pre_exec(|| {
+                    if libc::setpgid(0, 0) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        if let Some((soft, hard)) = self.rlimit_nofile {
+            // Safe because setrlimit() is async-signal-safe.
+            unsafe {
+                cmd.pre_exec(move || {
+                    let limit = libc::rlimit {
+                        rlim_cur: soft as libc::rlim_t,
+                        rlim_max: hard as libc::rlim_t,
+                    };
+                    if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
 
         Ok(cmd)
     }
+
+    /// runc only runs on Linux, elsewhere the client is only built for its types and every
+    /// command fails.
+    #[cfg(not(target_os = "linux"))]
+    fn command_with_cgroup(
+        &self,
+        _args: &[String],
+        _systemd_cgroup: Option<bool>,
+    ) -> Result<Command> {
+        Err(Error::Unimplemented(
+            "runc commands on this platform".to_string(),
+        ))
+    }
+
+    /// The subcommand `cmd` runs, the first argument after the global options.
+    fn subcommand(&self, cmd: &Command) -> String {
+        #[cfg(feature = "async")]
+        let cmd = cmd.as_std();
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        let globals = if args
+            .iter()
+            .take(self.args.len())
+            .eq(self.args.iter().map(|a| a.as_str()))
+        {
+            self.args.len()
+        } else {
+            // The command overrides the cgroup driver, see `command_with_cgroup`.
+            let kept = self.args.iter().filter(|a| *a != SYSTEMD_CGROUP).count();
+            kept + usize::from(args.get(kept).map_or(false, |a| a == SYSTEMD_CGROUP))
+        };
+        args.get(globals).map(|a| a.to_string()).unwrap_or_default()
+    }
+
+    /// Let the hook set with [`GlobalOpts::command_hook`] adjust `cmd` before it's spawned.
+    fn apply_command_hook(&self, cmd: &mut Command) {
+        if let Some(hook) = &self.command_hook {
+            (hook.0)(cmd);
+        }
+    }
+
+    fn log_offset(&self) -> u64 {
+        self.json_log.as_deref().map(logs::log_offset).unwrap_or(0)
+    }
+
+    fn command_failed(
+        &self,
+        status: ExitStatus,
+        stdout: String,
+        stderr: String,
+        log_offset: u64,
+    ) -> Error {
+        let log_messages = self
+            .json_log
+            .as_deref()
+            .map(|path| logs::read_error_entries(path, log_offset))
+            .unwrap_or_default();
+        Error::CommandFailed {
+            status,
+            stdout,
+            stderr,
+            log_messages,
+        }
+    }
+}
+
+/// Cut `output` to at most `max` bytes on a char boundary, return whether anything was cut.
+fn truncate_output(output: &mut String, max: usize) -> bool {
+    if output.len() <= max {
+        return false;
+    }
+    let mut end = max;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    output.truncate(end);
+    true
+}
+
+/// Make sure raw resources are at least a JSON object before handing them to runc.
+fn check_resources_json(resources: &str) -> Result<()> {
+    serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(resources)
+        .map(|_| ())
+        .map_err(Error::JsonDeserializationFailed)
+}
+
+/// Parse the JSON runc printed when run with `args`.
+///
+/// Empty output, or `null` which Go prints for an empty list, is `empty` if there's a sensible
+/// default, [`Error::EmptyOutput`] otherwise.
+fn parse_output<T: serde::de::DeserializeOwned>(
+    args: &[String],
+    output: &str,
+    empty: Option<T>,
+) -> Result<T> {
+    let output = output.trim();
+    if output.is_empty() || output == "null" {
+        return empty.ok_or_else(|| Error::EmptyOutput {
+            command: args.join(" "),
+        });
+    }
+    serde_json::from_str(output).map_err(Error::JsonDeserializationFailed)
+}
+
+/// Whether `c` is still created, from `bundle` rather than another one with the same id.
+fn is_created_from(c: &Container, bundle: &str) -> bool {
+    c.container_status() == ContainerStatus::Created && Path::new(&c.bundle) == Path::new(bundle)
+}
+
+/// Response of a create which found the container `c` already created, its pid is written to
+/// `pid_file` like runc would have.
+fn created_response(c: &Container, pid_file: Option<&PathBuf>) -> Result<Response> {
+    use std::os::unix::process::ExitStatusExt;
+
+    if let Some(path) = pid_file {
+        std::fs::write(path, c.pid.to_string()).map_err(Error::FileSystemError)?;
+    }
+    Ok(Response {
+        pid: c.pid as u32,
+        status: ExitStatus::from_raw(0),
+        output: String::new(),
+        stdout: String::new(),
+        stderr: String::new(),
+        truncated: false,
+    })
+}
+
+/// Report the pid runc wrote to `pid_file` in the response.
+///
+/// The pid of the runc process is useless for detached containers, the pid file is the only
+/// reliable source of the container's pid.
+fn pid_from_file(mut res: Response, pid_file: Option<&PathBuf>) -> Result<Response> {
+    if let Some(path) = pid_file {
+        res.pid = utils::read_pid_file(path).map_err(Error::FileSystemError)?;
+    }
+    Ok(res)
+}
+
+/// Fail early when the process wants a terminal but there's no console socket to send it to,
+/// runc fails with an unclear message otherwise.
+fn check_console_socket(spec: &Process, opts: Option<&ExecOpts>) -> Result<()> {
+    let has_socket = opts.map_or(false, |o| o.console_socket.is_some());
+    if matches!(spec.terminal(), Some(true)) && !has_socket {
+        return Err(Error::MissingConsoleSocket);
+    }
+    Ok(())
+}
+
+/// Make `files` inherited by the runc process as fds 3, 4, ... in order, as expected by
+/// `--preserve-fds`.
+fn preserve_fds(cmd: &mut Command, files: &[Arc<File>]) {
+    if files.is_empty() {
+        return;
+    }
+    let fds: Vec<RawFd> = files.iter().map(|f| f.as_raw_fd()).collect();
+    let mut tmp = vec![-1; fds.len()];
+    let first_free = 3 + fds.len() as RawFd;
+    // Safe because fcntl() and dup2() are async-signal-safe, and nothing is allocated.
+    unsafe {
+        cmd.pre_exec(move || {
+            // Move all fds out of the target range first, so none of them is overwritten before
+            // it's duplicated. The temporary copies are CLOEXEC and go away on exec.
+            for (t, fd) in tmp.iter_mut().zip(fds.iter()) {
+                *t = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, first_free);
+                if *t < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            // dup2() clears CLOEXEC on the new fds, so they survive exec.
+            for (i, t) in tmp.iter().enumerate() {
+                if libc::dup2(*t, 3 + i as RawFd) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+// Older runc binaries reject subcommands they don't know with "unknown command" or
+// "No help topic for ..." messages, translate those to [Error::Unsupported] so callers can fall back.
+fn unsupported_command(e: Error, command: &str) -> Error {
+    match e {
+        Error::CommandFailed {
+            ref stdout,
+            ref stderr,
+            ..
+        } => {
+            let msg = format!("{}{}", stdout, stderr).to_lowercase();
+            if msg.contains("unknown command") || msg.contains("no help topic") {
+                Error::Unsupported(command.to_string())
+            } else {
+                e
+            }
+        }
+        e => e,
+    }
 }
 
 #[cfg(not(feature = "async"))]
 impl Runc {
-    fn launch(&self, cmd: Command, combined_output: bool) -> Result<Response> {
-        let (status, pid, stdout, stderr) = self.spawner.execute(cmd)?;
+    fn launch(
+        &self,
+        mut cmd: Command,
+        combined_output: bool,
+        id: Option<&str>,
+    ) -> Result<Response> {
+        self.apply_command_hook(&mut cmd);
+        debug!("Execute command {:?}", cmd);
+        let subcommand = self.subcommand(&cmd);
+        let log_offset = self.log_offset();
+        let start = Instant::now();
+        let (status, pid, mut stdout, mut stderr) = self.spawner.execute(cmd)?;
+        debug!(
+            "runc {} of container {} (pid {}) exited with {} after {:?}",
+            subcommand,
+            id.unwrap_or("-"),
+            pid,
+            status,
+            start.elapsed()
+        );
+        let truncated = truncate_output(&mut stdout, self.max_output_bytes)
+            | truncate_output(&mut stderr, self.max_output_bytes);
         if status.success() {
             let output = if combined_output {
-                stdout + stderr.as_str()
+                stdout.clone() + stderr.as_str()
             } else {
-                stdout
+                stdout.clone()
             };
             Ok(Response {
                 pid,
                 status,
                 output,
-            })
-        } else {
-            Err(Error::CommandFailed {
-                status,
                 stdout,
                 stderr,
+                truncated,
             })
+        } else {
+            Err(self.command_failed(status, stdout, stderr, log_offset))
         }
     }
 
@@ -156,24 +623,52 @@ impl Runc {
     where
         P: AsRef<Path>,
     {
-        let mut args = vec![
-            "create".to_string(),
-            "--bundle".to_string(),
-            utils::abs_string(bundle)?,
-        ];
+        utils::validate_container_id(id)?;
+        if self.validate_bundles {
+            self.validate_bundle(&bundle)?;
+        }
+        let bundle = utils::abs_string(bundle)?;
+        let mut args = vec!["create".to_string(), "--bundle".to_string(), bundle.clone()];
         if let Some(opts) = opts {
             args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
-        match opts {
+        let systemd_cgroup = opts.and_then(|o| o.systemd_cgroup);
+        let mut cmd = self.command_with_cgroup(&args, systemd_cgroup)?;
+        if let Some(opts) = opts {
+            preserve_fds(&mut cmd, &opts.extra_files);
+        }
+        let res = match opts {
             Some(CreateOpts { io: Some(io), .. }) => {
                 io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
-                let res = self.launch(cmd, true)?;
+                let res = self.launch(cmd, true, Some(id));
                 io.close_after_start();
-                Ok(res)
+                res
             }
-            _ => self.launch(cmd, true),
+            _ => self.launch(cmd, true, Some(id)),
+        };
+        let pid_file = opts.and_then(|o| o.pid_file.as_ref());
+        let res = match res {
+            Err(e) if opts.map_or(false, |o| o.ignore_existing) && e.is_already_exists() => {
+                return self.existing_created(id, &bundle, pid_file, e);
+            }
+            r => r?,
+        };
+        pid_from_file(res, pid_file)
+    }
+
+    /// Accept the container `id` which runc found to exist already if it's still created from
+    /// `bundle`, e.g. by a create which failed afterwards, fail with `err` otherwise.
+    fn existing_created(
+        &self,
+        id: &str,
+        bundle: &str,
+        pid_file: Option<&PathBuf>,
+        err: Error,
+    ) -> Result<Response> {
+        match self.state(id) {
+            Ok(c) if is_created_from(&c, bundle) => created_response(&c, pid_file),
+            _ => Err(err),
         }
     }
 
@@ -184,30 +679,105 @@ impl Runc {
             args.append(&mut opts.args());
         }
         args.push(id.to_string());
-        self.launch(self.command(&args)?, true)?;
+        match self.launch(self.command(&args)?, true, Some(id)) {
+            Err(e)
+                if opts.map_or(false, |o| o.ignore_not_found)
+                    && e.command_error_kind() == CommandErrorKind::NotFound =>
+            {
+                return Ok(())
+            }
+            r => r?,
+        };
+        if opts.map_or(false, |o| o.verify) {
+            self.verify_deleted(id)?;
+        }
         Ok(())
     }
 
+    /// Delete a container, killing its processes if it isn't stopped
+    ///
+    /// A normal deletion is tried first. If runc refuses because the container isn't stopped,
+    /// all its processes are killed with `SIGKILL` and the deletion is retried with `--force`.
+    pub fn delete_force(&self, id: &str, opts: &ForceDeleteOpts) -> Result<()> {
+        match self.delete(id, None) {
+            Err(e) if e.command_error_kind() == CommandErrorKind::NotStopped => {}
+            r => return r,
+        }
+        let kill_opts = KillOpts::new().all(true);
+        if let Err(e) = self.kill(id, libc::SIGKILL as u32, Some(&kill_opts)) {
+            warn!("failed to kill container {} before deleting it: {}", id, e);
+        }
+        if !self.wait_stopped(id, opts.stop_timeout)? {
+            warn!(
+                "container {} didn't stop within {:?}",
+                id, opts.stop_timeout
+            );
+        }
+
+        let delete_opts = DeleteOpts::new().force(true);
+        let mut backoff = opts.backoff;
+        let mut attempt = 0;
+        loop {
+            match self.delete(id, Some(&delete_opts)) {
+                Err(e)
+                    if attempt < opts.retries
+                        && e.command_error_kind() != CommandErrorKind::NotFound =>
+                {
+                    warn!("failed to force delete container {}: {}", id, e);
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                r => return r,
+            }
+        }
+    }
+
+    // Check the container state until it's not found, runc may be removing it in the background.
+    fn verify_deleted(&self, id: &str) -> Result<()> {
+        for _ in 0..DELETE_VERIFY_ATTEMPTS {
+            match self.state(id) {
+                Err(e) if e.command_error_kind() == CommandErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e),
+                Ok(_) => std::thread::sleep(STOP_POLL_INTERVAL),
+            }
+        }
+        Err(Error::DeleteVerificationFailed(id.to_string()))
+    }
+
     /// Execute an additional process inside the container
-    pub fn exec(&self, id: &str, spec: &Process, opts: Option<&ExecOpts>) -> Result<()> {
-        let (_temp_file, filename) = write_value_to_temp_file(spec)?;
+    pub fn exec(&self, id: &str, spec: &Process, opts: Option<&ExecOpts>) -> Result<Response> {
+        check_console_socket(spec, opts)?;
+        let (temp_file, filename) = write_value_to_temp_file(self.spec_dir.as_deref(), spec)?;
         let mut args = vec!["exec".to_string(), "--process".to_string(), filename];
         if let Some(opts) = opts {
             args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
         let mut cmd = self.command(&args)?;
-        match opts {
-            Some(ExecOpts { io: Some(io), .. }) => {
-                io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
-                self.launch(cmd, true)?;
-                io.close_after_start();
-            }
-            _ => {
-                self.launch(cmd, true)?;
-            }
+        if let Some(opts) = opts {
+            preserve_fds(&mut cmd, &opts.extra_files);
         }
-        Ok(())
+        let res = match opts {
+            Some(ExecOpts { io: Some(io), .. }) => io
+                .set(&mut cmd)
+                .map_err(|e| Error::IoSet(e.to_string()))
+                .and_then(|_| self.launch(cmd, true, Some(id)))
+                .map(|res| {
+                    io.close_after_start();
+                    res
+                }),
+            _ => self.launch(cmd, true, Some(id)),
+        }
+        .and_then(|res| pid_from_file(res, opts.and_then(|o| o.pid_file.as_ref())));
+        self.cleanup_spec_file(temp_file, res.is_err());
+        res
+    }
+
+    /// Send a signal given by name, e.g. `SIGTERM` or `TERM`, to processes inside the container
+    pub fn kill_by_name(&self, id: &str, sig: &str, opts: Option<&KillOpts>) -> Result<()> {
+        let sig: Signal = sig.parse()?;
+        self.kill(id, sig.number(), opts)
     }
 
     /// Send the specified signal to processes inside the container
@@ -218,40 +788,173 @@ impl Runc {
         }
         args.push(id.to_string());
         args.push(sig.to_string());
-        let _ = self.launch(self.command(&args)?, true)?;
-        Ok(())
+        match self.launch(self.command(&args)?, true, Some(id)) {
+            Err(e)
+                if opts.map_or(true, |o| o.ignore_not_running)
+                    && e.command_error_kind() == CommandErrorKind::NotRunning =>
+            {
+                Ok(())
+            }
+            r => r.map(|_| ()),
+        }
+    }
+
+    /// Return the features supported by the runtime (runc 1.1+)
+    ///
+    /// Returns [Error::Unsupported] if the runc binary doesn't know the `features` command.
+    pub fn features(&self) -> Result<Features> {
+        let args = ["features".to_string()];
+        let res = self
+            .launch(self.command(&args)?, false, None)
+            .map_err(|e| unsupported_command(e, "features"))?;
+        parse_output(&args, &res.output, None)
     }
 
     /// List all containers associated with this runc instance
     pub fn list(&self) -> Result<Vec<Container>> {
         let args = ["list".to_string(), "--format=json".to_string()];
-        let res = self.launch(self.command(&args)?, true)?;
-        let output = res.output.trim();
+        let res = self.launch(self.command(&args)?, false, None)?;
+        parse_output(&args, &res.output, Some(Vec::new()))
+    }
 
-        // Ugly hack to work around golang
-        Ok(if output == "null" {
-            Vec::new()
-        } else {
-            serde_json::from_str(output).map_err(Error::JsonDeserializationFailed)?
-        })
+    /// List the containers matching `filter`, e.g. the running ones
+    pub fn list_filtered(&self, filter: &ListFilter) -> Result<Vec<Container>> {
+        let mut containers = self.list()?;
+        containers.retain(|c| filter.matches(c));
+        Ok(containers)
     }
 
     /// Pause a container
     pub fn pause(&self, id: &str) -> Result<()> {
         let args = ["pause".to_string(), id.to_string()];
-        let _ = self.launch(self.command(&args)?, true)?;
+        let _ = self.launch(self.command(&args)?, true, Some(id))?;
         Ok(())
     }
 
     /// Resume a container
     pub fn resume(&self, id: &str) -> Result<()> {
         let args = ["resume".to_string(), id.to_string()];
-        let _ = self.launch(self.command(&args)?, true)?;
+        let _ = self.launch(self.command(&args)?, true, Some(id))?;
+        Ok(())
+    }
+
+    /// Gracefully restart a container from the same bundle and return the pid of its new process
+    ///
+    /// The container is sent SIGTERM and given `grace` to stop before being killed with SIGKILL,
+    /// then it's deleted, re-created and started.
+    pub fn restart<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        create_opts: Option<&CreateOpts>,
+        delete_opts: Option<&DeleteOpts>,
+        grace: Duration,
+    ) -> Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        self.stop(id, grace)?;
+        self.delete(id, delete_opts)?;
+        self.create(id, bundle, create_opts)?;
+        self.start(id)?;
+        Ok(self.state(id)?.pid)
+    }
+
+    // Send SIGTERM to the container and SIGKILL to all its processes if it doesn't stop within grace.
+    fn stop(&self, id: &str, grace: Duration) -> Result<()> {
+        if self.wait_stopped(id, Duration::ZERO)? {
+            return Ok(());
+        }
+        self.kill(id, libc::SIGTERM as u32, None)?;
+        if !self.wait_stopped(id, grace)? {
+            self.kill(id, libc::SIGKILL as u32, Some(&KillOpts::new().all(true)))?;
+            self.wait_stopped(id, grace)?;
+        }
+        Ok(())
+    }
+
+    // Poll the container state until it's stopped, return false if it's still alive after timeout.
+    fn wait_stopped(&self, id: &str, timeout: Duration) -> Result<bool> {
+        let state =
+            self.wait_for_status(id, ContainerStatus::Stopped, timeout, STOP_POLL_INTERVAL)?;
+        Ok(state.container_status() == ContainerStatus::Stopped)
+    }
+
+    /// Whether the container exists, failures other than runc not finding it are errors
+    pub fn exists(&self, id: &str) -> Result<bool> {
+        match self.state(id) {
+            Ok(_) => Ok(true),
+            Err(e) if e.command_error_kind() == CommandErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Poll the state of a container every `interval` until it has `status` or `timeout` passed
+    ///
+    /// The last state is returned in both cases, its status tells whether it was reached.
+    pub fn wait_for_status(
+        &self,
+        id: &str,
+        status: ContainerStatus,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Container> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let state = self.state(id)?;
+            if state.container_status() == status || Instant::now() >= deadline {
+                return Ok(state);
+            }
+            std::thread::sleep(interval);
+        }
+    }
+
+    /// Send SIGTERM to the container and SIGKILL it if it's not stopped after `grace`,
+    /// a container that is already gone is treated as terminated
+    pub fn terminate(&self, id: &str, grace: Duration) -> Result<()> {
+        if self.signal_unless_stopped(id, libc::SIGTERM)? {
+            return Ok(());
+        }
+        match self.wait_stopped(id, grace) {
+            Ok(true) => return Ok(()),
+            Err(e) if e.command_error_kind() == CommandErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+            Ok(false) => {}
+        }
+        self.signal_unless_stopped(id, libc::SIGKILL)?;
         Ok(())
     }
 
-    pub fn checkpoint(&self) -> Result<()> {
-        Err(Error::Unimplemented("checkpoint".to_string()))
+    // Send a signal to the container, return true if it failed because the container has
+    // already stopped or is gone.
+    fn signal_unless_stopped(&self, id: &str, sig: libc::c_int) -> Result<bool> {
+        let err = match self.kill(id, sig as u32, None) {
+            Ok(()) => return Ok(false),
+            Err(e) => e,
+        };
+        match self.state(id) {
+            Ok(s) if s.container_status() == ContainerStatus::Stopped => Ok(true),
+            Err(e) if e.command_error_kind() == CommandErrorKind::NotFound => Ok(true),
+            _ => Err(err),
+        }
+    }
+
+    /// Checkpoint a running container with criu
+    ///
+    /// The criu in `PATH` is used unless another one is set with [`GlobalOpts::criu`].
+    pub fn checkpoint(&self, id: &str, opts: Option<&CheckpointOpts>) -> Result<()> {
+        let mut args = vec!["checkpoint".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let mut cmd = self.command(&args)?;
+        if let Some(opts) = opts {
+            let files: Vec<_> = opts.status_file.iter().cloned().collect();
+            preserve_fds(&mut cmd, &files);
+        }
+        self.launch(cmd, true, Some(id))?;
+        Ok(())
     }
 
     pub fn restore(&self) -> Result<()> {
@@ -265,22 +968,40 @@ impl Runc {
             "--format=json".to_string(),
             id.to_string(),
         ];
-        let res = self.launch(self.command(&args)?, false)?;
-        let output = res.output.trim();
+        let res = self.launch(self.command(&args)?, false, Some(id))?;
+        parse_output(&args, &res.output, Some(Vec::new()))
+    }
 
-        // Ugly hack to work around golang
-        Ok(if output == "null" {
-            Vec::new()
+    /// List all the processes inside the container with the columns printed by `ps`,
+    /// `ps_opts` are passed to `ps` and default to `-ef`.
+    pub fn ps_full(&self, id: &str, ps_opts: &[&str]) -> Result<Vec<ProcessInfo>> {
+        let mut args = vec![
+            "ps".to_string(),
+            "--format=table".to_string(),
+            id.to_string(),
+        ];
+        if ps_opts.is_empty() {
+            args.push("-ef".to_string());
         } else {
-            serde_json::from_str(output).map_err(Error::JsonDeserializationFailed)?
-        })
+            args.extend(ps_opts.iter().map(|o| o.to_string()));
+        }
+        let res = self.launch(self.command(&args)?, false, Some(id))?;
+        parse_ps_table(&res.stdout)
     }
 
     /// Run the create, start, delete lifecycle of the container and return its exit status
+    ///
+    /// With [`CreateOpts::detach`], runc returns once the container started and `pid` of the
+    /// response is the container's init if a pid file is given. The container keeps the
+    /// pipes of `io`, the caller owns draining them until the container exits.
     pub fn run<P>(&self, id: &str, bundle: P, opts: Option<&CreateOpts>) -> Result<Response>
     where
         P: AsRef<Path>,
     {
+        utils::validate_container_id(id)?;
+        if self.validate_bundles {
+            self.validate_bundle(&bundle)?;
+        }
         let mut args = vec![
             "run".to_string(),
             "--bundle".to_string(),
@@ -290,32 +1011,53 @@ impl Runc {
             args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
-        if let Some(CreateOpts { io: Some(io), .. }) = opts {
-            io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+        let systemd_cgroup = opts.and_then(|o| o.systemd_cgroup);
+        let mut cmd = self.command_with_cgroup(&args, systemd_cgroup)?;
+        if let Some(opts) = opts {
+            preserve_fds(&mut cmd, &opts.extra_files);
+        }
+        let res = match opts {
+            Some(CreateOpts { io: Some(io), .. }) => {
+                io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+                let res = self.launch(cmd, true, Some(id))?;
+                io.close_after_start();
+                res
+            }
+            _ => self.launch(cmd, true, Some(id))?,
         };
-        self.launch(cmd, true)
+        pid_from_file(res, opts.and_then(|o| o.pid_file.as_ref()))
     }
 
     /// Start an already created container
     pub fn start(&self, id: &str) -> Result<Response> {
         let args = ["start".to_string(), id.to_string()];
-        self.launch(self.command(&args)?, true)
+        self.launch(self.command(&args)?, true, Some(id))
+    }
+
+    /// Check the rootfs of a running container is mounted read-only, as requested with
+    /// `root.readonly` in its spec
+    pub fn assert_rootfs_readonly(&self, id: &str) -> Result<()> {
+        let pid = self.state(id)?.pid;
+        let path = format!("/proc/{}/mountinfo", pid);
+        let mountinfo = std::fs::read_to_string(&path).map_err(Error::FileSystemError)?;
+        match utils::mount_readonly(&mountinfo, "/") {
+            Some(true) => Ok(()),
+            _ => Err(Error::RootfsNotReadonly(id.to_string())),
+        }
     }
 
     /// Return the state of a container
     pub fn state(&self, id: &str) -> Result<Container> {
         let args = ["state".to_string(), id.to_string()];
-        let res = self.launch(self.command(&args)?, true)?;
-        serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
+        let res = self.launch(self.command(&args)?, false, Some(id))?;
+        parse_output(&args, &res.output, None)
     }
 
     /// Return the latest statistics for a container
     pub fn stats(&self, id: &str) -> Result<events::Stats> {
         let args = vec!["events".to_string(), "--stats".to_string(), id.to_string()];
-        let res = self.launch(self.command(&args)?, true)?;
-        let event: events::Event =
-            serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)?;
+        let res = self.launch(self.command(&args)?, false, Some(id))?;
+        let event: events::Event = parse_output(&args, &res.output, None)?;
         if let Some(stats) = event.stats {
             Ok(stats)
         } else {
@@ -323,35 +1065,59 @@ impl Runc {
         }
     }
 
+    /// Return the statistics and the processes of a container, only failing if there are no
+    /// statistics, a failure to list the processes is kept in the snapshot
+    pub fn snapshot(&self, id: &str) -> Result<ContainerSnapshot> {
+        let stats = self.stats(id)?;
+        Ok(ContainerSnapshot {
+            stats,
+            processes: self.ps_full(id, &[]),
+        })
+    }
+
     /// Update a container with the provided resource spec
     pub fn update(&self, id: &str, resources: &LinuxResources) -> Result<()> {
-        let (_temp_file, filename) = write_value_to_temp_file(resources)?;
+        let json = serde_json::to_string(resources).map_err(Error::JsonDeserializationFailed)?;
+        self.update_raw(id, &json)
+    }
+
+    /// Update a container with resources given as a JSON object, it's passed to runc as is
+    pub fn update_raw(&self, id: &str, resources: &str) -> Result<()> {
+        check_resources_json(resources)?;
+        let (temp_file, filename) =
+            utils::write_str_to_temp_file(self.spec_dir.as_deref(), resources)?;
         let args = [
             "update".to_string(),
             "--resources".to_string(),
             filename,
             id.to_string(),
         ];
-        self.launch(self.command(&args)?, true)?;
-        Ok(())
+        let res = self
+            .launch(self.command(&args)?, true, Some(id))
+            .map(|_| ());
+        self.cleanup_spec_file(temp_file, res.is_err());
+        res
     }
-}
 
-// a macro tool to cleanup the file with name $filename,
-// there is no async drop in async rust, so we have to call remove_file everytime
-// after a temp file created, before return of a function.
-// with this macro we don't have to write the match case codes everytime.
-#[cfg(feature = "async")]
-macro_rules! tc {
-    ($b:expr, $filename: expr) => {
-        match $b {
-            Ok(r) => r,
-            Err(e) => {
-                let _ = tokio::fs::remove_file($filename).await;
-                return Err(e);
+    /// Remove the temp spec file, unless the command failed and it should be kept for debugging.
+    fn cleanup_spec_file(&self, temp_file: NamedTempFile, failed: bool) {
+        if failed && self.keep_spec_files {
+            match temp_file.keep() {
+                Ok((_, path)) => warn!("keep spec file of failed command: {}", path.display()),
+                Err(e) => warn!("failed to keep spec file: {}", e),
             }
         }
-    };
+    }
+}
+
+/// Hook adjusting each runc command right before it's spawned, see [`GlobalOpts::command_hook`].
+#[derive(Clone)]
+pub(crate) struct CommandHook(pub(crate) Arc<dyn Fn(&mut Command) + Send + Sync>);
+
+impl Debug for CommandHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CommandHook")
+    }
 }
 
 #[cfg(not(feature = "async"))]
@@ -363,34 +1129,139 @@ pub trait Spawner: Debug {
 #[async_trait]
 pub trait Spawner: Debug {
     async fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)>;
-}
 
-/// Async implementation for [Runc].
-///
+    /// Execute `cmd`, failing with [`Error::CommandTimeout`] if it runs for longer than `timeout`.
+    ///
+    /// The default implementation drops the output produced before the timeout.
+    async fn execute_timeout(
+        &self,
+        cmd: Command,
+        timeout: Duration,
+    ) -> Result<(ExitStatus, u32, String, String)>
+    where
+        Self: Sync,
+    {
+        tokio::time::timeout(timeout, self.execute(cmd))
+            .await
+            .map_err(|_| Error::CommandTimeout {
+                subcommand: String::new(),
+                timeout,
+                stdout: String::new(),
+                stderr: String::new(),
+            })?
+    }
+}
+
+/// Async implementation for [Runc].
+///
 /// Note that you MUST use this client on tokio runtime, as this client internally use [`tokio::process::Command`]
 /// and some other utilities.
 #[cfg(feature = "async")]
 impl Runc {
-    async fn launch(&self, cmd: Command, combined_output: bool) -> Result<Response> {
+    async fn launch(
+        &self,
+        mut cmd: Command,
+        combined_output: bool,
+        id: Option<&str>,
+    ) -> Result<Response> {
+        self.apply_command_hook(&mut cmd);
         debug!("Execute command {:?}", cmd);
-        let (status, pid, stdout, stderr) = self.spawner.execute(cmd).await?;
+        // Make sure runc doesn't outlive the command when it's abandoned on timeout.
+        cmd.kill_on_drop(true);
+        // Waiting for a permit doesn't count against the timeout, which only bounds runc.
+        let _permit = match &self.command_limit {
+            Some(limit) => Some(
+                limit
+                    .acquire()
+                    .await
+                    .map_err(|e| Error::Other(Box::new(e)))?,
+            ),
+            None => None,
+        };
+        let _in_flight = InFlight::new(&self.in_flight);
+        let log_offset = self.log_offset();
+        let subcommand = self.subcommand(&cmd);
+        let start = Instant::now();
+        let (status, pid, mut stdout, mut stderr) = match self.timeout {
+            Some(timeout) => {
+                // Spawners don't know which subcommand they run, name it here.
+                let subcommand = subcommand.clone();
+                self.spawner
+                    .execute_timeout(cmd, timeout)
+                    .await
+                    .map_err(|e| match e {
+                        Error::CommandTimeout {
+                            timeout,
+                            mut stdout,
+                            mut stderr,
+                            ..
+                        } => {
+                            truncate_output(&mut stdout, self.max_output_bytes);
+                            truncate_output(&mut stderr, self.max_output_bytes);
+                            Error::CommandTimeout {
+                                subcommand,
+                                timeout,
+                                stdout,
+                                stderr,
+                            }
+                        }
+                        e => e,
+                    })?
+            }
+            None => self.spawner.execute(cmd).await?,
+        };
+        debug!(
+            "runc {} of container {} (pid {}) exited with {} after {:?}",
+            subcommand,
+            id.unwrap_or("-"),
+            pid,
+            status,
+            start.elapsed()
+        );
+        let truncated = truncate_output(&mut stdout, self.max_output_bytes)
+            | truncate_output(&mut stderr, self.max_output_bytes);
         if status.success() {
             let output = if combined_output {
-                stdout + stderr.as_str()
+                stdout.clone() + stderr.as_str()
             } else {
-                stdout
+                stdout.clone()
             };
             Ok(Response {
                 pid,
                 status,
                 output,
-            })
-        } else {
-            Err(Error::CommandFailed {
-                status,
                 stdout,
                 stderr,
+                truncated,
             })
+        } else {
+            Err(self.command_failed(status, stdout, stderr, log_offset))
+        }
+    }
+
+    /// Number of commands of this client and its clones which are running, the ones waiting
+    /// for [`GlobalOpts::max_concurrent_commands`] aren't counted
+    pub fn in_flight_commands(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Return a copy of this client whose commands time out after `timeout`
+    ///
+    /// This is useful to override the configured timeout for slow commands like `checkpoint`
+    /// or `delete --force`. When a command times out, the runc process is killed.
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of this client whose commands wait forever, e.g. to restore a large
+    /// checkpoint
+    pub fn without_timeout(&self) -> Self {
+        Self {
+            timeout: None,
+            ..self.clone()
         }
     }
 
@@ -404,24 +1275,52 @@ impl Runc {
     where
         P: AsRef<Path>,
     {
-        let mut args = vec![
-            "create".to_string(),
-            "--bundle".to_string(),
-            utils::abs_string(bundle)?,
-        ];
+        utils::validate_container_id(id)?;
+        if self.validate_bundles {
+            self.validate_bundle(&bundle)?;
+        }
+        let bundle = utils::abs_string(bundle)?;
+        let mut args = vec!["create".to_string(), "--bundle".to_string(), bundle.clone()];
         if let Some(opts) = opts {
             args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
-        match opts {
+        let systemd_cgroup = opts.and_then(|o| o.systemd_cgroup);
+        let mut cmd = self.command_with_cgroup(&args, systemd_cgroup)?;
+        if let Some(opts) = opts {
+            preserve_fds(&mut cmd, &opts.extra_files);
+        }
+        let res = match opts {
             Some(CreateOpts { io: Some(io), .. }) => {
                 io.set(&mut cmd).map_err(Error::UnavailableIO)?;
-                let res = self.launch(cmd, true).await?;
+                let res = self.launch(cmd, true, Some(id)).await;
                 io.close_after_start();
-                Ok(res)
+                res
+            }
+            _ => self.launch(cmd, true, Some(id)).await,
+        };
+        let pid_file = opts.and_then(|o| o.pid_file.as_ref());
+        let res = match res {
+            Err(e) if opts.map_or(false, |o| o.ignore_existing) && e.is_already_exists() => {
+                return self.existing_created(id, &bundle, pid_file, e).await;
             }
-            _ => self.launch(cmd, true).await,
+            r => r?,
+        };
+        pid_from_file(res, pid_file)
+    }
+
+    /// Accept the container `id` which runc found to exist already if it's still created from
+    /// `bundle`, e.g. by a create which failed afterwards, fail with `err` otherwise.
+    async fn existing_created(
+        &self,
+        id: &str,
+        bundle: &str,
+        pid_file: Option<&PathBuf>,
+        err: Error,
+    ) -> Result<Response> {
+        match self.state(id).await {
+            Ok(c) if is_created_from(&c, bundle) => created_response(&c, pid_file),
+            _ => Err(err),
         }
     }
 
@@ -432,39 +1331,192 @@ impl Runc {
             args.append(&mut opts.args());
         }
         args.push(id.to_string());
-        let _ = self.launch(self.command(&args)?, true).await?;
+        match self.launch(self.command(&args)?, true, Some(id)).await {
+            Err(e)
+                if opts.map_or(false, |o| o.ignore_not_found)
+                    && e.command_error_kind() == CommandErrorKind::NotFound =>
+            {
+                return Ok(())
+            }
+            r => r?,
+        };
+        if opts.map_or(false, |o| o.verify) {
+            self.verify_deleted(id).await?;
+        }
         Ok(())
     }
 
+    /// Delete a container, killing its processes if it isn't stopped
+    ///
+    /// A normal deletion is tried first. If runc refuses because the container isn't stopped,
+    /// all its processes are killed with `SIGKILL` and the deletion is retried with `--force`.
+    pub async fn delete_force(&self, id: &str, opts: &ForceDeleteOpts) -> Result<()> {
+        match self.delete(id, None).await {
+            Err(e) if e.command_error_kind() == CommandErrorKind::NotStopped => {}
+            r => return r,
+        }
+        let kill_opts = KillOpts::new().all(true);
+        if let Err(e) = self.kill(id, libc::SIGKILL as u32, Some(&kill_opts)).await {
+            warn!("failed to kill container {} before deleting it: {}", id, e);
+        }
+        if !self.wait_stopped(id, opts.stop_timeout).await? {
+            warn!(
+                "container {} didn't stop within {:?}",
+                id, opts.stop_timeout
+            );
+        }
+
+        let delete_opts = DeleteOpts::new().force(true);
+        let mut backoff = opts.backoff;
+        let mut attempt = 0;
+        loop {
+            match self.delete(id, Some(&delete_opts)).await {
+                Err(e)
+                    if attempt < opts.retries
+                        && e.command_error_kind() != CommandErrorKind::NotFound =>
+                {
+                    warn!("failed to force delete container {}: {}", id, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                r => return r,
+            }
+        }
+    }
+
+    // Check the container state until it's not found, runc may be removing it in the background.
+    async fn verify_deleted(&self, id: &str) -> Result<()> {
+        for _ in 0..DELETE_VERIFY_ATTEMPTS {
+            match self.state(id).await {
+                Err(e) if e.command_error_kind() == CommandErrorKind::NotFound => return Ok(()),
+                Err(e) => return Err(e),
+                Ok(_) => tokio::time::sleep(STOP_POLL_INTERVAL).await,
+            }
+        }
+        Err(Error::DeleteVerificationFailed(id.to_string()))
+    }
+
     /// Return an event stream of container notifications
     pub async fn events(&self, _id: &str, _interval: &std::time::Duration) -> Result<()> {
         Err(Error::Unimplemented("events".to_string()))
     }
 
-    /// Execute an additional process inside the container
-    pub async fn exec(&self, id: &str, spec: &Process, opts: Option<&ExecOpts>) -> Result<()> {
-        let f = write_value_to_temp_file(spec).await?;
-        let mut args = vec!["exec".to_string(), "--process".to_string(), f.clone()];
-        if let Some(opts) = opts {
-            args.append(&mut tc!(opts.args(), &f));
+    /// Stream the OOM events of a container, ending when the container exits.
+    ///
+    /// `runc events` only notices OOM kills every few seconds, see [`events::watch_oom_kills`]
+    /// to watch the cgroup directly on cgroup v2. The `runc events` process is killed when the
+    /// stream is dropped.
+    pub async fn oom_events(
+        &self,
+        id: &str,
+    ) -> Result<futures::stream::BoxStream<'static, Result<events::Event>>> {
+        utils::validate_container_id(id)?;
+        let args = ["events".to_string(), id.to_string()];
+        let (child, stdout) = self.spawn_events(&args).await?;
+        Ok(keep_alive(events::oom_stream(stdout), child))
+    }
+
+    /// Stream the statistics of a container sampled every `interval`, ending when the
+    /// container exits.
+    ///
+    /// A single `runc events --interval` collects all the samples, instead of one runc per
+    /// sample with [`Runc::stats`]. It's killed when the stream is dropped.
+    ///
+    /// runc takes the interval in milliseconds, a shorter one is rejected.
+    pub async fn stats_stream(
+        &self,
+        id: &str,
+        interval: Duration,
+    ) -> Result<futures::stream::BoxStream<'static, Result<events::Stats>>> {
+        utils::validate_container_id(id)?;
+        if interval < Duration::from_millis(1) {
+            return Err(Error::InvalidInterval(interval));
         }
-        args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
-        match opts {
-            Some(ExecOpts { io: Some(io), .. }) => {
-                tc!(
-                    io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string())),
-                    &f
-                );
-                tc!(self.launch(cmd, true).await, &f);
-                io.close_after_start();
+        let args = [
+            "events".to_string(),
+            "--interval".to_string(),
+            format!("{}ms", interval.as_millis()),
+            id.to_string(),
+        ];
+        let (child, stdout) = self.spawn_events(&args).await?;
+        Ok(keep_alive(events::stats_stream(stdout), child))
+    }
+
+    /// Start `runc events`, which is killed when the returned child is dropped.
+    async fn spawn_events(
+        &self,
+        args: &[String],
+    ) -> Result<(
+        tokio::process::Child,
+        tokio::io::BufReader<tokio::process::ChildStdout>,
+    )> {
+        let mut cmd = self.command(args)?;
+        cmd.stderr(Stdio::null()).kill_on_drop(true);
+        self.apply_command_hook(&mut cmd);
+        debug!("Execute command {:?}", cmd);
+        let mut child = spawn_retrying(|| cmd.spawn()).await?;
+        let stdout = child.stdout.take().ok_or_else(|| {
+            Error::UnavailableIO(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "runc events stdout isn't piped",
+            ))
+        })?;
+        Ok((child, tokio::io::BufReader::new(stdout)))
+    }
+
+    /// Merge the OOM events of a container and the exit of its init `pid`, which is
+    /// registered on `monitor`, into one channel
+    pub async fn events_channel(
+        &self,
+        id: &str,
+        monitor: &monitor::Monitor,
+        pid: u32,
+    ) -> Result<tokio::sync::mpsc::Receiver<monitor::ContainerEvent>> {
+        let oom = self.oom_events(id).await?;
+        Ok(monitor::events_channel(monitor.start(pid), oom))
+    }
+
+    /// Execute an additional process inside the container
+    pub async fn exec(
+        &self,
+        id: &str,
+        spec: &Process,
+        opts: Option<&ExecOpts>,
+    ) -> Result<Response> {
+        check_console_socket(spec, opts)?;
+        let f = write_value_to_temp_file(self.spec_dir.as_deref(), spec).await?;
+        // There is no async drop, every way out of here goes through the cleanup below.
+        let res = async {
+            let mut args = vec!["exec".to_string(), "--process".to_string(), f.clone()];
+            if let Some(opts) = opts {
+                args.append(&mut opts.args()?);
             }
-            _ => {
-                tc!(self.launch(cmd, true).await, &f);
+            args.push(id.to_string());
+            let mut cmd = self.command(&args)?;
+            if let Some(opts) = opts {
+                preserve_fds(&mut cmd, &opts.extra_files);
             }
+            let res = match opts {
+                Some(ExecOpts { io: Some(io), .. }) => {
+                    io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+                    let res = self.launch(cmd, true, Some(id)).await?;
+                    io.close_after_start();
+                    res
+                }
+                _ => self.launch(cmd, true, Some(id)).await?,
+            };
+            pid_from_file(res, opts.and_then(|o| o.pid_file.as_ref()))
         }
-        let _ = tokio::fs::remove_file(&f).await;
-        Ok(())
+        .await;
+        self.cleanup_spec_file(&f, res.is_err()).await;
+        res
+    }
+
+    /// Send a signal given by name, e.g. `SIGTERM` or `TERM`, to processes inside the container
+    pub async fn kill_by_name(&self, id: &str, sig: &str, opts: Option<&KillOpts>) -> Result<()> {
+        let sig: Signal = sig.parse()?;
+        self.kill(id, sig.number(), opts).await
     }
 
     /// Send the specified signal to processes inside the container
@@ -475,40 +1527,176 @@ impl Runc {
         }
         args.push(id.to_string());
         args.push(sig.to_string());
-        let _ = self.launch(self.command(&args)?, true).await?;
-        Ok(())
+        match self.launch(self.command(&args)?, true, Some(id)).await {
+            Err(e)
+                if opts.map_or(true, |o| o.ignore_not_running)
+                    && e.command_error_kind() == CommandErrorKind::NotRunning =>
+            {
+                Ok(())
+            }
+            r => r.map(|_| ()),
+        }
+    }
+
+    /// Return the features supported by the runtime (runc 1.1+)
+    ///
+    /// Returns [Error::Unsupported] if the runc binary doesn't know the `features` command.
+    pub async fn features(&self) -> Result<Features> {
+        let args = ["features".to_string()];
+        let res = self
+            .launch(self.command(&args)?, false, None)
+            .await
+            .map_err(|e| unsupported_command(e, "features"))?;
+        parse_output(&args, &res.output, None)
     }
 
     /// List all containers associated with this runc instance
     pub async fn list(&self) -> Result<Vec<Container>> {
         let args = ["list".to_string(), "--format=json".to_string()];
-        let res = self.launch(self.command(&args)?, true).await?;
-        let output = res.output.trim();
+        let res = self.launch(self.command(&args)?, false, None).await?;
+        parse_output(&args, &res.output, Some(Vec::new()))
+    }
 
-        // Ugly hack to work around golang
-        Ok(if output == "null" {
-            Vec::new()
-        } else {
-            serde_json::from_str(output).map_err(Error::JsonDeserializationFailed)?
-        })
+    /// List the containers matching `filter`, e.g. the running ones
+    pub async fn list_filtered(&self, filter: &ListFilter) -> Result<Vec<Container>> {
+        let mut containers = self.list().await?;
+        containers.retain(|c| filter.matches(c));
+        Ok(containers)
     }
 
     /// Pause a container
     pub async fn pause(&self, id: &str) -> Result<()> {
         let args = ["pause".to_string(), id.to_string()];
-        let _ = self.launch(self.command(&args)?, true).await?;
+        let _ = self.launch(self.command(&args)?, true, Some(id)).await?;
         Ok(())
     }
 
     /// Resume a container
     pub async fn resume(&self, id: &str) -> Result<()> {
         let args = ["resume".to_string(), id.to_string()];
-        let _ = self.launch(self.command(&args)?, true).await?;
+        let _ = self.launch(self.command(&args)?, true, Some(id)).await?;
+        Ok(())
+    }
+
+    /// Gracefully restart a container from the same bundle and return the pid of its new process
+    ///
+    /// The container is sent SIGTERM and given `grace` to stop before being killed with SIGKILL,
+    /// then it's deleted, re-created and started.
+    pub async fn restart<P>(
+        &self,
+        id: &str,
+        bundle: P,
+        create_opts: Option<&CreateOpts>,
+        delete_opts: Option<&DeleteOpts>,
+        grace: Duration,
+    ) -> Result<usize>
+    where
+        P: AsRef<Path>,
+    {
+        self.stop(id, grace).await?;
+        self.delete(id, delete_opts).await?;
+        self.create(id, bundle, create_opts).await?;
+        self.start(id).await?;
+        Ok(self.state(id).await?.pid)
+    }
+
+    // Send SIGTERM to the container and SIGKILL to all its processes if it doesn't stop within grace.
+    async fn stop(&self, id: &str, grace: Duration) -> Result<()> {
+        if self.wait_stopped(id, Duration::ZERO).await? {
+            return Ok(());
+        }
+        self.kill(id, libc::SIGTERM as u32, None).await?;
+        if !self.wait_stopped(id, grace).await? {
+            self.kill(id, libc::SIGKILL as u32, Some(&KillOpts::new().all(true)))
+                .await?;
+            self.wait_stopped(id, grace).await?;
+        }
+        Ok(())
+    }
+
+    // Poll the container state until it's stopped, return false if it's still alive after timeout.
+    async fn wait_stopped(&self, id: &str, timeout: Duration) -> Result<bool> {
+        let state = self
+            .wait_for_status(id, ContainerStatus::Stopped, timeout, STOP_POLL_INTERVAL)
+            .await?;
+        Ok(state.container_status() == ContainerStatus::Stopped)
+    }
+
+    /// Whether the container exists, failures other than runc not finding it are errors
+    pub async fn exists(&self, id: &str) -> Result<bool> {
+        match self.state(id).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.command_error_kind() == CommandErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Poll the state of a container every `interval` until it has `status` or `timeout` passed
+    ///
+    /// The last state is returned in both cases, its status tells whether it was reached.
+    pub async fn wait_for_status(
+        &self,
+        id: &str,
+        status: ContainerStatus,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Container> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let state = self.state(id).await?;
+            if state.container_status() == status || Instant::now() >= deadline {
+                return Ok(state);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Send SIGTERM to the container and SIGKILL it if it's not stopped after `grace`,
+    /// a container that is already gone is treated as terminated
+    pub async fn terminate(&self, id: &str, grace: Duration) -> Result<()> {
+        if self.signal_unless_stopped(id, libc::SIGTERM).await? {
+            return Ok(());
+        }
+        match self.wait_stopped(id, grace).await {
+            Ok(true) => return Ok(()),
+            Err(e) if e.command_error_kind() == CommandErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+            Ok(false) => {}
+        }
+        self.signal_unless_stopped(id, libc::SIGKILL).await?;
         Ok(())
     }
 
-    pub async fn checkpoint(&self) -> Result<()> {
-        Err(Error::Unimplemented("checkpoint".to_string()))
+    // Send a signal to the container, return true if it failed because the container has
+    // already stopped or is gone.
+    async fn signal_unless_stopped(&self, id: &str, sig: libc::c_int) -> Result<bool> {
+        let err = match self.kill(id, sig as u32, None).await {
+            Ok(()) => return Ok(false),
+            Err(e) => e,
+        };
+        match self.state(id).await {
+            Ok(s) if s.container_status() == ContainerStatus::Stopped => Ok(true),
+            Err(e) if e.command_error_kind() == CommandErrorKind::NotFound => Ok(true),
+            _ => Err(err),
+        }
+    }
+
+    /// Checkpoint a running container with criu
+    ///
+    /// The criu in `PATH` is used unless another one is set with [`GlobalOpts::criu`].
+    pub async fn checkpoint(&self, id: &str, opts: Option<&CheckpointOpts>) -> Result<()> {
+        let mut args = vec!["checkpoint".to_string()];
+        if let Some(opts) = opts {
+            args.append(&mut opts.args()?);
+        }
+        args.push(id.to_string());
+        let mut cmd = self.command(&args)?;
+        if let Some(opts) = opts {
+            let files: Vec<_> = opts.status_file.iter().cloned().collect();
+            preserve_fds(&mut cmd, &files);
+        }
+        self.launch(cmd, true, Some(id)).await?;
+        Ok(())
     }
 
     pub async fn restore(&self) -> Result<()> {
@@ -522,22 +1710,40 @@ impl Runc {
             "--format=json".to_string(),
             id.to_string(),
         ];
-        let res = self.launch(self.command(&args)?, true).await?;
-        let output = res.output.trim();
+        let res = self.launch(self.command(&args)?, false, Some(id)).await?;
+        parse_output(&args, &res.output, Some(Vec::new()))
+    }
 
-        // Ugly hack to work around golang
-        Ok(if output == "null" {
-            Vec::new()
+    /// List all the processes inside the container with the columns printed by `ps`,
+    /// `ps_opts` are passed to `ps` and default to `-ef`.
+    pub async fn ps_full(&self, id: &str, ps_opts: &[&str]) -> Result<Vec<ProcessInfo>> {
+        let mut args = vec![
+            "ps".to_string(),
+            "--format=table".to_string(),
+            id.to_string(),
+        ];
+        if ps_opts.is_empty() {
+            args.push("-ef".to_string());
         } else {
-            serde_json::from_str(output).map_err(Error::JsonDeserializationFailed)?
-        })
+            args.extend(ps_opts.iter().map(|o| o.to_string()));
+        }
+        let res = self.launch(self.command(&args)?, false, Some(id)).await?;
+        parse_ps_table(&res.stdout)
     }
 
     /// Run the create, start, delete lifecycle of the container and return its exit status
-    pub async fn run<P>(&self, id: &str, bundle: P, opts: Option<&CreateOpts>) -> Result<()>
+    ///
+    /// With [`CreateOpts::detach`], runc returns once the container started and `pid` of the
+    /// response is the container's init if a pid file is given. The container keeps the
+    /// pipes of `io`, the caller owns draining them until the container exits.
+    pub async fn run<P>(&self, id: &str, bundle: P, opts: Option<&CreateOpts>) -> Result<Response>
     where
         P: AsRef<Path>,
     {
+        utils::validate_container_id(id)?;
+        if self.validate_bundles {
+            self.validate_bundle(&bundle)?;
+        }
         let mut args = vec![
             "run".to_string(),
             "--bundle".to_string(),
@@ -547,34 +1753,102 @@ impl Runc {
             args.append(&mut opts.args()?);
         }
         args.push(id.to_string());
-        let mut cmd = self.command(&args)?;
-        if let Some(CreateOpts { io: Some(io), .. }) = opts {
-            io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+        let systemd_cgroup = opts.and_then(|o| o.systemd_cgroup);
+        let mut cmd = self.command_with_cgroup(&args, systemd_cgroup)?;
+        if let Some(opts) = opts {
+            preserve_fds(&mut cmd, &opts.extra_files);
+        }
+        let res = match opts {
+            Some(CreateOpts { io: Some(io), .. }) => {
+                io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+                let res = self.launch(cmd, true, Some(id)).await?;
+                io.close_after_start();
+                res
+            }
+            _ => self.launch(cmd, true, Some(id)).await?,
         };
-        let _ = self.launch(cmd, true).await?;
-        Ok(())
+        pid_from_file(res, opts.and_then(|o| o.pid_file.as_ref()))
     }
 
     /// Start an already created container
     pub async fn start(&self, id: &str) -> Result<()> {
         let args = vec!["start".to_string(), id.to_string()];
-        let _ = self.launch(self.command(&args)?, true).await?;
+        let _ = self.launch(self.command(&args)?, true, Some(id)).await?;
         Ok(())
     }
 
+    /// Spawn runc with `args` after the global flags, and return without waiting for it
+    ///
+    /// Its stdio is piped unless `io` is given, it's up to the caller to call
+    /// [`Io::close_after_start`](io::Io::close_after_start), see [`Runc::spawn_with_io`].
+    /// The configured timeout doesn't apply, the caller is responsible for waiting on the
+    /// child to reap it.
+    pub async fn spawn(
+        &self,
+        args: &[String],
+        io: Option<&dyn io::Io>,
+    ) -> Result<tokio::process::Child> {
+        let mut cmd = self.command(args)?;
+        if let Some(io) = io {
+            io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+        }
+        self.spawn_raw(cmd).await
+    }
+
+    /// Spawn runc with `args` after the global flags and its stdio set by `io`, and return
+    /// without waiting for it
+    ///
+    /// Unlike with [`Runc::spawn`], the ends of `io` handed to runc are closed on our side
+    /// once it's started, so readers get EOF when runc and the container are done with them.
+    pub async fn spawn_with_io(
+        &self,
+        args: &[String],
+        io: &dyn io::Io,
+    ) -> Result<tokio::process::Child> {
+        let mut cmd = self.command(args)?;
+        io.set(&mut cmd).map_err(|e| Error::IoSet(e.to_string()))?;
+        // The command holds copies of the ends given to runc, gone once it's spawned.
+        let child = self.spawn_raw(cmd).await?;
+        io.close_after_start();
+        Ok(child)
+    }
+
+    /// Spawn `cmd` as is, but for the command hook, retrying while the binary is busy being
+    /// written
+    ///
+    /// As with [`Runc::spawn`], the caller is responsible for reaping the child.
+    pub async fn spawn_raw(&self, mut cmd: Command) -> Result<tokio::process::Child> {
+        self.apply_command_hook(&mut cmd);
+        debug!("Spawn command {:?}", cmd);
+        spawn_retrying(|| cmd.spawn()).await
+    }
+
+    /// Check the rootfs of a running container is mounted read-only, as requested with
+    /// `root.readonly` in its spec
+    pub async fn assert_rootfs_readonly(&self, id: &str) -> Result<()> {
+        let pid = self.state(id).await?.pid;
+        let path = format!("/proc/{}/mountinfo", pid);
+        let mountinfo = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(Error::FileSystemError)?;
+        match utils::mount_readonly(&mountinfo, "/") {
+            Some(true) => Ok(()),
+            _ => Err(Error::RootfsNotReadonly(id.to_string())),
+        }
+    }
+
     /// Return the state of a container
-    pub async fn state(&self, id: &str) -> Result<Vec<usize>> {
+    pub async fn state(&self, id: &str) -> Result<Container> {
         let args = vec!["state".to_string(), id.to_string()];
-        let res = self.launch(self.command(&args)?, true).await?;
-        serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)
+        let res = self.launch(self.command(&args)?, false, Some(id)).await?;
+        parse_output(&args, &res.output, None)
     }
 
     /// Return the latest statistics for a container
     pub async fn stats(&self, id: &str) -> Result<events::Stats> {
         let args = vec!["events".to_string(), "--stats".to_string(), id.to_string()];
-        let res = self.launch(self.command(&args)?, true).await?;
-        let event: events::Event =
-            serde_json::from_str(&res.output).map_err(Error::JsonDeserializationFailed)?;
+        let res = self.launch(self.command(&args)?, false, Some(id)).await?;
+        let event: events::Event = parse_output(&args, &res.output, None)?;
         if let Some(stats) = event.stats {
             Ok(stats)
         } else {
@@ -582,28 +1856,94 @@ impl Runc {
         }
     }
 
+    /// Return the latest statistics for each of the containers, collected concurrently.
+    ///
+    /// Results are in the order of `ids`, a failure for one container doesn't affect the others.
+    pub async fn stats_many(&self, ids: &[&str]) -> Vec<(String, Result<events::Stats>)> {
+        futures::stream::iter(ids)
+            .map(|id| async move { (id.to_string(), self.stats(id).await) })
+            .buffered(STATS_CONCURRENCY)
+            .collect()
+            .await
+    }
+
+    /// Return the statistics and the processes of a container, only failing if there are no
+    /// statistics, a failure to list the processes is kept in the snapshot
+    ///
+    /// Both are collected at the same time.
+    pub async fn snapshot(&self, id: &str) -> Result<ContainerSnapshot> {
+        let (stats, processes) = tokio::join!(self.stats(id), self.ps_full(id, &[]));
+        Ok(ContainerSnapshot {
+            stats: stats?,
+            processes,
+        })
+    }
+
     /// Update a container with the provided resource spec
     pub async fn update(&self, id: &str, resources: &LinuxResources) -> Result<()> {
-        let f = write_value_to_temp_file(resources).await?;
+        let json = serde_json::to_string(resources).map_err(Error::JsonDeserializationFailed)?;
+        self.update_raw(id, &json).await
+    }
+
+    /// Update a container with resources given as a JSON object, it's passed to runc as is
+    pub async fn update_raw(&self, id: &str, resources: &str) -> Result<()> {
+        check_resources_json(resources)?;
+        let f = utils::write_str_to_temp_file(self.spec_dir.as_deref(), resources).await?;
         let args = [
             "update".to_string(),
             "--resources".to_string(),
             f.to_string(),
             id.to_string(),
         ];
-        let _ = tc!(self.launch(self.command(&args)?, true).await, &f);
-        let _ = tokio::fs::remove_file(&f).await;
-        Ok(())
+        let res = match self.command(&args) {
+            Ok(cmd) => self.launch(cmd, true, Some(id)).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        self.cleanup_spec_file(&f, res.is_err()).await;
+        res
+    }
+
+    /// Remove the temp spec file, unless the command failed and it should be kept for debugging.
+    async fn cleanup_spec_file(&self, filename: &str, failed: bool) {
+        if failed && self.keep_spec_files {
+            warn!("keep spec file of failed command: {}", filename);
+        } else {
+            let _ = tokio::fs::remove_file(filename).await;
+        }
+    }
+}
+
+/// Shell snippets shared by the fake runc scripts of the sync and async tests.
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod test_scripts {
+    /// Print the state of `fake-id` with `status`, which is expanded by the shell, e.g.
+    /// `$status` or `$(cat file)`.
+    pub(crate) fn echo_state(status: &str) -> String {
+        format!(
+            r#"echo '{{"id":"fake-id","pid":100,"status":"'{}'","bundle":"/bundle","rootfs":"/rootfs","created":0,"annotations":{{}}}}'"#,
+            status
+        )
+    }
+
+    /// Run `action` with `$a` set to the value of each argument following one of `flags`,
+    /// e.g. `--process|--resources`.
+    pub(crate) fn on_flag(flags: &str, action: &str) -> String {
+        format!(
+            "prev=\nfor a; do\n    case \"$prev\" in {}) {} ;; esac\n    prev=$a\ndone",
+            flags, action
+        )
     }
 }
 
 #[cfg(test)]
 #[cfg(all(target_os = "linux", not(feature = "async")))]
 mod tests {
-    use std::sync::Arc;
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path, sync::Arc};
 
     use super::{
         io::{InheritedStdIo, PipedStdIo},
+        test_scripts::{echo_state, on_flag},
         *,
     };
 
@@ -628,6 +1968,16 @@ mod tests {
             .expect("unable to create runc instance")
     }
 
+    /// Create a client running a shell script in place of the runc binary.
+    fn fake_client(opts: GlobalOpts, dir: &Path, script: &str) -> Runc {
+        let path = dir.join("runc");
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        opts.command(path)
+            .build()
+            .expect("unable to create runc instance")
+    }
+
     fn dummy_process() -> Process {
         serde_json::from_str(
             "
@@ -660,6 +2010,7 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -689,6 +2040,7 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -700,6 +2052,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_run_detach() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls");
+        // The container outlives runc and writes once runc exited.
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"echo "$*" > {}
+while [ $# -gt 0 ]; do
+    [ "$1" = --pid-file ] && echo 4242 > "$2"
+    shift
+done
+(sleep 1; echo hello) &"#,
+                calls.display()
+            ),
+        );
+        let io = Arc::new(
+            io::PipedIo::new(
+                nix::unistd::getuid().as_raw(),
+                nix::unistd::getgid().as_raw(),
+                &io::IOOption {
+                    open_stdin: false,
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        );
+        let opts = CreateOpts::new()
+            .io(io.clone())
+            .pid_file(dir.path().join("pid"))
+            .detach(true);
+
+        let start = std::time::Instant::now();
+        let res = runc.run("fake-id", "fake-bundle", Some(&opts)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(res.pid, 4242);
+        assert!(fs::read_to_string(&calls)
+            .unwrap()
+            .contains("--detach fake-id"));
+        // Ends with EOF once the container exits, runc's pipe ends are closed.
+        let stdout = io.copy_stdout(Vec::new()).unwrap();
+        assert_eq!(stdout.join().unwrap().unwrap(), b"hello\n");
+    }
+
     #[test]
     fn test_exec() {
         let opts = ExecOpts::new();
@@ -717,6 +2115,7 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -744,6 +2143,7 @@ mod tests {
                 status,
                 stdout,
                 stderr,
+                ..
             }) => {
                 if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                     eprintln!("fail_runc succeeded.");
@@ -755,64 +2155,2019 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_output() {
-        // test create cmd with inherit Io, expect empty cmd output
-        let mut opts = CreateOpts::new();
-        opts.io = Some(Arc::new(InheritedStdIo::new().unwrap()));
-        let echo_runc = echo_client();
-        let response = echo_runc
-            .create("fake-id", "fake-bundle", Some(&opts))
-            .expect("echo failed.");
-        assert_ne!(response.pid, 0);
-        assert!(response.status.success());
-        assert!(response.output.is_empty());
-
-        // test create cmd with pipe Io, expect nonempty cmd output
-        let mut opts = CreateOpts::new();
-        opts.io = Some(Arc::new(PipedStdIo::new().unwrap()));
-        let echo_runc = echo_client();
-        let response = echo_runc
-            .create("fake-id", "fake-bundle", Some(&opts))
-            .expect("echo failed.");
-        assert_ne!(response.pid, 0);
-        assert!(response.status.success());
-        assert!(!response.output.is_empty());
+    /// Fake runtime which records the subcommands it's called with.
+    ///
+    /// The container is reported running until it's killed, and running again once restarted.
+    #[derive(Debug, Default)]
+    struct RecordingSpawner {
+        calls: std::sync::Mutex<Vec<String>>,
     }
-}
 
-/// Tokio tests
-#[cfg(test)]
-#[cfg(all(target_os = "linux", feature = "async"))]
-mod tests {
-    use std::sync::Arc;
+    impl Spawner for RecordingSpawner {
+        fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
+            use std::os::unix::process::ExitStatusExt;
 
-    use super::{
-        io::{InheritedStdIo, PipedStdIo},
-        *,
-    };
+            // Skip the global options, "--log-format text".
+            let subcommand = cmd.get_args().nth(2).unwrap().to_string_lossy().to_string();
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(subcommand.clone());
+            let stdout = if subcommand == "state" {
+                let called = |c: &str| calls.iter().any(|x| x == c);
+                let (pid, status) = if called("start") {
+                    (200, "running")
+                } else if called("kill") {
+                    (100, "stopped")
+                } else {
+                    (100, "running")
+                };
+                format!(
+                    r#"{{"id":"fake-id","pid":{},"status":"{}","bundle":"/bundle","rootfs":"/rootfs","created":0,"annotations":{{}}}}"#,
+                    pid, status
+                )
+            } else {
+                String::new()
+            };
+            Ok((ExitStatus::from_raw(0), 1, stdout, String::new()))
+        }
+    }
 
-    fn ok_client() -> Runc {
-        GlobalOpts::new()
-            .command("/bin/true")
-            .build()
-            .expect("unable to create runc instance")
+    #[test]
+    fn test_restart() {
+        let spawner = Arc::new(RecordingSpawner::default());
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(spawner.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        let pid = runc
+            .restart("fake-id", "fake-bundle", None, None, Duration::from_secs(1))
+            .expect("restart failed.");
+        assert_eq!(pid, 200);
+        assert_eq!(
+            *spawner.calls.lock().unwrap(),
+            vec!["state", "kill", "state", "delete", "create", "start", "state"]
+        );
     }
 
-    fn fail_client() -> Runc {
-        GlobalOpts::new()
-            .command("/bin/false")
-            .build()
-            .expect("unable to create runc instance")
+    #[test]
+    fn test_pause_resume() {
+        let spawner = Arc::new(RecordingSpawner::default());
+        let mut opts = GlobalOpts::new().command("/bin/true");
+        opts.custom_spawner(spawner.clone());
+        let runc = opts.build().expect("unable to create runc instance");
+
+        runc.pause("fake-id").expect("pause failed.");
+        runc.resume("fake-id").expect("resume failed.");
+        assert_eq!(*spawner.calls.lock().unwrap(), vec!["pause", "resume"]);
     }
 
-    fn echo_client() -> Runc {
-        GlobalOpts::new()
+    #[test]
+    fn test_unsupported_command() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let failed = |stderr: &str| Error::CommandFailed {
+            status: ExitStatus::from_raw(3 << 8),
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+            log_messages: vec![],
+        };
+        match unsupported_command(failed("No help topic for 'features'"), "features") {
+            Error::Unsupported(cmd) => assert_eq!(cmd, "features"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match unsupported_command(failed("runc: unknown command \"features\""), "features") {
+            Error::Unsupported(cmd) => assert_eq!(cmd, "features"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        match unsupported_command(failed("permission denied"), "features") {
+            Error::CommandFailed { .. } => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        match fail_client().features() {
+            Err(Error::CommandFailed { .. }) => {}
+            r => panic!("unexpected result from fail_runc: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_features_from_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"cat <<EOF
+{"ociVersionMin":"1.0.0","ociVersionMax":"1.1.0-rc.1","linux":{"namespaces":["cgroup","ipc","mount","network","pid","user","uts"],"cgroup":{"v1":true,"v2":true,"systemd":true}},"annotations":{"org.opencontainers.runc.version":"1.1.4"}}
+EOF"#;
+        let runc = fake_client(GlobalOpts::new(), dir.path(), script);
+        let features = runc.features().unwrap();
+        assert_eq!(features.oci_version_max.as_deref(), Some("1.1.0-rc.1"));
+        let linux = features.linux.unwrap();
+        assert!(linux.namespaces.unwrap().contains(&"user".to_string()));
+        assert_eq!(linux.cgroup.unwrap().v2, Some(true));
+
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"echo 'No help topic for "features"' >&2; exit 3"#,
+        );
+        assert!(matches!(runc.features(), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_rlimit_nofile() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(
+            GlobalOpts::new().rlimit_nofile(256, 512),
+            dir.path(),
+            "ulimit -Sn; ulimit -Hn",
+        );
+        let response = runc.start("fake-id").expect("fake runc failed.");
+        assert_eq!(response.output, "256\n512\n");
+    }
+
+    #[test]
+    fn test_separate_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "echo out; echo err >&2");
+        let response = runc.start("fake-id").expect("fake runc failed.");
+        assert_eq!(response.stdout, "out\n");
+        assert_eq!(response.stderr, "err\n");
+        assert_eq!(response.output, "out\nerr\n");
+    }
+
+    #[test]
+    fn test_delete_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("state-called");
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"case "$*" in
+*delete*) exit 0 ;;
+esac
+if [ -e {marker} ]; then echo "container fake-id does not exist" >&2; exit 1; fi
+touch {marker}
+{state}"#,
+                state = echo_state("stopped"),
+                marker = marker.display()
+            ),
+        );
+        let opts = DeleteOpts::new().verify(true);
+        runc.delete("fake-id", Some(&opts)).unwrap();
+        assert!(marker.exists());
+
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                "case \"$*\" in\n*delete*) exit 0 ;;\nesac\n{}",
+                echo_state("stopped")
+            ),
+        );
+        match runc.delete("fake-id", Some(&opts)) {
+            Err(Error::DeleteVerificationFailed(id)) => assert_eq!(id, "fake-id"),
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+        // Not verified unless asked.
+        runc.delete("fake-id", None).unwrap();
+    }
+
+    #[test]
+    fn test_delete_ignore_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"echo "container fake-id does not exist" >&2; exit 1"#,
+        );
+        let opts = DeleteOpts::new().force(true);
+        match runc.delete("fake-id", Some(&opts)) {
+            Err(Error::CommandFailed { .. }) => {}
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+        runc.delete("fake-id", Some(&opts.clone().ignore_not_found(true)))
+            .unwrap();
+
+        // crun words it differently.
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"echo "error opening file \`/run/crun/fake-id/status\`: No such file or directory" >&2; exit 1"#,
+        );
+        runc.delete("fake-id", Some(&opts.clone().ignore_not_found(true)))
+            .unwrap();
+
+        // Other failures are still reported.
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "exit 1");
+        let opts = DeleteOpts::new().ignore_not_found(true);
+        assert!(runc.delete("fake-id", Some(&opts)).is_err());
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"echo "cannot delete container fake-id that is not stopped: running" >&2; exit 1"#,
+        );
+        match runc.delete("fake-id", Some(&opts)) {
+            Err(e) => assert_eq!(e.command_error_kind(), CommandErrorKind::NotStopped),
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_delete_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls");
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"echo "$*" >> {calls}
+case "$*" in
+*"delete --force"*) exit 0 ;;
+*delete*) echo "cannot delete container fake-id that is not stopped: running" >&2; exit 1 ;;
+*state*) {state} ;;
+esac"#,
+                state = echo_state("stopped"),
+                calls = calls.display()
+            ),
+        );
+        runc.delete_force("fake-id", &ForceDeleteOpts::new())
+            .unwrap();
+        let log = fs::read_to_string(&calls).unwrap();
+        let log: Vec<_> = log.lines().collect();
+        assert_eq!(log.len(), 4);
+        assert!(log[0].ends_with("delete fake-id"));
+        assert!(log[1].ends_with("kill --all fake-id 9"));
+        assert!(log[2].ends_with("state fake-id"));
+        assert!(log[3].ends_with("delete --force fake-id"));
+    }
+
+    #[test]
+    fn test_kill_not_running() {
+        let dir = tempfile::tempdir().unwrap();
+        for stderr in [
+            "container not running",
+            "os: process already finished",
+            "kill container: No such process",
+        ] {
+            let script = format!("echo '{}' >&2; exit 1", stderr);
+            let runc = fake_client(GlobalOpts::new(), dir.path(), &script);
+            runc.kill("fake-id", 15, None).unwrap();
+            runc.kill("fake-id", 15, Some(&KillOpts::new().all(true)))
+                .unwrap();
+            match runc.kill(
+                "fake-id",
+                15,
+                Some(&KillOpts::new().ignore_not_running(false)),
+            ) {
+                Err(e) => assert_eq!(e.command_error_kind(), CommandErrorKind::NotRunning),
+                r => panic!("unexpected result for {:?}: {:?}", stderr, r),
+            }
+        }
+
+        // Other failures are still reported.
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "exit 1");
+        assert!(runc.kill("fake-id", 15, None).is_err());
+    }
+
+    #[test]
+    fn test_kill_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "echo \"$*\" >&2; exit 1");
+        let opts = KillOpts::new().all(true);
+        match runc.kill_by_name("fake-id", "TERM", Some(&opts)) {
+            Err(Error::CommandFailed { stderr, .. }) => {
+                assert!(stderr.trim_end().ends_with("kill --all fake-id 15"))
+            }
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+        match runc.kill_by_name("fake-id", "SIGNOPE", None) {
+            Err(Error::InvalidSignal(s)) => assert_eq!(s, "SIGNOPE"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_exists_wait_for_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let count = dir.path().join("count");
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"n=$(cat {count} 2>/dev/null || echo 0)
+echo $((n + 1)) > {count}
+case "$*" in
+*missing*) echo "container missing does not exist" >&2; exit 1 ;;
+*broken*) echo "permission denied" >&2; exit 1 ;;
+esac
+status=created
+[ "$n" -ge 2 ] && status=running
+{state}"#,
+                state = echo_state("$status"),
+                count = count.display()
+            ),
+        );
+        assert!(!runc.exists("missing").unwrap());
+        assert!(runc.exists("broken").is_err());
+        fs::remove_file(&count).unwrap();
+
+        // Running from the third state on.
+        let interval = Duration::from_millis(10);
+        let state = runc
+            .wait_for_status(
+                "fake-id",
+                ContainerStatus::Running,
+                Duration::from_secs(5),
+                interval,
+            )
+            .unwrap();
+        assert_eq!(state.container_status(), ContainerStatus::Running);
+        assert_eq!(fs::read_to_string(&count).unwrap().trim(), "3");
+
+        // Never reached, the last state is returned at the deadline.
+        let state = runc
+            .wait_for_status(
+                "fake-id",
+                ContainerStatus::Paused,
+                Duration::from_millis(100),
+                interval,
+            )
+            .unwrap();
+        assert_eq!(state.container_status(), ContainerStatus::Running);
+        assert!(runc.exists("fake-id").unwrap());
+    }
+
+    #[test]
+    fn test_spawn_retrying() {
+        let text_busy = || std::io::Error::from_raw_os_error(libc::ETXTBSY);
+
+        let mut attempts = 0;
+        let res = spawn_retrying(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(text_busy())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(res.unwrap(), 3);
+
+        // Bounded.
+        let mut attempts = 0;
+        let res: Result<()> = spawn_retrying(|| {
+            attempts += 1;
+            Err(text_busy())
+        });
+        assert!(matches!(res, Err(Error::ProcessSpawnFailed(_))));
+        assert_eq!(attempts, SPAWN_ATTEMPTS);
+
+        // Other errors aren't retried.
+        let mut attempts = 0;
+        let res: Result<()> = spawn_retrying(|| {
+            attempts += 1;
+            Err(std::io::ErrorKind::NotFound.into())
+        });
+        assert!(matches!(res, Err(Error::ProcessSpawnFailed(_))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_validate_bundles() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = tempfile::tempdir().unwrap();
+        let mut spec = serde_json::to_value(specs::minimal_spec(&["true"], "rootfs")).unwrap();
+        spec["process"]["args"] = serde_json::json!([]);
+        fs::write(bundle.path().join("config.json"), spec.to_string()).unwrap();
+
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "exit 0");
+        runc.create("fake-id", bundle.path(), None).unwrap();
+
+        let runc = fake_client(
+            GlobalOpts::new().validate_bundles(true),
+            dir.path(),
+            "exit 0",
+        );
+        match runc.create("fake-id", bundle.path(), None) {
+            Err(Error::InvalidSpec(msg)) => assert!(msg.contains("process.args")),
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+        assert!(runc.run("fake-id", bundle.path(), None).is_err());
+    }
+
+    #[test]
+    fn test_clone_same_argv() {
+        let dir = tempfile::tempdir().unwrap();
+        let argv = dir.path().join("argv");
+        let opts = GlobalOpts::new().root("/run/test").debug(true);
+        let runc = fake_client(
+            opts,
+            dir.path(),
+            &format!("echo \"$@\" >> {}\necho '[]'", argv.display()),
+        );
+        let cloned = runc.clone();
+
+        runc.list().unwrap();
+        cloned.list().unwrap();
+        let content = fs::read_to_string(&argv).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], lines[1]);
+        assert!(
+            lines[0].starts_with("--root /run/test --debug"),
+            "{}",
+            lines[0]
+        );
+    }
+
+    #[test]
+    fn test_json_output_ignores_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        let warn = "echo 'level=warning msg=\"unable to get oom kill count\"' >&2";
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!("{}\necho '[]'", warn),
+        );
+        assert!(runc.list().unwrap().is_empty());
+
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"{}
+{state}"#,
+                warn,
+                state = echo_state("running")
+            ),
+        );
+        assert_eq!(runc.state("fake-id").unwrap().pid, 100);
+    }
+
+    #[test]
+    fn test_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = on_flag(
+            "--resources",
+            &format!(
+                r#"cp "$a" {dir}/resources.json; echo "$a" > {dir}/path"#,
+                dir = dir.path().display()
+            ),
+        );
+        let runc = fake_client(GlobalOpts::new(), dir.path(), &script);
+        let resources: LinuxResources =
+            serde_json::from_str(r#"{"memory": {"limit": 268435456}, "cpu": {"quota": 50000}}"#)
+                .unwrap();
+        runc.update("fake-id", &resources)
+            .expect("fake runc failed.");
+
+        let written: LinuxResources =
+            serde_json::from_slice(&fs::read(dir.path().join("resources.json")).unwrap()).unwrap();
+        assert_eq!(written.memory().as_ref().unwrap().limit(), Some(268435456));
+        assert_eq!(written.cpu().as_ref().unwrap().quota(), Some(50000));
+        // The temp file is removed once the command returns.
+        let path = fs::read_to_string(dir.path().join("path")).unwrap();
+        assert!(!Path::new(path.trim()).exists());
+    }
+
+    #[test]
+    fn test_command_failed_log_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log.json");
+        fs::write(&log, "{\"level\":\"error\",\"msg\":\"stale\"}\n").unwrap();
+        let script = format!(
+            r#"echo '{{"level":"error","msg":"container with id exists: fake-id"}}' >> {}
+exit 1"#,
+            log.display()
+        );
+        let runc = fake_client(GlobalOpts::new().log(&log).log_json(), dir.path(), &script);
+        match runc.create("fake-id", "fake-bundle", None) {
+            Err(Error::CommandFailed { log_messages, .. }) => {
+                assert_eq!(log_messages.len(), 1);
+                assert_eq!(log_messages[0].msg, "container with id exists: fake-id");
+            }
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+
+        let log_entries = runc.read_log();
+        assert_eq!(log_entries.len(), 2);
+        assert_eq!(log_entries[0].msg, "stale");
+
+        // Without a JSON log, nothing is collected.
+        let runc = fake_client(GlobalOpts::new().log(&log), dir.path(), &script);
+        assert!(runc.read_log().is_empty());
+        match runc.create("fake-id", "fake-bundle", None) {
+            Err(Error::CommandFailed { log_messages, .. }) => assert!(log_messages.is_empty()),
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_keep_spec_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_file = dir.path().join("path");
+        let script = format!(
+            "{}\nexit 1",
+            on_flag(
+                "--process",
+                &format!(r#"echo "$a" > {}"#, path_file.display()),
+            )
+        );
+        for keep in [true, false] {
+            let runc = fake_client(GlobalOpts::new().keep_spec_files(keep), dir.path(), &script);
+            runc.exec("fake-id", &dummy_process(), None).unwrap_err();
+            let spec = PathBuf::from(fs::read_to_string(&path_file).unwrap().trim());
+            assert_eq!(spec.exists(), keep);
+            let _ = fs::remove_file(spec);
+        }
+    }
+
+    #[test]
+    fn test_spec_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec_dir = dir.path().join("specs");
+        let path_file = dir.path().join("path");
+        let script = on_flag(
+            "--process",
+            &format!(r#"echo "$a" > {}"#, path_file.display()),
+        );
+        let runc = fake_client(GlobalOpts::new().spec_dir(&spec_dir), dir.path(), &script);
+        runc.exec("fake-id", &dummy_process(), None).unwrap();
+        let spec = PathBuf::from(fs::read_to_string(&path_file).unwrap().trim());
+        assert_eq!(spec.parent().unwrap(), spec_dir);
+        let mode = fs::metadata(&spec_dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let runc = fake_client(GlobalOpts::new().spec_dir(&file), dir.path(), &script);
+        match runc.exec("fake-id", &dummy_process(), None) {
+            Err(Error::SpecFileCreationFailed { path, .. }) => assert_eq!(path, file),
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_systemd_cgroup_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let cases = [
+            (true, None, true),
+            (true, Some(false), false),
+            (false, Some(true), true),
+            (false, None, false),
+        ];
+        for (global, call, expected) in cases {
+            let opts = GlobalOpts::new().rootless(false).systemd_cgroup(global);
+            let runc = fake_client(opts, dir.path(), r#"echo "$@""#);
+            let opts = CreateOpts::new().systemd_cgroup(call);
+            let res = runc.create("fake-id", "fake-bundle", Some(&opts)).unwrap();
+            let argv: Vec<&str> = res.stdout.split_whitespace().collect();
+            assert_eq!(
+                argv.iter().filter(|a| **a == "--systemd-cgroup").count(),
+                expected as usize,
+                "global {}, override {:?}: {:?}",
+                global,
+                call,
+                argv
+            );
+            let subcommand = argv.iter().position(|a| *a == "create").unwrap();
+            assert!(argv[subcommand..].iter().all(|a| *a != "--systemd-cgroup"));
+
+            let res = runc.run("fake-id", "fake-bundle", Some(&opts)).unwrap();
+            assert_eq!(res.stdout.contains("--systemd-cgroup"), expected);
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_status_fd() {
+        use std::{io::Read, os::unix::io::FromRawFd};
+
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"echo "$@"; echo ready >&3"#,
+        );
+        // CLOEXEC keeps other tests' children from holding the write end open.
+        let (rd, wr) = nix::unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC).unwrap();
+        // Safe because both fds are fresh and owned by nobody else.
+        let (mut rd, wr) = unsafe { (File::from_raw_fd(rd), File::from_raw_fd(wr)) };
+
+        let opts = CheckpointOpts::new().lazy_pages(true).status_file(wr);
+        runc.checkpoint("fake-id", Some(&opts)).unwrap();
+        drop(opts);
+
+        let mut status = String::new();
+        rd.read_to_string(&mut status).unwrap();
+        assert_eq!(status, "ready\n");
+    }
+
+    #[test]
+    fn test_exec_terminal_needs_console_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "");
+        let mut process = dummy_process();
+        process.set_terminal(Some(true));
+
+        match runc.exec("fake-id", &process, None) {
+            Err(Error::MissingConsoleSocket) => {}
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+        match runc.exec("fake-id", &process, Some(&ExecOpts::new().detach(true))) {
+            Err(Error::MissingConsoleSocket) => {}
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+        let opts = ExecOpts::new().console_socket(dir.path().join("console.sock"));
+        runc.exec("fake-id", &process, Some(&opts)).unwrap();
+        runc.exec("fake-id", &dummy_process(), None).unwrap();
+    }
+
+    #[test]
+    fn test_terminate() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls");
+        let script = |status: &str| {
+            format!(
+                r#"echo "$*" >> {calls}
+case "$*" in
+*gone*) echo "container gone does not exist" >&2; exit 1 ;;
+*"kill fake-id 15"*) exit 0 ;;
+*"kill fake-id 9"*) exit 0 ;;
+*state*) {state} ;;
+esac"#,
+                calls = calls.display(),
+                state = echo_state(status)
+            )
+        };
+
+        // stops after SIGTERM, no SIGKILL
+        let runc = fake_client(GlobalOpts::new(), dir.path(), &script("stopped"));
+        runc.terminate("fake-id", Duration::from_secs(1)).unwrap();
+        let log = fs::read_to_string(&calls).unwrap();
+        let log: Vec<_> = log.lines().collect();
+        assert_eq!(log.len(), 2);
+        assert!(log[0].ends_with("kill fake-id 15"));
+        assert!(log[1].ends_with("state fake-id"));
+
+        // ignores SIGTERM, killed after the grace period
+        fs::remove_file(&calls).unwrap();
+        let runc = fake_client(GlobalOpts::new(), dir.path(), &script("running"));
+        runc.terminate("fake-id", Duration::from_millis(200))
+            .unwrap();
+        let log = fs::read_to_string(&calls).unwrap();
+        let log: Vec<_> = log.lines().collect();
+        assert!(log.len() > 2);
+        assert!(log[0].ends_with("kill fake-id 15"));
+        assert!(log[log.len() - 1].ends_with("kill fake-id 9"));
+
+        // already gone
+        runc.terminate("gone", Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn test_max_output_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let max = 1 << 20;
+        let opts = GlobalOpts::new().max_output_bytes(max);
+        let runc = fake_client(opts, dir.path(), "yes | head -c 50000000");
+        let res = runc.run("fake-id", "fake-bundle", None).unwrap();
+        assert!(res.truncated);
+        assert_eq!(res.stdout.len(), max);
+        assert!(res.stdout.starts_with("y\ny\n"));
+
+        let opts = GlobalOpts::new().max_output_bytes(max);
+        let runc = fake_client(opts, dir.path(), "yes | head -c 50000000 >&2; exit 1");
+        match runc.run("fake-id", "fake-bundle", None) {
+            Err(Error::CommandFailed { stderr, .. }) => assert_eq!(stderr.len(), max),
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+
+        let mut output = "aé".to_string();
+        assert!(truncate_output(&mut output, 2));
+        assert_eq!(output, "a");
+        assert!(!truncate_output(&mut output, 2));
+    }
+
+    #[test]
+    fn test_update_raw() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("resources.json");
+        let script = on_flag("--resources", &format!(r#"cp "$a" {}"#, out.display()));
+        let runc = fake_client(GlobalOpts::new(), dir.path(), &script);
+        let resources = r#"{"memory": {"limit": 268435456}}"#;
+        runc.update_raw("fake-id", resources).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), resources);
+
+        fs::remove_file(&out).unwrap();
+        for bad in ["{\"memory\":", "[]"] {
+            assert!(matches!(
+                runc.update_raw("fake-id", bad),
+                Err(Error::JsonDeserializationFailed(_))
+            ));
+        }
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn test_spec_file_removed_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = format!(
+            "{}\nexit 1",
+            on_flag(
+                "--process|--resources",
+                &format!(
+                    r#"echo "$a" > {dir}/path; stat -c %a "$a" > {dir}/mode"#,
+                    dir = dir.path().display()
+                ),
+            )
+        );
+        let runc = fake_client(GlobalOpts::new(), dir.path(), &script);
+        for _ in 0..3 {
+            runc.exec("fake-id", &dummy_process(), None).unwrap_err();
+            let spec = PathBuf::from(fs::read_to_string(dir.path().join("path")).unwrap().trim());
+            assert!(!spec.exists());
+            assert_eq!(
+                fs::read_to_string(dir.path().join("mode")).unwrap().trim(),
+                "600"
+            );
+
+            runc.update_raw("fake-id", "{}").unwrap_err();
+            let spec = PathBuf::from(fs::read_to_string(dir.path().join("path")).unwrap().trim());
+            assert!(!spec.exists());
+        }
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let fail = dir.path().join("ps-fails");
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"case "$*" in
+*events*) echo '{{"type":"stats","id":"fake-id","data":{{"cpu":{{"usage":{{"total":42}}}},"memory":{{}},"pids":{{}},"blkio":{{}}}}}}' ;;
+*"ps --format=table fake-id -ef"*)
+    [ -e {fail} ] && {{ echo "ps failed" >&2; exit 1; }}
+    printf 'UID PID PPID C STIME TTY TIME CMD\nroot 1 0 0 10:00 ? 00:00:00 sleep 100\n' ;;
+esac"#,
+                fail = fail.display()
+            ),
+        );
+        let snapshot = runc.snapshot("fake-id").unwrap();
+        assert_eq!(snapshot.stats.cpu_usage_nanos(), Some(42));
+        let processes = snapshot.processes.unwrap();
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].pid, 1);
+        assert_eq!(processes[0].cmd, "sleep 100");
+
+        // The stats are kept when ps fails.
+        fs::write(&fail, "").unwrap();
+        let snapshot = runc.snapshot("fake-id").unwrap();
+        assert_eq!(snapshot.stats.cpu_usage_nanos(), Some(42));
+        assert!(matches!(
+            snapshot.processes,
+            Err(Error::CommandFailed { .. })
+        ));
+
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "exit 1");
+        assert!(runc.snapshot("fake-id").is_err());
+    }
+
+    #[test]
+    fn test_command_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        // The hook runs after the client cleared the environment.
+        let opts = GlobalOpts::new().env_clear(true).command_hook(|cmd| {
+            cmd.env("RUNC_HOOKED", "1");
+        });
+        let runc = fake_client(opts, dir.path(), "env");
+        let res = runc.start("fake-id").unwrap();
+        assert!(res.stdout.lines().any(|l| l == "RUNC_HOOKED=1"));
+    }
+
+    #[test]
+    fn test_command_args() {
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .root("/run/fake")
+            .log("/tmp/fake.json")
+            .log_json()
+            .systemd_cgroup(true)
+            .rootless(false)
+            .build()
+            .unwrap();
+        let argv = |systemd_cgroup| {
+            let args = ["state".to_string(), "fake-id".to_string()];
+            let cmd = runc.command_with_cgroup(&args, systemd_cgroup).unwrap();
+            cmd.get_args()
+                .map(|a| a.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let global = "--root /run/fake --log /tmp/fake.json --log-format json";
+        assert_eq!(
+            argv(None),
+            format!("{} --systemd-cgroup --rootless=false state fake-id", global)
+        );
+        assert_eq!(
+            argv(Some(true)),
+            format!("{} --rootless=false --systemd-cgroup state fake-id", global)
+        );
+        assert_eq!(
+            argv(Some(false)),
+            format!("{} --rootless=false state fake-id", global)
+        );
+        // The override doesn't change the client's own arguments.
+        assert!(runc.args.iter().any(|a| a == SYSTEMD_CGROUP));
+    }
+
+    #[test]
+    fn test_subcommand() {
+        // Whatever follows the global options is the subcommand, even one unknown to the client.
+        let runc = GlobalOpts::new()
+            .command("/bin/true")
+            .root("/run/fake")
+            .log("/tmp/fake.json")
+            .systemd_cgroup(true)
+            .build()
+            .unwrap();
+        let args = ["new-subcommand".to_string(), "fake-id".to_string()];
+        for systemd_cgroup in [None, Some(true), Some(false)] {
+            let cmd = runc.command_with_cgroup(&args, systemd_cgroup).unwrap();
+            assert_eq!(
+                runc.subcommand(&cmd),
+                "new-subcommand",
+                "{:?}",
+                systemd_cgroup
+            );
+        }
+        let runc = GlobalOpts::new().command("/bin/true").build().unwrap();
+        let cmd = runc.command_with_cgroup(&args, Some(true)).unwrap();
+        assert_eq!(runc.subcommand(&cmd), "new-subcommand");
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let args = ["state".to_string(), "fake-id".to_string()];
+        for empty in ["", "  \n", "null\n"] {
+            let list: Vec<Container> = parse_output(&args, empty, Some(Vec::new())).unwrap();
+            assert!(list.is_empty());
+            match parse_output::<Container>(&args, empty, None) {
+                Err(Error::EmptyOutput { command }) => assert_eq!(command, "state fake-id"),
+                r => panic!("unexpected result for {:?}: {:?}", empty, r),
+            }
+        }
+        assert!(matches!(
+            parse_output::<Vec<Container>>(&args, "garbage", Some(Vec::new())),
+            Err(Error::JsonDeserializationFailed(_))
+        ));
+        assert_eq!(
+            parse_output::<Vec<usize>>(&args, "[1, 2]\n", Some(Vec::new())).unwrap(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_empty_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "exit 0");
+        assert!(runc.list().unwrap().is_empty());
+        assert!(runc.ps("fake-id").unwrap().is_empty());
+        match runc.state("fake-id") {
+            Err(e @ Error::EmptyOutput { .. }) => {
+                assert_eq!(e.to_string(), "Runc state fake-id printed no output")
+            }
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+        assert!(matches!(
+            runc.stats("fake-id"),
+            Err(Error::EmptyOutput { .. })
+        ));
+    }
+
+    #[test]
+    fn test_create_ignore_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let status = dir.path().join("status");
+        fs::write(&status, "created").unwrap();
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"case "$*" in
+*create*) echo "container with id exists: fake-id" >&2; exit 1 ;;
+*state*) {state} ;;
+esac"#,
+                state = echo_state(&format!("$(cat {})", status.display()))
+            ),
+        );
+        let opts = CreateOpts::new();
+        let err = runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .unwrap_err();
+        assert!(err.is_already_exists());
+
+        let pid_file = dir.path().join("pid");
+        let opts = opts.ignore_existing(true).pid_file(&pid_file);
+        let res = runc.create("fake-id", "/bundle", Some(&opts)).unwrap();
+        assert_eq!(res.pid, 100);
+        assert!(res.status.success());
+        assert_eq!(fs::read_to_string(&pid_file).unwrap(), "100");
+
+        // A container with the same id from another bundle isn't the one being created.
+        let err = runc
+            .create("fake-id", "/other-bundle", Some(&opts))
+            .unwrap_err();
+        assert!(err.is_already_exists());
+
+        // Neither is a container which was started since.
+        fs::write(&status, "running").unwrap();
+        let err = runc.create("fake-id", "/bundle", Some(&opts)).unwrap_err();
+        assert!(err.is_already_exists());
+    }
+
+    #[test]
+    fn test_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = r#"echo "foo=$FOO home=$HOME""#;
+        let runc = fake_client(GlobalOpts::new().env("FOO", "bar"), dir.path(), script);
+        let response = runc.run("fake-id", "fake-bundle", None).unwrap();
+        assert_eq!(
+            response.stdout.trim(),
+            format!("foo=bar home={}", std::env::var("HOME").unwrap_or_default())
+        );
+
+        let opts = GlobalOpts::new().env_clear(true).env("FOO", "baz");
+        let runc = fake_client(opts, dir.path(), "exec env");
+        let response = runc.run("fake-id", "fake-bundle", None).unwrap();
+        let vars: Vec<&str> = response
+            .stdout
+            .lines()
+            .filter_map(|l| l.split('=').next())
+            .collect();
+        for key in KEPT_ENV {
+            assert_eq!(vars.contains(&key), std::env::var_os(key).is_some());
+        }
+        assert!(!vars.contains(&"HOME"));
+        assert!(response.stdout.contains("FOO=baz"));
+    }
+
+    #[test]
+    fn test_pid_from_pid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        // Write a pid which isn't the one of the fake runc itself.
+        let script = on_flag("--pid-file", r#"echo 4242 > "$a""#);
+        let runc = fake_client(GlobalOpts::new(), dir.path(), &script);
+        let pid_file = dir.path().join("pid");
+
+        let opts = CreateOpts::new().detach(true).pid_file(&pid_file);
+        let res = runc.create("fake-id", "fake-bundle", Some(&opts)).unwrap();
+        assert_eq!(res.pid, 4242);
+        fs::remove_file(&pid_file).unwrap();
+        let res = runc.run("fake-id", "fake-bundle", Some(&opts)).unwrap();
+        assert_eq!(res.pid, 4242);
+        fs::remove_file(&pid_file).unwrap();
+
+        let opts = ExecOpts::new().detach(true).pid_file(&pid_file);
+        let res = runc.exec("fake-id", &dummy_process(), Some(&opts)).unwrap();
+        assert_eq!(res.pid, 4242);
+
+        // Without a pid file the pid of the runc process is reported.
+        let res = runc.create("fake-id", "fake-bundle", None).unwrap();
+        assert_ne!(res.pid, 4242);
+    }
+
+    #[test]
+    fn test_update_only_set_resources() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("resources.json");
+        let script = on_flag("--resources", &format!(r#"cp "$a" {}"#, out.display()));
+        let runc = fake_client(GlobalOpts::new(), dir.path(), &script);
+        let resources = crate::specs::LinuxResourcesBuilder::new()
+            .pids_limit(10)
+            .build()
+            .unwrap();
+        runc.update("fake-id", &resources).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&out).unwrap()).unwrap();
+        assert_eq!(written, serde_json::json!({"pids": {"limit": 10}}));
+    }
+
+    #[test]
+    fn test_create_invalid_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "touch called");
+        for id in ["../escape", "a/b", ""] {
+            match runc.create(id, dir.path(), None) {
+                Err(Error::InvalidIdError(bad)) => assert_eq!(bad, id),
+                r => panic!("unexpected result for {:?}: {:?}", id, r),
+            }
+            assert!(matches!(
+                runc.run(id, dir.path(), None),
+                Err(Error::InvalidIdError(_))
+            ));
+        }
+        assert!(!dir.path().join("called").exists());
+    }
+
+    #[test]
+    fn test_extra_args() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts =
+            GlobalOpts::new().extra_args(vec!["--platform".to_string(), "ptrace".to_string()]);
+        let runc = fake_client(opts, dir.path(), r#"echo "$@""#);
+        let response = runc.run("fake-id", "fake-bundle", None).unwrap();
+        let args: Vec<&str> = response.stdout.split_whitespace().collect();
+        let run = args.iter().position(|a| *a == "run").unwrap();
+        let platform = args.iter().position(|a| *a == "--platform").unwrap();
+        let log_format = args.iter().position(|a| *a == "--log-format").unwrap();
+        assert!(log_format < platform && platform < run);
+        assert_eq!(args[platform + 1], "ptrace");
+    }
+
+    #[test]
+    fn test_run_no_pivot_no_new_keyring() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(GlobalOpts::new(), dir.path(), r#"echo "$@""#);
+        let opts = CreateOpts::new().no_pivot(true).no_new_keyring(true);
+        let response = runc
+            .run("fake-id", "fake-bundle", Some(&opts))
+            .expect("fake runc failed.");
+        let args: Vec<&str> = response.stdout.split_whitespace().collect();
+        let run = args.iter().position(|a| *a == "run").unwrap();
+        assert!(args[run..].contains(&"--no-pivot"));
+        assert!(args[run..].contains(&"--no-new-keyring"));
+        assert_eq!(args.last(), Some(&"fake-id"));
+    }
+
+    #[test]
+    fn test_extra_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        fs::write(&input, "hello from fd 3").unwrap();
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"for a; do [ "$prev" = --preserve-fds ] && echo "preserve $a"; prev=$a; done
+cat <&3"#,
+        );
+
+        let opts = CreateOpts::new().extra_files(vec![fs::File::open(&input).unwrap()]);
+        let response = runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .expect("fake runc failed.");
+        assert_eq!(response.stdout, "preserve 1\nhello from fd 3");
+    }
+
+    #[test]
+    fn test_output() {
+        // test create cmd with inherit Io, expect empty cmd output
+        let mut opts = CreateOpts::new();
+        opts.io = Some(Arc::new(InheritedStdIo::new().unwrap()));
+        let echo_runc = echo_client();
+        let response = echo_runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .expect("echo failed.");
+        assert_ne!(response.pid, 0);
+        assert!(response.status.success());
+        assert!(response.output.is_empty());
+        assert!(response.stdout.is_empty());
+
+        // test create cmd with pipe Io, expect nonempty cmd output
+        let mut opts = CreateOpts::new();
+        opts.io = Some(Arc::new(PipedStdIo::new().unwrap()));
+        let echo_runc = echo_client();
+        let response = echo_runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .expect("echo failed.");
+        assert_ne!(response.pid, 0);
+        assert!(response.status.success());
+        assert!(!response.output.is_empty());
+        assert!(!response.stdout.is_empty());
+    }
+}
+
+/// Tokio tests
+#[cfg(test)]
+#[cfg(all(target_os = "linux", feature = "async"))]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path, sync::Arc};
+
+    use super::{
+        io::{InheritedStdIo, PipedStdIo},
+        test_scripts::{echo_state, on_flag},
+        *,
+    };
+
+    fn ok_client() -> Runc {
+        GlobalOpts::new()
+            .command("/bin/true")
+            .build()
+            .expect("unable to create runc instance")
+    }
+
+    fn fail_client() -> Runc {
+        GlobalOpts::new()
+            .command("/bin/false")
+            .build()
+            .expect("unable to create runc instance")
+    }
+
+    fn echo_client() -> Runc {
+        GlobalOpts::new()
             .command("/bin/echo")
             .build()
             .expect("unable to create runc instance")
     }
 
+    /// Create a client running a shell script in place of the runc binary.
+    fn fake_client(opts: GlobalOpts, dir: &Path, script: &str) -> Runc {
+        let path = dir.join("runc");
+        fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        opts.command(path)
+            .build()
+            .expect("unable to create runc instance")
+    }
+
+    /// Wait for the process to be gone, it may be left as a zombie until it's reaped.
+    async fn wait_process_gone(pid: i32) -> bool {
+        for _ in 0..20 {
+            match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+                Err(_) => return true,
+                // The state is the first field after the command name, e.g. "1234 (sleep) Z ..."
+                Ok(stat) if stat.rsplit(") ").next().unwrap().starts_with('Z') => return true,
+                Ok(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn test_async_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!("echo $$ > {}\nexec sleep 10", pid_file.display()),
+        );
+
+        match runc
+            .with_timeout(Duration::from_millis(500))
+            .state("fake-id")
+            .await
+        {
+            Err(Error::CommandTimeout { .. }) => {}
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+        let pid = fs::read_to_string(&pid_file)
+            .unwrap()
+            .trim()
+            .parse::<i32>()
+            .unwrap();
+        assert!(wait_process_gone(pid).await);
+    }
+
+    #[tokio::test]
+    async fn test_async_timeout_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut opts = GlobalOpts::new();
+        opts.timeout(60_000);
+        let runc = fake_client(opts, dir.path(), "exec sleep 10");
+
+        let start = Instant::now();
+        match runc
+            .with_timeout(Duration::from_millis(200))
+            .state("fake-id")
+            .await
+        {
+            Err(Error::CommandTimeout { .. }) => {}
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // The override is applied to the copy only.
+        assert_eq!(runc.timeout, Some(Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn test_async_without_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "sleep 0.3\necho '[]'")
+            .with_timeout(Duration::from_millis(100));
+
+        match runc.list().await {
+            Err(Error::CommandTimeout { subcommand, .. }) => assert_eq!(subcommand, "list"),
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+        assert!(runc.without_timeout().list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_async_timeout_partial_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            "echo starting\necho stuck on cgroup >&2\nexec sleep 10",
+        );
+
+        match runc
+            .with_timeout(Duration::from_millis(500))
+            .state("fake-id")
+            .await
+        {
+            Err(Error::CommandTimeout {
+                subcommand,
+                timeout,
+                stdout,
+                stderr,
+            }) => {
+                assert_eq!(subcommand, "state");
+                assert_eq!(timeout, Duration::from_millis(500));
+                assert_eq!(stdout, "starting\n");
+                assert_eq!(stderr, "stuck on cgroup\n");
+            }
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_timeout_inherited_pipes() {
+        let dir = tempfile::tempdir().unwrap();
+        // The background process keeps stdout open after runc is gone.
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "sleep 3 &\necho '[]'")
+            .with_timeout(Duration::from_millis(500));
+
+        let start = Instant::now();
+        assert!(runc.list().await.unwrap().is_empty());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_async_json_output_ignores_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        let warn = "echo 'level=warning msg=\"unable to get oom kill count\"' >&2";
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!("{}\necho '[]'", warn),
+        );
+        assert!(runc.list().await.unwrap().is_empty());
+
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"{}
+{state}"#,
+                warn,
+                state = echo_state("running")
+            ),
+        );
+        assert_eq!(runc.state("fake-id").await.unwrap().pid, 100);
+    }
+
+    #[tokio::test]
+    async fn test_async_oom_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"echo '{"type":"stats","id":"fake-id","data":{}}'
+echo '{"type":"oom","id":"fake-id"}'"#,
+        );
+        let events: Vec<_> = runc.oom_events("fake-id").await.unwrap().collect().await;
+        assert_eq!(events.len(), 1);
+        assert!(events[0].as_ref().unwrap().is_oom());
+    }
+
+    #[tokio::test]
+    async fn test_async_delete_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("state-called");
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"case "$*" in
+*delete*) exit 0 ;;
+esac
+if [ -e {marker} ]; then echo "container fake-id does not exist" >&2; exit 1; fi
+touch {marker}
+{state}"#,
+                state = echo_state("stopped"),
+                marker = marker.display()
+            ),
+        );
+        let opts = DeleteOpts::new().verify(true);
+        runc.delete("fake-id", Some(&opts)).await.unwrap();
+        assert!(marker.exists());
+
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                "case \"$*\" in\n*delete*) exit 0 ;;\nesac\n{}",
+                echo_state("stopped")
+            ),
+        );
+        match runc.delete("fake-id", Some(&opts)).await {
+            Err(Error::DeleteVerificationFailed(id)) => assert_eq!(id, "fake-id"),
+            r => panic!("unexpected result from fake runc: {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_create_ignore_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"case "$*" in
+*create*) echo "container with id exists: fake-id" >&2; exit 1 ;;
+*state*) {state} ;;
+esac"#,
+                state = echo_state("created")
+            ),
+        );
+        let opts = CreateOpts::new();
+        assert!(runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .await
+            .unwrap_err()
+            .is_already_exists());
+        let pid_file = dir.path().join("pid");
+        let opts = opts.ignore_existing(true).pid_file(&pid_file);
+        let res = runc
+            .create("fake-id", "/bundle", Some(&opts))
+            .await
+            .unwrap();
+        assert_eq!(res.pid, 100);
+        assert_eq!(std::fs::read_to_string(&pid_file).unwrap(), "100");
+        assert!(runc
+            .create("fake-id", "/other-bundle", Some(&opts))
+            .await
+            .unwrap_err()
+            .is_already_exists());
+    }
+
+    #[tokio::test]
+    async fn test_async_delete_ignore_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"echo "container fake-id does not exist" >&2; exit 1"#,
+        );
+        let opts = DeleteOpts::new().force(true);
+        assert!(runc.delete("fake-id", Some(&opts)).await.is_err());
+        runc.delete("fake-id", Some(&opts.clone().ignore_not_found(true)))
+            .await
+            .unwrap();
+
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"echo "error opening file \`/run/crun/fake-id/status\`: No such file or directory" >&2; exit 1"#,
+        );
+        runc.delete("fake-id", Some(&opts.clone().ignore_not_found(true)))
+            .await
+            .unwrap();
+
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"echo "cannot delete container fake-id that is not stopped: running" >&2; exit 1"#,
+        );
+        assert!(runc
+            .delete("fake-id", Some(&opts.clone().ignore_not_found(true)))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_delete_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls");
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"echo "$*" >> {calls}
+case "$*" in
+*"delete --force"*) exit 0 ;;
+*delete*) echo "cannot delete container fake-id that is not stopped: running" >&2; exit 1 ;;
+*state*) {state} ;;
+esac"#,
+                state = echo_state("stopped"),
+                calls = calls.display()
+            ),
+        );
+        runc.delete_force("fake-id", &ForceDeleteOpts::new())
+            .await
+            .unwrap();
+        let log = fs::read_to_string(&calls).unwrap();
+        let log: Vec<_> = log.lines().collect();
+        assert_eq!(log.len(), 4);
+        assert!(log[0].ends_with("delete fake-id"));
+        assert!(log[1].ends_with("kill --all fake-id 9"));
+        assert!(log[2].ends_with("state fake-id"));
+        assert!(log[3].ends_with("delete --force fake-id"));
+    }
+
+    #[tokio::test]
+    async fn test_async_kill_not_running() {
+        let dir = tempfile::tempdir().unwrap();
+        for stderr in [
+            "container not running",
+            "os: process already finished",
+            "kill container: No such process",
+        ] {
+            let script = format!("echo '{}' >&2; exit 1", stderr);
+            let runc = fake_client(GlobalOpts::new(), dir.path(), &script);
+            runc.kill("fake-id", 15, None).await.unwrap();
+            runc.kill("fake-id", 15, Some(&KillOpts::new().all(true)))
+                .await
+                .unwrap();
+            match runc
+                .kill(
+                    "fake-id",
+                    15,
+                    Some(&KillOpts::new().ignore_not_running(false)),
+                )
+                .await
+            {
+                Err(e) => assert_eq!(e.command_error_kind(), CommandErrorKind::NotRunning),
+                r => panic!("unexpected result for {:?}: {:?}", stderr, r),
+            }
+        }
+
+        // Other failures are still reported.
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "exit 1");
+        assert!(runc.kill("fake-id", 15, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_async_exists_wait_for_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let count = dir.path().join("count");
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"n=$(cat {count} 2>/dev/null || echo 0)
+echo $((n + 1)) > {count}
+case "$*" in
+*missing*) echo "container missing does not exist" >&2; exit 1 ;;
+*broken*) echo "permission denied" >&2; exit 1 ;;
+esac
+status=created
+[ "$n" -ge 2 ] && status=running
+{state}"#,
+                state = echo_state("$status"),
+                count = count.display()
+            ),
+        );
+        assert!(!runc.exists("missing").await.unwrap());
+        assert!(runc.exists("broken").await.is_err());
+        fs::remove_file(&count).unwrap();
+
+        // Running from the third state on.
+        let interval = Duration::from_millis(10);
+        let state = runc
+            .wait_for_status(
+                "fake-id",
+                ContainerStatus::Running,
+                Duration::from_secs(5),
+                interval,
+            )
+            .await
+            .unwrap();
+        assert_eq!(state.container_status(), ContainerStatus::Running);
+        assert_eq!(fs::read_to_string(&count).unwrap().trim(), "3");
+
+        // Never reached, the last state is returned at the deadline.
+        let state = runc
+            .wait_for_status(
+                "fake-id",
+                ContainerStatus::Paused,
+                Duration::from_millis(100),
+                interval,
+            )
+            .await
+            .unwrap();
+        assert_eq!(state.container_status(), ContainerStatus::Running);
+        assert!(runc.exists("fake-id").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_async_spawn_retrying() {
+        let text_busy = || std::io::Error::from_raw_os_error(libc::ETXTBSY);
+
+        let mut attempts = 0;
+        let res = spawn_retrying(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(text_busy())
+            } else {
+                Ok(attempts)
+            }
+        })
+        .await;
+        assert_eq!(res.unwrap(), 3);
+
+        // Bounded.
+        let mut attempts = 0;
+        let res: Result<()> = spawn_retrying(|| {
+            attempts += 1;
+            Err(text_busy())
+        })
+        .await;
+        assert!(matches!(res, Err(Error::ProcessSpawnFailed(_))));
+        assert_eq!(attempts, SPAWN_ATTEMPTS);
+
+        // Other errors aren't retried.
+        let mut attempts = 0;
+        let res: Result<()> = spawn_retrying(|| {
+            attempts += 1;
+            Err(std::io::ErrorKind::NotFound.into())
+        })
+        .await;
+        assert!(matches!(res, Err(Error::ProcessSpawnFailed(_))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_async_spawn() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut opts = GlobalOpts::new();
+        opts.timeout(50);
+        let runc = fake_client(opts.root("/run/test"), dir.path(), "sleep 0.2\necho \"$@\"");
+
+        // The timeout is left to the owner of the child.
+        let child = runc
+            .spawn(&["run".to_string(), "fake-id".to_string()], None)
+            .await
+            .unwrap();
+        let output = child.wait_with_output().await.unwrap();
+        assert!(output.status.success());
+        let argv = String::from_utf8(output.stdout).unwrap();
+        assert!(argv.starts_with("--root /run/test"), "{}", argv);
+        assert!(argv.trim_end().ends_with("run fake-id"), "{}", argv);
+
+        let io = InheritedStdIo::new().unwrap();
+        let mut child = runc
+            .spawn(&["start".to_string(), "fake-id".to_string()], Some(&io))
+            .await
+            .unwrap();
+        assert!(child.stdout.is_none());
+        assert!(child.wait().await.unwrap().success());
+    }
+
+    #[tokio::test]
+    async fn test_async_spec_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec_dir = dir.path().join("specs");
+        let path_file = dir.path().join("path");
+        let script = on_flag(
+            "--resources",
+            &format!(r#"echo "$a" > {}"#, path_file.display()),
+        );
+        let runc = fake_client(GlobalOpts::new().spec_dir(&spec_dir), dir.path(), &script);
+
+        runc.update("fake-id", &LinuxResources::default())
+            .await
+            .unwrap();
+        let spec = PathBuf::from(fs::read_to_string(&path_file).unwrap().trim());
+        assert_eq!(spec.parent().unwrap(), spec_dir);
+        assert!(spec_dir.is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_async_terminate() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = dir.path().join("calls");
+        let count = dir.path().join("count");
+        // the container stops on the second state query after SIGTERM
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"echo "$*" >> {calls}
+case "$*" in
+*gone*) echo "container gone does not exist" >&2; exit 1 ;;
+*kill*) exit 0 ;;
+esac
+n=$(cat {count} 2>/dev/null || echo 0)
+echo $((n + 1)) > {count}
+status=running
+[ "$n" -ge 1 ] && status=stopped
+{state}"#,
+                state = echo_state("$status"),
+                calls = calls.display(),
+                count = count.display()
+            ),
+        );
+        runc.terminate("fake-id", Duration::from_secs(5))
+            .await
+            .unwrap();
+        let log = fs::read_to_string(&calls).unwrap();
+        let log: Vec<_> = log.lines().collect();
+        assert_eq!(log.len(), 3);
+        assert!(log[0].ends_with("kill fake-id 15"));
+        assert!(log[1].ends_with("state fake-id"));
+        assert!(log[2].ends_with("state fake-id"));
+
+        runc.terminate("gone", Duration::from_secs(1))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_async_max_output_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let max = 1 << 20;
+        let runc = fake_client(
+            GlobalOpts::new().max_output_bytes(max),
+            dir.path(),
+            "yes | head -c 50000000",
+        );
+        let res = runc.run("fake-id", "fake-bundle", None).await.unwrap();
+        assert!(res.truncated);
+        assert_eq!(res.stdout.len(), max);
+
+        let res = runc
+            .with_timeout(Duration::from_secs(10))
+            .run("fake-id", "fake-bundle", None)
+            .await
+            .unwrap();
+        assert!(res.truncated);
+        assert_eq!(res.stdout.len(), max);
+    }
+
+    #[tokio::test]
+    async fn test_async_update_raw() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("resources.json");
+        let script = on_flag("--resources", &format!(r#"cp "$a" {}"#, out.display()));
+        let runc = fake_client(GlobalOpts::new(), dir.path(), &script);
+        let resources = r#"{"memory": {"limit": 268435456}}"#;
+        runc.update_raw("fake-id", resources).await.unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), resources);
+        assert!(matches!(
+            runc.update_raw("fake-id", "{\"memory\":").await,
+            Err(Error::JsonDeserializationFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_async_cancel_kills_process_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let pids = dir.path().join("pids");
+        let script = format!(
+            "sleep 30 &\necho $$ $! > {}.tmp\nmv {0}.tmp {0}\nwait",
+            pids.display()
+        );
+        let runc = fake_client(GlobalOpts::new().set_pgid(true), dir.path(), &script);
+
+        let task = tokio::spawn(async move { runc.create("fake-id", "fake-bundle", None).await });
+        let mut waited = 0;
+        while !pids.exists() {
+            assert!(waited < 100, "fake runc didn't start");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            waited += 1;
+        }
+        task.abort();
+        assert!(task.await.unwrap_err().is_cancelled());
+
+        let pids = fs::read_to_string(&pids).unwrap();
+        for pid in pids.split_whitespace() {
+            assert!(
+                wait_process_gone(pid.parse().unwrap()).await,
+                "{} is alive",
+                pid
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_max_concurrent_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let script = format!(
+            "echo \"start $(date +%s%N)\" >> {0}\nsleep 0.3\necho \"end $(date +%s%N)\" >> {0}",
+            log.display()
+        );
+        let runc = fake_client(
+            GlobalOpts::new().max_concurrent_commands(2),
+            dir.path(),
+            &script,
+        );
+
+        let tasks: Vec<_> = (0..6)
+            .map(|_| {
+                let runc = runc.clone();
+                tokio::spawn(async move { runc.kill("fake-id", 9, None).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(runc.in_flight_commands() <= 2);
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert_eq!(runc.in_flight_commands(), 0);
+
+        // Appends are atomic, the lines are in the order the fakes started and ended.
+        let log = fs::read_to_string(&log).unwrap();
+        let (mut running, mut max) = (0, 0);
+        for line in log.lines() {
+            if line.starts_with("start") {
+                running += 1;
+                max = max.max(running);
+            } else {
+                running -= 1;
+            }
+        }
+        assert_eq!(log.lines().count(), 12);
+        assert_eq!(max, 2);
+
+        assert!(matches!(
+            GlobalOpts::new()
+                .command("/bin/true")
+                .max_concurrent_commands(0)
+                .build(),
+            Err(Error::NoConcurrentCommands)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_async_spec_file_removed_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = format!(
+            "{}\nexit 1",
+            on_flag(
+                "--process|--resources",
+                &format!(
+                    r#"echo "$a" > {dir}/path; stat -c %a "$a" > {dir}/mode"#,
+                    dir = dir.path().display()
+                ),
+            )
+        );
+        let runc = fake_client(GlobalOpts::new(), dir.path(), &script);
+        let process: Process = serde_json::from_str(r#"{"cwd": "/"}"#).unwrap();
+        for _ in 0..3 {
+            runc.exec("fake-id", &process, None).await.unwrap_err();
+            let spec = PathBuf::from(fs::read_to_string(dir.path().join("path")).unwrap().trim());
+            assert!(!spec.exists());
+            assert_eq!(
+                fs::read_to_string(dir.path().join("mode")).unwrap().trim(),
+                "600"
+            );
+
+            runc.update_raw("fake-id", "{}").await.unwrap_err();
+            let spec = PathBuf::from(fs::read_to_string(dir.path().join("path")).unwrap().trim());
+            assert!(!spec.exists());
+            assert_eq!(
+                fs::read_to_string(dir.path().join("mode")).unwrap().trim(),
+                "600"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let fail = dir.path().join("ps-fails");
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"case "$*" in
+*events*) echo '{{"type":"stats","id":"fake-id","data":{{"cpu":{{"usage":{{"total":42}}}},"memory":{{}},"pids":{{}},"blkio":{{}}}}}}' ;;
+*"ps --format=table fake-id -ef"*)
+    [ -e {fail} ] && {{ echo "ps failed" >&2; exit 1; }}
+    printf 'UID PID PPID C STIME TTY TIME CMD\nroot 1 0 0 10:00 ? 00:00:00 sleep 100\n' ;;
+esac"#,
+                fail = fail.display()
+            ),
+        );
+        let snapshot = runc.snapshot("fake-id").await.unwrap();
+        assert_eq!(snapshot.stats.cpu_usage_nanos(), Some(42));
+        assert_eq!(snapshot.processes.unwrap()[0].cmd, "sleep 100");
+
+        // The stats are kept when ps fails.
+        fs::write(&fail, "").unwrap();
+        let snapshot = runc.snapshot("fake-id").await.unwrap();
+        assert_eq!(snapshot.stats.cpu_usage_nanos(), Some(42));
+        assert!(matches!(
+            snapshot.processes,
+            Err(Error::CommandFailed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_async_ps_full_ignores_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"echo "warning: something" >&2
+printf 'UID PID PPID C STIME TTY TIME CMD\nroot 1 0 0 10:00 ? 00:00:00 sleep 100\n'"#,
+        );
+        let processes = runc.ps_full("fake-id", &[]).await.unwrap();
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].cmd, "sleep 100");
+    }
+
+    #[tokio::test]
+    async fn test_async_command_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = GlobalOpts::new().env_clear(true).command_hook(|cmd| {
+            cmd.env("RUNC_HOOKED", "1");
+        });
+        let runc = fake_client(opts, dir.path(), "env");
+        let res = runc.start("fake-id").await.unwrap();
+        assert!(res.stdout.lines().any(|l| l == "RUNC_HOOKED=1"));
+
+        let mut child = runc.spawn(&["start".to_string()], None).await.unwrap();
+        let mut stdout = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut stdout)
+            .await
+            .unwrap();
+        child.wait().await.unwrap();
+        assert!(stdout.lines().any(|l| l == "RUNC_HOOKED=1"));
+    }
+
+    #[tokio::test]
+    async fn test_async_stats_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = dir.path().join("args");
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            &format!(
+                r#"echo "$*" > {}
+for usage in 1 2 3; do
+    echo '{{"type":"stats","id":"fake-id","data":{{"cpu":{{"usage":{{"total":'$usage'}}}}}}}}'
+    [ $usage = 2 ] && echo '{{"type":"oom","id":"fake-id"}}'
+done
+exit 0"#,
+                args.display()
+            ),
+        );
+        let samples: Vec<_> = runc
+            .stats_stream("fake-id", Duration::from_millis(500))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        let usage: Vec<_> = samples
+            .into_iter()
+            .map(|s| s.unwrap().cpu_usage_nanos().unwrap())
+            .collect();
+        assert_eq!(usage, [1, 2, 3]);
+        let args = fs::read_to_string(&args).unwrap();
+        assert!(args.trim_end().ends_with("events --interval 500ms fake-id"));
+
+        // Rounding down to whole milliseconds would leave runc a zero interval.
+        match runc
+            .stats_stream("fake-id", Duration::from_micros(500))
+            .await
+        {
+            Err(Error::InvalidInterval(interval)) => {
+                assert_eq!(interval, Duration::from_micros(500))
+            }
+            r => panic!("unexpected result: {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_spawn_with_io() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "echo hello; echo oops >&2");
+        let opts = io::IOOption {
+            open_stdin: false,
+            ..Default::default()
+        };
+        let uid = nix::unistd::getuid();
+        let gid = nix::unistd::getgid();
+        let io = io::PipedIo::new(uid.as_raw(), gid.as_raw(), &opts).unwrap();
+
+        let mut child = runc
+            .spawn_with_io(&["run".to_string(), "fake-id".to_string()], &io)
+            .await
+            .unwrap();
+        assert!(child.stdout.is_none());
+        // Ends with EOF, our copies of the write ends are closed.
+        let stdout = io.copy_stdout(Vec::new()).unwrap();
+        let stderr = io.copy_stderr(Vec::new()).unwrap();
+        assert!(child.wait().await.unwrap().success());
+        assert_eq!(stdout.await.unwrap().unwrap(), b"hello\n");
+        assert_eq!(stderr.await.unwrap().unwrap(), b"oops\n");
+    }
+
+    #[tokio::test]
+    async fn test_async_stats_many() {
+        let dir = tempfile::tempdir().unwrap();
+        let runc = fake_client(
+            GlobalOpts::new(),
+            dir.path(),
+            r#"for id; do :; done
+case "$id" in
+bad-*) echo "container $id does not exist" >&2; exit 1 ;;
+esac
+sleep 0.5
+echo '{"type":"stats","id":"'$id'","data":{"cpu":{"usage":{"total":42}},"memory":{},"pids":{},"blkio":{}}}'"#,
+        );
+
+        let ids = ["c1", "bad-1", "c2", "c3", "bad-2", "c4"];
+        let start = std::time::Instant::now();
+        let results = runc.stats_many(&ids).await;
+        // Each good container takes 0.5s, sequential collection would take 2s.
+        assert!(start.elapsed() < Duration::from_millis(1500));
+
+        assert_eq!(results.len(), ids.len());
+        for ((id, res), expected) in results.iter().zip(ids.iter()) {
+            assert_eq!(id, expected);
+            if id.starts_with("bad-") {
+                assert!(matches!(res, Err(Error::CommandFailed { .. })));
+            } else {
+                assert_eq!(res.as_ref().unwrap().cpu_usage_nanos(), Some(42));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = on_flag(
+            "--resources",
+            &format!(
+                r#"cp "$a" {dir}/resources.json; echo "$a" > {dir}/path"#,
+                dir = dir.path().display()
+            ),
+        );
+        let runc = fake_client(GlobalOpts::new(), dir.path(), &script);
+        let resources: LinuxResources =
+            serde_json::from_str(r#"{"memory": {"limit": 268435456}, "cpu": {"quota": 50000}}"#)
+                .unwrap();
+        runc.update("fake-id", &resources)
+            .await
+            .expect("fake runc failed.");
+
+        let written: LinuxResources =
+            serde_json::from_slice(&fs::read(dir.path().join("resources.json")).unwrap()).unwrap();
+        assert_eq!(written.memory().as_ref().unwrap().limit(), Some(268435456));
+        assert_eq!(written.cpu().as_ref().unwrap().quota(), Some(50000));
+        // The temp file is removed once the command returns.
+        let path = fs::read_to_string(dir.path().join("path")).unwrap();
+        assert!(!Path::new(path.trim()).exists());
+    }
+
+    #[tokio::test]
+    async fn test_async_keep_spec_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_file = dir.path().join("path");
+        let script = format!(
+            "{}\nexit 1",
+            on_flag(
+                "--process",
+                &format!(r#"echo "$a" > {}"#, path_file.display()),
+            )
+        );
+        let process: Process = serde_json::from_str(r#"{"cwd": "/"}"#).unwrap();
+        for keep in [true, false] {
+            let runc = fake_client(GlobalOpts::new().keep_spec_files(keep), dir.path(), &script);
+            runc.exec("fake-id", &process, None).await.unwrap_err();
+            let spec = PathBuf::from(fs::read_to_string(&path_file).unwrap().trim());
+            assert_eq!(spec.exists(), keep);
+            let _ = fs::remove_file(spec);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_extra_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        fs::write(&input, "hello from fd 4").unwrap();
+        let runc = fake_client(GlobalOpts::new(), dir.path(), "cat <&4");
+
+        let files = vec![
+            fs::File::open("/dev/null").unwrap(),
+            fs::File::open(&input).unwrap(),
+        ];
+        let opts = CreateOpts::new().extra_files(files);
+        let response = runc
+            .create("fake-id", "fake-bundle", Some(&opts))
+            .await
+            .expect("fake runc failed.");
+        assert_eq!(response.stdout, "hello from fd 4");
+    }
+
     #[tokio::test]
     async fn test_async_create() {
         let opts = CreateOpts::new();
@@ -839,6 +4194,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -870,6 +4226,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -909,6 +4266,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -944,6 +4302,7 @@ mod tests {
                     status,
                     stdout,
                     stderr,
+                    ..
                 }) => {
                     if status.code().unwrap() == 1 && stdout.is_empty() && stderr.is_empty() {
                         eprintln!("fail_runc succeeded.");
@@ -971,6 +4330,7 @@ mod tests {
         assert_ne!(response.pid, 0);
         assert!(response.status.success());
         assert!(response.output.is_empty());
+        assert!(response.stdout.is_empty());
 
         // test create cmd with pipe Io, expect nonempty cmd output
         let mut opts = CreateOpts::new();
@@ -982,40 +4342,312 @@ mod tests {
         assert_ne!(response.pid, 0);
         assert!(response.status.success());
         assert!(!response.output.is_empty());
+        assert!(!response.stdout.is_empty());
     }
 }
 
-#[derive(Debug)]
+/// Spawning runc is retried this many times while its binary is being replaced, like go-runc.
+const SPAWN_ATTEMPTS: usize = 5;
+
+const SPAWN_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// Delay before spawning again after the `attempt`th failure, only ETXTBSY is retried.
+fn spawn_retry_delay(e: &std::io::Error, attempt: usize) -> Option<Duration> {
+    if e.raw_os_error() == Some(libc::ETXTBSY) && attempt < SPAWN_ATTEMPTS {
+        Some(SPAWN_RETRY_DELAY)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(feature = "async"))]
+fn spawn_retrying<T>(mut spawn: impl FnMut() -> std::io::Result<T>) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        match spawn() {
+            Ok(child) => return Ok(child),
+            Err(e) => match spawn_retry_delay(&e, attempt) {
+                Some(delay) => std::thread::sleep(delay),
+                None => return Err(Error::ProcessSpawnFailed(e)),
+            },
+        }
+        attempt += 1;
+    }
+}
+
+#[cfg(feature = "async")]
+async fn spawn_retrying<T>(mut spawn: impl FnMut() -> std::io::Result<T>) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        match spawn() {
+            Ok(child) => return Ok(child),
+            Err(e) => match spawn_retry_delay(&e, attempt) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Err(Error::ProcessSpawnFailed(e)),
+            },
+        }
+        attempt += 1;
+    }
+}
+
+/// Spawner keeping the first [`DEFAULT_MAX_OUTPUT_BYTES`] of the stdout and stderr of each
+/// command, see [`CappedExecutor`] for another limit.
+#[derive(Debug, Default)]
 pub struct DefaultExecutor {}
 
 #[cfg(feature = "async")]
 #[async_trait]
 impl Spawner for DefaultExecutor {
     async fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
-        let mut cmd = cmd;
-        let child = cmd.spawn().map_err(Error::ProcessSpawnFailed)?;
-        let pid = child.id().unwrap();
-        let result = child
-            .wait_with_output()
+        CappedExecutor::default().execute(cmd).await
+    }
+
+    async fn execute_timeout(
+        &self,
+        cmd: Command,
+        timeout: Duration,
+    ) -> Result<(ExitStatus, u32, String, String)> {
+        CappedExecutor::default()
+            .execute_timeout(cmd, timeout)
             .await
-            .map_err(Error::InvalidCommand)?;
-        let status = result.status;
-        let stdout = String::from_utf8_lossy(&result.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&result.stderr).to_string();
-        Ok((status, pid, stdout, stderr))
     }
 }
 
 #[cfg(not(feature = "async"))]
 impl Spawner for DefaultExecutor {
+    fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
+        CappedExecutor::default().execute(cmd)
+    }
+}
+
+/// Spawner used unless a custom one is set, it keeps up to `max_output_bytes` of the stdout
+/// and stderr of each command and drains the rest.
+#[derive(Debug)]
+pub struct CappedExecutor {
+    max_output_bytes: usize,
+}
+
+impl CappedExecutor {
+    pub fn new(max_output_bytes: usize) -> Self {
+        Self { max_output_bytes }
+    }
+}
+
+impl Default for CappedExecutor {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_OUTPUT_BYTES)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl Spawner for CappedExecutor {
+    async fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
+        let mut cmd = cmd;
+        let mut child = spawn_retrying(|| cmd.spawn()).await?;
+        let pid = child.id().unwrap();
+        let guard = ProcessGroupGuard::new(pid);
+        let (stdout, stdout_task) = collect_pipe(child.stdout.take(), self.max_output_bytes);
+        let (stderr, stderr_task) = collect_pipe(child.stderr.take(), self.max_output_bytes);
+        let status = child.wait().await.map_err(Error::InvalidCommand)?;
+        guard.disarm();
+        stdout_task.await.map_err(|e| Error::Other(Box::new(e)))?;
+        stderr_task.await.map_err(|e| Error::Other(Box::new(e)))?;
+        let output =
+            |buf: &Arc<Mutex<Vec<u8>>>| String::from_utf8_lossy(&buf.lock().unwrap()).to_string();
+        Ok((status, pid, output(&stdout), output(&stderr)))
+    }
+
+    async fn execute_timeout(
+        &self,
+        cmd: Command,
+        timeout: Duration,
+    ) -> Result<(ExitStatus, u32, String, String)> {
+        let mut cmd = cmd;
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut child = spawn_retrying(|| cmd.spawn()).await?;
+        let pid = child.id().unwrap();
+        let guard = ProcessGroupGuard::new(pid);
+        let (stdout, mut stdout_task) = collect_pipe(child.stdout.take(), self.max_output_bytes);
+        let (stderr, mut stderr_task) = collect_pipe(child.stderr.take(), self.max_output_bytes);
+        let output =
+            |buf: &Arc<Mutex<Vec<u8>>>| String::from_utf8_lossy(&buf.lock().unwrap()).to_string();
+
+        match tokio::time::timeout_at(deadline, child.wait()).await {
+            Ok(status) => {
+                let status = status.map_err(Error::InvalidCommand)?;
+                guard.disarm();
+                // Processes detached by runc may have inherited the pipes, they don't extend
+                // the timeout.
+                let deadline = deadline.max(tokio::time::Instant::now() + TIMEOUT_OUTPUT_GRACE);
+                drain_until([&mut stdout_task, &mut stderr_task], deadline).await;
+                Ok((status, pid, output(&stdout), output(&stderr)))
+            }
+            Err(_) => {
+                // Kill the children of runc too, they may hold the pipes open.
+                drop(guard);
+                let _ = child.kill().await;
+                drain_until(
+                    [&mut stdout_task, &mut stderr_task],
+                    tokio::time::Instant::now() + TIMEOUT_OUTPUT_GRACE,
+                )
+                .await;
+                Err(Error::CommandTimeout {
+                    subcommand: String::new(),
+                    timeout,
+                    stdout: output(&stdout),
+                    stderr: output(&stderr),
+                })
+            }
+        }
+    }
+}
+
+/// Pick up what's left in the pipes until `deadline`, unless they are held open by another
+/// process.
+#[cfg(feature = "async")]
+async fn drain_until(tasks: [&mut JoinHandle<()>; 2], deadline: tokio::time::Instant) {
+    for task in tasks {
+        if tokio::time::timeout_at(deadline, &mut *task).await.is_err() {
+            task.abort();
+        }
+    }
+}
+
+/// Keep `child` alive as long as `stream`.
+#[cfg(feature = "async")]
+fn keep_alive<T: Send + 'static>(
+    stream: futures::stream::BoxStream<'static, T>,
+    child: tokio::process::Child,
+) -> futures::stream::BoxStream<'static, T> {
+    stream
+        .map(move |item| {
+            let _ = &child;
+            item
+        })
+        .boxed()
+}
+
+/// Counts a command as in flight until dropped.
+#[cfg(feature = "async")]
+struct InFlight<'a>(&'a AtomicUsize);
+
+#[cfg(feature = "async")]
+impl<'a> InFlight<'a> {
+    fn new(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Kill the process group led by a spawned runc unless disarmed once runc has exited, so
+/// neither runc nor its children outlive a command whose future is dropped.
+///
+/// runc leads its own group only when started with [`GlobalOpts::set_pgid`], otherwise only
+/// runc itself is killed, by `kill_on_drop`.
+#[cfg(feature = "async")]
+struct ProcessGroupGuard(Option<nix::unistd::Pid>);
+
+#[cfg(feature = "async")]
+impl ProcessGroupGuard {
+    fn new(pid: u32) -> Self {
+        let pid = nix::unistd::Pid::from_raw(pid as i32);
+        match nix::unistd::getpgid(Some(pid)) {
+            Ok(pgid) if pgid == pid => Self(Some(pid)),
+            _ => Self(None),
+        }
+    }
+
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for ProcessGroupGuard {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.0 {
+            if let Err(e) = nix::sys::signal::killpg(pgid, nix::sys::signal::Signal::SIGKILL) {
+                warn!("failed to kill process group {}: {}", pgid, e);
+            }
+        }
+    }
+}
+
+/// Read `pipe` to its end in the background, what has been read so far is available in the
+/// returned buffer even if the reading is aborted.
+///
+/// Only the first `max + 1` bytes are kept, so the caller can tell the output was cut.
+#[cfg(feature = "async")]
+fn collect_pipe<R>(pipe: Option<R>, max: usize) -> (Arc<Mutex<Vec<u8>>>, JoinHandle<()>)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let buf = Arc::new(Mutex::new(Vec::new()));
+    let collected = buf.clone();
+    let task = tokio::spawn(async move {
+        let mut pipe = match pipe {
+            Some(pipe) => pipe,
+            None => return,
+        };
+        let mut chunk = [0u8; 4096];
+        loop {
+            match pipe.read(&mut chunk).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let mut collected = collected.lock().unwrap();
+                    let room = (max + 1).saturating_sub(collected.len());
+                    collected.extend_from_slice(&chunk[..n.min(room)]);
+                }
+            }
+        }
+    });
+    (buf, task)
+}
+
+#[cfg(not(feature = "async"))]
+impl Spawner for CappedExecutor {
     fn execute(&self, cmd: Command) -> Result<(ExitStatus, u32, String, String)> {
         let mut cmd = cmd;
-        let child = cmd.spawn().map_err(Error::ProcessSpawnFailed)?;
+        let mut child = spawn_retrying(|| cmd.spawn())?;
         let pid = child.id();
-        let result = child.wait_with_output().map_err(Error::InvalidCommand)?;
-        let status = result.status;
-        let stdout = String::from_utf8_lossy(&result.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&result.stderr).to_string();
-        Ok((status, pid, stdout, stderr))
+        let max = self.max_output_bytes;
+        let stderr = child.stderr.take();
+        let stderr = std::thread::spawn(move || stderr.map(|p| read_capped(p, max)));
+        let stdout = child.stdout.take().map(|p| read_capped(p, max));
+        let stderr = stderr
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+        let status = child.wait().map_err(Error::InvalidCommand)?;
+        let output = |buf: Option<std::io::Result<Vec<u8>>>| -> Result<String> {
+            let buf = buf.transpose().map_err(Error::InvalidCommand)?;
+            Ok(String::from_utf8_lossy(&buf.unwrap_or_default()).to_string())
+        };
+        Ok((status, pid, output(stdout)?, output(stderr)?))
+    }
+}
+
+/// Read `pipe` to its end, keeping only the first `max + 1` bytes so the caller can tell the
+/// output was cut.
+#[cfg(not(feature = "async"))]
+fn read_capped(mut pipe: impl std::io::Read, max: usize) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        let n = match pipe.read(&mut chunk) {
+            Ok(0) => return Ok(buf),
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let room = (max + 1).saturating_sub(buf.len());
+        buf.extend_from_slice(&chunk[..n.min(room)]);
     }
 }
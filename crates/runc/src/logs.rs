@@ -0,0 +1,299 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Reader for the log file written by runc with `--log-format json`.
+
+use std::{
+    fs::File,
+    io::{BufRead, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Only the tail of the log is inspected, a shared log file may grow large.
+const MAX_LOG_READ: u64 = 64 * 1024;
+
+/// Maximum number of entries attached to an error.
+const MAX_LOG_ENTRIES: usize = 10;
+
+/// A single line of runc's JSON log, e.g.
+/// `{"level":"error","msg":"container with id exists: foo","time":"2022-10-25T07:15:06Z"}`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogEntry {
+    pub level: String,
+    pub msg: String,
+    pub time: Option<String>,
+}
+
+impl LogEntry {
+    fn is_error(&self) -> bool {
+        matches!(self.level.as_str(), "error" | "fatal" | "panic")
+    }
+
+    /// Parsed `time` of the entry, runc writes it in RFC 3339.
+    pub fn timestamp(&self) -> Option<OffsetDateTime> {
+        self.time
+            .as_deref()
+            .and_then(|t| OffsetDateTime::parse(t, &Rfc3339).ok())
+    }
+}
+
+/// Where a [`LogLine`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSource {
+    Runc,
+    Stdout,
+    Stderr,
+}
+
+/// A line of the combined view of runc's log and the container output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    pub time: OffsetDateTime,
+    pub source: LogSource,
+    /// Level of runc log entries, [`None`] for container output.
+    pub level: Option<String>,
+    pub msg: String,
+}
+
+impl LogLine {
+    /// A line of container output, stamped with the current time.
+    pub fn output(source: LogSource, msg: impl Into<String>) -> Self {
+        Self {
+            time: OffsetDateTime::now_utc(),
+            source,
+            level: None,
+            msg: msg.into(),
+        }
+    }
+}
+
+/// Read container output line by line, stamping each line when it's read.
+pub fn read_output<R: BufRead>(source: LogSource, reader: R) -> Vec<LogLine> {
+    reader
+        .lines()
+        .map_while(|l| l.ok())
+        .map(|l| LogLine::output(source, l))
+        .collect()
+}
+
+/// Current size of the log file, entries written after it belong to the next command.
+pub(crate) fn log_offset(path: &Path) -> u64 {
+    path.metadata().map(|m| m.len()).unwrap_or(0)
+}
+
+/// Read the last error level entries written to the log after `offset`.
+///
+/// A missing file yields no entries, lines which can't be parsed (e.g. a partially
+/// written last line) are skipped.
+pub(crate) fn read_error_entries(path: &Path, offset: u64) -> Vec<LogEntry> {
+    let mut entries: Vec<LogEntry> = read_entries(path, offset)
+        .into_iter()
+        .filter(LogEntry::is_error)
+        .collect();
+    if entries.len() > MAX_LOG_ENTRIES {
+        entries.drain(..entries.len() - MAX_LOG_ENTRIES);
+    }
+    entries
+}
+
+/// Read the entries of the log, at most the last [`MAX_LOG_READ`] bytes of it.
+///
+/// A missing file yields no entries, lines which can't be parsed are skipped.
+pub(crate) fn read_log(path: &Path) -> Vec<LogEntry> {
+    read_entries(path, 0)
+}
+
+/// Merge the runc log entries written after `offset` with container output into a single
+/// time ordered view, e.g. to follow what happened during a failing create.
+///
+/// Entries without a parsable time are placed after the entry preceding them in the log.
+pub fn combined_tail(
+    path: &Path,
+    offset: u64,
+    output: impl IntoIterator<Item = LogLine>,
+) -> Vec<LogLine> {
+    let mut last = None;
+    let mut lines: Vec<LogLine> = read_entries(path, offset)
+        .into_iter()
+        .filter_map(|entry| {
+            last = entry.timestamp().or(last);
+            Some(LogLine {
+                time: last?,
+                source: LogSource::Runc,
+                level: Some(entry.level),
+                msg: entry.msg,
+            })
+        })
+        .collect();
+    lines.extend(output);
+    // Stable, so lines of a source with equal times keep their order.
+    lines.sort_by_key(|l| l.time);
+    lines
+}
+
+/// Read the entries written to the log after `offset`, at most the last [`MAX_LOG_READ`] bytes.
+fn read_entries(path: &Path, offset: u64) -> Vec<LogEntry> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(_) => return vec![],
+    };
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    // The log was truncated or rotated in between, read it from the start.
+    let offset = if offset > len { 0 } else { offset };
+    let start = offset.max(len.saturating_sub(MAX_LOG_READ));
+
+    let mut buf = Vec::new();
+    if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_end(&mut buf).is_err() {
+        return vec![];
+    }
+    let content = String::from_utf8_lossy(&buf);
+    let mut lines = content.lines();
+    // Starting in the middle of the file, the first line is likely partial.
+    if start > offset {
+        lines.next();
+    }
+
+    lines
+        .filter_map(|line| serde_json::from_str::<LogEntry>(line).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_read_error_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.json");
+        fs::write(
+            &path,
+            r#"{"level":"error","msg":"stale error","time":"2022-10-25T07:15:05Z"}
+"#,
+        )
+        .unwrap();
+        let offset = log_offset(&path);
+
+        let mut log = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        std::io::Write::write_all(
+            &mut log,
+            br#"{"level":"info","msg":"starting","time":"2022-10-25T07:15:06Z"}
+{"level":"error","msg":"container with id exists: foo","time":"2022-10-25T07:15:06Z"}
+{"level":"fatal","msg":"unable to start container","time":"2022-10-25T07:15:06Z"}
+{"level":"error","msg":"trunc"#,
+        )
+        .unwrap();
+
+        let entries = read_error_entries(&path, offset);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].msg, "container with id exists: foo");
+        assert_eq!(entries[1].level, "fatal");
+        assert_eq!(entries[1].time.as_deref(), Some("2022-10-25T07:15:06Z"));
+
+        // Everything is read again if the log shrank in between.
+        assert_eq!(read_error_entries(&path, u64::MAX).len(), 3);
+    }
+
+    #[test]
+    fn test_read_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.json");
+        fs::write(
+            &path,
+            r#"{"level":"info","msg":"starting","time":"2022-10-25T07:15:06Z"}
+{"level":"warning","msg":"cgroup v1 is deprecated"}
+not json
+{"level":"error","msg":"container_linux.go:380: starting container process caused: exec: \"foo\": executable file not found in $PATH","time":"2022-10-25T07:15:07Z"}
+"#,
+        )
+        .unwrap();
+
+        let entries = read_log(&path);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].level, "info");
+        assert_eq!(entries[1].time, None);
+        assert_eq!(entries[2].level, "error");
+        assert!(entries[2]
+            .msg
+            .ends_with(r#"exec: "foo": executable file not found in $PATH"#));
+        assert!(entries[2].timestamp().is_some());
+
+        assert!(read_log(&dir.path().join("missing.json")).is_empty());
+    }
+
+    #[test]
+    fn test_read_error_entries_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing.json");
+        assert_eq!(log_offset(&path), 0);
+        assert!(read_error_entries(&path, 0).is_empty());
+    }
+
+    #[test]
+    fn test_combined_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.json");
+        fs::write(
+            &path,
+            r#"{"level":"debug","msg":"nsexec started","time":"2022-10-25T07:15:06.100Z"}
+{"level":"debug","msg":"no time"}
+{"level":"error","msg":"exec failed","time":"2022-10-25T07:15:06.300Z"}
+"#,
+        )
+        .unwrap();
+
+        let at = |ms: i64| {
+            OffsetDateTime::parse("2022-10-25T07:15:06Z", &Rfc3339).unwrap()
+                + time::Duration::milliseconds(ms)
+        };
+        let output = vec![
+            LogLine {
+                time: at(200),
+                ..LogLine::output(LogSource::Stdout, "hello")
+            },
+            LogLine {
+                time: at(400),
+                ..LogLine::output(LogSource::Stderr, "bye")
+            },
+        ];
+
+        let lines = combined_tail(&path, 0, output);
+        let msgs: Vec<&str> = lines.iter().map(|l| l.msg.as_str()).collect();
+        assert_eq!(
+            msgs,
+            ["nsexec started", "no time", "hello", "exec failed", "bye"]
+        );
+        assert_eq!(lines[1].time, at(100));
+        assert_eq!(lines[3].level.as_deref(), Some("error"));
+        assert_eq!(lines[4].source, LogSource::Stderr);
+        assert!(lines.windows(2).all(|w| w[0].time <= w[1].time));
+    }
+
+    #[test]
+    fn test_read_output() {
+        let before = OffsetDateTime::now_utc();
+        let lines = read_output(LogSource::Stdout, "a\nb\n".as_bytes());
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].msg, "b");
+        assert!(lines.iter().all(|l| l.time >= before && l.level.is_none()));
+    }
+}
@@ -14,17 +14,31 @@
    limitations under the License.
 */
 
-use std::process::{ExitStatus, Output};
+use std::{
+    collections::HashMap,
+    os::unix::process::ExitStatusExt,
+    process::{ExitStatus, Output},
+    sync::{Arc, Mutex, Weak},
+};
 
 use async_trait::async_trait;
-use log::error;
+use futures::{stream::BoxStream, FutureExt, StreamExt};
+use log::{debug, error};
+use nix::{
+    sys::wait::{waitpid, WaitPidFlag, WaitStatus},
+    unistd::Pid,
+};
 use time::OffsetDateTime;
 use tokio::{
     process::Command,
-    sync::oneshot::{channel, Receiver, Sender},
+    signal::unix::{signal, SignalKind},
+    sync::{
+        mpsc,
+        oneshot::{channel, Receiver, Sender},
+    },
 };
 
-use crate::error::Error;
+use crate::{error::Error, events::Event};
 
 /// A trait for spawning and waiting for a process.
 ///
@@ -91,6 +105,164 @@ pub struct Exit {
     pub status: i32,
 }
 
+/// Reaper for processes which were spawned elsewhere, e.g. a container init whose parent was
+/// runc, with the current process as child subreaper.
+///
+/// Only registered pids are waited for, children spawned with [`Command`] are left to tokio.
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    subscribers: Arc<Mutex<HashMap<u32, Vec<Subscriber>>>>,
+}
+
+#[derive(Debug)]
+enum Subscriber {
+    Exit(Sender<Exit>),
+    Status(Sender<ExitStatus>),
+}
+
+impl Monitor {
+    /// Create a monitor reaping registered pids on `SIGCHLD`, must be called within a tokio
+    /// runtime. The handler task stops when the last clone of the monitor is dropped.
+    pub fn new() -> std::io::Result<Self> {
+        let mut sigchld = signal(SignalKind::child())?;
+        let monitor = Self {
+            subscribers: Default::default(),
+        };
+        let subscribers = Arc::downgrade(&monitor.subscribers);
+        tokio::spawn(async move {
+            while sigchld.recv().await.is_some() {
+                match Weak::upgrade(&subscribers) {
+                    Some(subscribers) => reap(&subscribers),
+                    None => break,
+                }
+            }
+        });
+        Ok(monitor)
+    }
+
+    /// Register `pid`, the returned receiver gets its exit once it's reaped.
+    ///
+    /// `pid` doesn't need to be a child yet, nothing is received until it's reparented to the
+    /// current process and exits.
+    ///
+    /// The status of a process killed by a signal is 128 + the signal number, as reported by shells.
+    pub fn start(&self, pid: u32) -> Receiver<Exit> {
+        let (tx, rx) = channel();
+        self.subscribe(pid, Subscriber::Exit(tx));
+        rx
+    }
+
+    /// Wait for `pid` to exit.
+    pub async fn wait(&self, pid: u32) -> std::io::Result<ExitStatus> {
+        let (tx, rx) = channel();
+        self.subscribe(pid, Subscriber::Status(tx));
+        rx.await.map_err(|_| {
+            error!("monitor dropped before {} exited.", pid);
+            std::io::ErrorKind::BrokenPipe.into()
+        })
+    }
+
+    fn subscribe(&self, pid: u32, subscriber: Subscriber) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .entry(pid)
+            .or_default()
+            .push(subscriber);
+        // The process may have exited before it was registered, its SIGCHLD is gone then.
+        reap(&self.subscribers);
+    }
+}
+
+/// Lifecycle event of a container, see [`events_channel`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerEvent {
+    /// The init process exited, the container is stopped
+    Exit { code: i32, at: OffsetDateTime },
+    /// A process of the container was killed for running out of memory
+    Oom,
+    /// Failure to get the events, the exit may still come
+    Error(String),
+}
+
+/// Merge the exit of a container's init, e.g. from [`Monitor::start`], with its OOM events,
+/// e.g. from [`Runc::oom_events`](crate::Runc::oom_events), into a single channel.
+///
+/// The exit is the last event, OOM events already pending at that point are delivered before.
+pub fn events_channel(
+    exit: Receiver<Exit>,
+    mut oom: BoxStream<'static, Result<Event, Error>>,
+) -> mpsc::Receiver<ContainerEvent> {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut exit = exit;
+        let mut oom_done = false;
+        loop {
+            let event = tokio::select! {
+                biased;
+                event = oom.next(), if !oom_done => match event {
+                    Some(Ok(_)) => ContainerEvent::Oom,
+                    Some(Err(e)) => ContainerEvent::Error(e.to_string()),
+                    None => {
+                        oom_done = true;
+                        continue;
+                    }
+                },
+                exit = &mut exit => {
+                    while let Some(Some(Ok(_))) = oom.next().now_or_never() {
+                        if tx.send(ContainerEvent::Oom).await.is_err() {
+                            return;
+                        }
+                    }
+                    let event = match exit {
+                        Ok(exit) => ContainerEvent::Exit {
+                            code: exit.status,
+                            at: exit.ts,
+                        },
+                        Err(_) => ContainerEvent::Error("exit notification dropped".to_string()),
+                    };
+                    let _ = tx.send(event).await;
+                    return;
+                }
+            };
+            if tx.send(event).await.is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// Reap the registered pids which exited and notify their subscribers.
+fn reap(subscribers: &Mutex<HashMap<u32, Vec<Subscriber>>>) {
+    let mut subscribers = subscribers.lock().unwrap();
+    let mut exited = vec![];
+    for pid in subscribers.keys() {
+        // Raw wait statuses, as understood by ExitStatus::from_raw().
+        let (status, raw) = match waitpid(Pid::from_raw(*pid as i32), Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(_, code)) => (code, (code & 0xff) << 8),
+            Ok(WaitStatus::Signaled(_, sig, _)) => (128 + sig as i32, sig as i32),
+            Ok(_) => continue,
+            Err(e) => {
+                // Not our child yet, e.g. a container init still parented by runc, it's tried
+                // again on the next SIGCHLD once it was reparented to us.
+                debug!("failed to wait for {}: {}", pid, e);
+                continue;
+            }
+        };
+        exited.push((*pid, status, raw));
+    }
+    let ts = OffsetDateTime::now_utc();
+    for (pid, status, raw) in exited {
+        for subscriber in subscribers.remove(&pid).unwrap_or_default() {
+            let _ = match subscriber {
+                Subscriber::Exit(tx) => tx.send(Exit { ts, pid, status }).map_err(|_| ()),
+                Subscriber::Status(tx) => tx.send(ExitStatus::from_raw(raw)).map_err(|_| ()),
+            };
+        }
+    }
+}
+
 /// Execution result returned by `execute()`.
 pub struct ExecuteResult {
     pub exit: Exit,
@@ -138,6 +310,39 @@ mod tests {
 
     use super::*;
 
+    #[tokio::test]
+    async fn test_events_channel() {
+        let (exit_tx, exit_rx) = channel();
+        let (oom_tx, oom_rx) = futures::channel::mpsc::unbounded();
+        let mut events = events_channel(exit_rx, oom_rx.boxed());
+
+        oom_tx
+            .unbounded_send(Event::from_line(r#"{"type":"oom","id":"fake-id"}"#))
+            .unwrap();
+        assert_eq!(events.recv().await, Some(ContainerEvent::Oom));
+
+        oom_tx.unbounded_send(Event::from_line("not json")).unwrap();
+        assert!(matches!(
+            events.recv().await,
+            Some(ContainerEvent::Error(_))
+        ));
+
+        let at = OffsetDateTime::now_utc();
+        exit_tx
+            .send(Exit {
+                ts: at,
+                pid: 100,
+                status: 137,
+            })
+            .unwrap();
+        assert_eq!(
+            events.recv().await,
+            Some(ContainerEvent::Exit { code: 137, at })
+        );
+        // Nothing comes after the exit.
+        assert_eq!(events.recv().await, None);
+    }
+
     #[tokio::test]
     async fn test_start_wait_without_output() {
         let monitor = DefaultMonitor::new();
@@ -165,6 +370,52 @@ mod tests {
         assert_eq!(status.status, 0);
     }
 
+    #[tokio::test]
+    async fn test_monitor_wait() {
+        let monitor = Monitor::new().unwrap();
+
+        let child = std::process::Command::new("/bin/true").spawn().unwrap();
+        let rx = monitor.start(child.id());
+        let exit = rx.await.unwrap();
+        assert_eq!(exit.pid, child.id());
+        assert_eq!(exit.status, 0);
+
+        let child = std::process::Command::new("/bin/sh")
+            .args(["-c", "sleep 0.1; exit 3"])
+            .spawn()
+            .unwrap();
+        let status = monitor.wait(child.id()).await.unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let mut child = std::process::Command::new("/bin/sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let wait = tokio::spawn({
+            let monitor = monitor.clone();
+            async move { monitor.wait(pid).await }
+        });
+        child.kill().unwrap();
+        let status = wait.await.unwrap().unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+    }
+
+    #[tokio::test]
+    async fn test_monitor_not_a_child() {
+        let monitor = Monitor::new().unwrap();
+        // The parent of the test process can't be waited for, it isn't reported as exited.
+        let ppid = nix::unistd::getppid().as_raw() as u32;
+        let mut rx = monitor.start(ppid);
+
+        // Another child exiting makes the monitor reap again.
+        let child = std::process::Command::new("/bin/true").spawn().unwrap();
+        monitor.wait(child.id()).await.unwrap();
+        let exit = tokio::time::timeout(std::time::Duration::from_millis(200), &mut rx).await;
+        assert!(exit.is_err(), "unexpected exit: {:?}", exit);
+        assert!(monitor.subscribers.lock().unwrap().contains_key(&ppid));
+    }
+
     #[tokio::test]
     async fn test_execute() {
         let mut cmd = Command::new("/bin/ls");
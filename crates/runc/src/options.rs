@@ -34,12 +34,21 @@
  */
 
 use std::{
+    fs::File,
+    net::SocketAddr,
     path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
     time::Duration,
 };
 
-use crate::{error::Error, io::Io, utils, DefaultExecutor, LogFormat, Runc, Spawner};
+use crate::{
+    error::Error,
+    io::Io,
+    utils::{self, RootlessDecision, RootlessProbe},
+    CappedExecutor, Command, CommandHook, LogFormat, Runc, Spawner, Version,
+    DEFAULT_MAX_OUTPUT_BYTES,
+};
 
 // constants for log format
 pub const JSON: &str = "json";
@@ -51,7 +60,8 @@ const LOG: &str = "--log";
 const LOG_FORMAT: &str = "--log-format";
 const ROOT: &str = "--root";
 const ROOTLESS: &str = "--rootless";
-const SYSTEMD_CGROUP: &str = "--systemd-cgroup";
+pub(crate) const SYSTEMD_CGROUP: &str = "--systemd-cgroup";
+const CRIU: &str = "--criu";
 
 // constants for runc-create/runc-exec flags
 const CONSOLE_SOCKET: &str = "--console-socket";
@@ -59,6 +69,8 @@ const DETACH: &str = "--detach";
 const NO_NEW_KEYRING: &str = "--no-new-keyring";
 const NO_PIVOT: &str = "--no-pivot";
 const PID_FILE: &str = "--pid-file";
+const PRESERVE_FDS: &str = "--preserve-fds";
+const CAP: &str = "--cap";
 
 // constants for runc-kill flags
 const ALL: &str = "--all";
@@ -66,6 +78,21 @@ const ALL: &str = "--all";
 // constants for runc-delete flags
 const FORCE: &str = "--force";
 
+// constants for runc-checkpoint flags
+const IMAGE_PATH: &str = "--image-path";
+const WORK_PATH: &str = "--work-path";
+const LEAVE_RUNNING: &str = "--leave-running";
+const TCP_ESTABLISHED: &str = "--tcp-established";
+const EXT_UNIX_SK: &str = "--ext-unix-sk";
+const SHELL_JOB: &str = "--shell-job";
+const FILE_LOCKS: &str = "--file-locks";
+const PRE_DUMP: &str = "--pre-dump";
+const PARENT_PATH: &str = "--parent-path";
+const LAZY_PAGES: &str = "--lazy-pages";
+const STATUS_FD: &str = "--status-fd";
+const PAGE_SERVER: &str = "--page-server";
+const EMPTY_NS: &str = "--empty-ns";
+
 // constant for command
 pub const DEFAULT_COMMAND: &str = "runc";
 
@@ -99,16 +126,54 @@ pub struct GlobalOpts {
     /// Note that "auto" is different from explicit "true" or "false".
     rootless: Option<bool>,
     /// Set process group ID (gpid).
+    ///
+    /// runc is started in a group of its own, which is killed along with runc when an async
+    /// command is dropped or times out.
     set_pgid: bool,
     /// Use systemd cgroup.
     systemd_cgroup: bool,
     /// Timeout settings for runc command.
     ///
-    /// Default is 5 seconds.
+    /// If [`None`], commands never time out.
     /// This will be used only in AsyncClient.
-    timeout: Duration,
+    timeout: Option<Duration>,
     /// executor that runs the commands
     executor: Option<Arc<dyn Spawner + Send + Sync>>,
+    /// Soft and hard limits of RLIMIT_NOFILE for the runc process.
+    rlimit_nofile: Option<(u64, u64)>,
+    /// Keep the temp spec files of failed commands.
+    keep_spec_files: bool,
+    /// Directory of the temp spec files, `XDG_RUNTIME_DIR` or the temp dir if unset.
+    spec_dir: Option<PathBuf>,
+    /// Check the bundle's spec before `create` and `run`.
+    validate_bundles: bool,
+    /// Environment variables set for the runc process.
+    env: Vec<(String, String)>,
+    /// Start the runc process with an empty environment instead of inheriting ours.
+    env_clear: bool,
+    /// Path to the criu binary used for checkpoint and restore.
+    ///
+    /// If [`None`], runc looks up `criu` in `PATH`.
+    criu: Option<PathBuf>,
+    /// Global flags not modeled here, appended after the other global flags.
+    extra_args: Vec<String>,
+    /// Source of the facts for the rootless auto detection, the host if [`None`].
+    rootless_probe: Option<Arc<dyn RootlessProbe>>,
+    /// Don't check the binary exists, is executable and is recent enough.
+    skip_validation: bool,
+    /// Oldest runc version accepted by [`GlobalOpts::build`].
+    min_version: Option<Version>,
+    /// Cap of the stdout and stderr kept from each command.
+    ///
+    /// If [`None`], [`DEFAULT_MAX_OUTPUT_BYTES`] is used.
+    max_output_bytes: Option<usize>,
+    /// Called with each runc command right before it's spawned.
+    command_hook: Option<CommandHook>,
+    /// Maximum number of commands run at the same time by the async client and its clones.
+    ///
+    /// If [`None`], there is no limit.
+    #[cfg(feature = "async")]
+    max_concurrent_commands: Option<usize>,
 }
 
 impl GlobalOpts {
@@ -179,18 +244,61 @@ impl GlobalOpts {
     }
 
     /// Set rootless mode to auto.
+    ///
+    /// Rootless mode, and the systemd cgroup driver when systemd delegated a cgroup v2 subtree,
+    /// are enabled when not running as root or running in a user namespace. See
+    /// [`utils::should_use_rootless`].
     pub fn rootless_auto(mut self) -> Self {
         self.rootless = None;
         self
     }
 
+    /// Rootless mode as set explicitly, [`None`] in auto mode.
+    ///
+    /// The outcome of the auto detection is given by [`Runc::rootless_decision`] once built.
+    pub fn is_rootless(&self) -> Option<bool> {
+        self.rootless
+    }
+
+    /// Start runc in a new process group, so a cancelled or timed out async command kills the
+    /// whole runc subtree instead of runc only. Default is off.
     pub fn set_pgid(mut self, set_pgid: bool) -> Self {
         self.set_pgid = set_pgid;
         self
     }
 
     pub fn timeout(&mut self, millis: u64) -> &mut Self {
-        self.timeout = Duration::from_millis(millis);
+        self.timeout = Some(Duration::from_millis(millis));
+        self
+    }
+
+    /// Let commands run as long as they need, which is the default.
+    pub fn timeout_none(&mut self) -> &mut Self {
+        self.timeout = None;
+        self
+    }
+
+    /// Run at most `max` commands at the same time, the others wait for one to finish. `max`
+    /// must be at least 1, [`build`](Self::build) fails otherwise.
+    ///
+    /// The limit is shared by the clones of the built client. Waiting doesn't count against
+    /// the timeout, and long-lived processes started by [`Runc::spawn`] aren't limited.
+    /// [`Runc::stats_many`] runs up to 8 commands at once on its own, each of them counts
+    /// against this limit too so a lower one wins.
+    #[cfg(feature = "async")]
+    pub fn max_concurrent_commands(mut self, max: usize) -> Self {
+        self.max_concurrent_commands = Some(max);
+        self
+    }
+
+    /// Adjust each runc command right before it's spawned, after the client has set its
+    /// arguments, environment and stdio, e.g. to renice runc or set up its process in a
+    /// `pre_exec` closure. The program itself can't be replaced.
+    ///
+    /// The command is a [`tokio::process::Command`] with the `async` feature, a
+    /// [`std::process::Command`] otherwise.
+    pub fn command_hook(mut self, hook: impl Fn(&mut Command) + Send + Sync + 'static) -> Self {
+        self.command_hook = Some(CommandHook(Arc::new(hook)));
         self
     }
 
@@ -199,17 +307,152 @@ impl GlobalOpts {
         self
     }
 
+    /// Set the soft and hard limits of open files (RLIMIT_NOFILE) for the runc process itself.
+    ///
+    /// Useful when runc needs many fds for mounts and pipes. This is only supported on Linux.
+    pub fn rlimit_nofile(mut self, soft: u64, hard: u64) -> Self {
+        self.rlimit_nofile = Some((soft, hard));
+        self
+    }
+
+    /// Write the temporary spec files passed to `exec` and `update` in `dir`, created with
+    /// mode 0700 if missing.
+    ///
+    /// Defaults to `XDG_RUNTIME_DIR`, or the temp dir if it's unset. This is needed when
+    /// neither is writable, e.g. with a read-only rootfs.
+    pub fn spec_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.spec_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Keep at most `max` bytes of the stdout and stderr of each command, the rest is read and
+    /// dropped and [`crate::Response::truncated`] is set. Default is 16 MiB.
+    ///
+    /// Custom spawners still buffer the whole output, it's only cut afterwards.
+    pub fn max_output_bytes(mut self, max: usize) -> Self {
+        self.max_output_bytes = Some(max);
+        self
+    }
+
+    /// Keep the temporary spec files passed to `exec` and `update` when the command fails.
+    ///
+    /// The path of the kept file is logged, it's left for the caller to remove. Default is off.
+    pub fn keep_spec_files(mut self, keep: bool) -> Self {
+        self.keep_spec_files = keep;
+        self
+    }
+
+    /// Check the `config.json` of the bundle with [`Runc::validate_bundle`] before `create`
+    /// and `run`, failing early with a precise error. Default is off.
+    pub fn validate_bundles(mut self, validate: bool) -> Self {
+        self.validate_bundles = validate;
+        self
+    }
+
+    /// Set an environment variable for the runc process, e.g. `XDG_RUNTIME_DIR` for rootless runc.
+    ///
+    /// `NOTIFY_SOCKET` is removed from the inherited environment but can be set explicitly here.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Don't inherit the environment of the current process, which may contain variables
+    /// changing runc's behavior like `LISTEN_FDS`. Only `PATH`, `XDG_RUNTIME_DIR` and
+    /// variables set with [`GlobalOpts::env`] are passed to runc.
+    pub fn env_clear(mut self, env_clear: bool) -> Self {
+        self.env_clear = env_clear;
+        self
+    }
+
+    /// Set the path to the criu binary, e.g. a bundled one instead of the one in `PATH`.
+    ///
+    /// [`GlobalOpts::build`] fails if it doesn't exist or isn't executable.
+    pub fn criu(mut self, criu: impl AsRef<Path>) -> Self {
+        self.criu = Some(criu.as_ref().to_path_buf());
+        self
+    }
+
+    /// Pass additional global flags, e.g. runsc's `--platform` or crun's `--cgroup-manager`.
+    ///
+    /// They're placed after the other global flags and before the subcommand of every call.
+    pub fn extra_args(mut self, args: impl IntoIterator<Item = String>) -> Self {
+        self.extra_args.extend(args);
+        self
+    }
+
+    /// Replace the probe used by rootless auto detection, e.g. to test the decision.
+    pub fn rootless_probe(mut self, probe: Arc<dyn RootlessProbe>) -> Self {
+        self.rootless_probe = Some(probe);
+        self
+    }
+
+    /// Fail to build unless `runc --version` reports at least `min`.
+    ///
+    /// The binary is run once, when building.
+    pub fn verify_version(mut self, min: Version) -> Self {
+        self.min_version = Some(min);
+        self
+    }
+
+    /// Use the command as is, e.g. with fake binaries in tests.
+    ///
+    /// By default [`GlobalOpts::build`] checks it resolves with `PATH` to an executable file,
+    /// and is recent enough if [`GlobalOpts::verify_version`] is set.
+    pub fn skip_validation(mut self) -> Self {
+        self.skip_validation = true;
+        self
+    }
+
     pub fn build(self) -> Result<Runc, Error> {
         self.args()
     }
 
+    /// Check `command` is as recent as required, it's run with `--version` for that.
+    fn check_version(&self, command: &Path) -> Result<(), Error> {
+        let min = match &self.min_version {
+            Some(min) if !self.skip_validation => min,
+            _ => return Ok(()),
+        };
+        let output = std::process::Command::new(command)
+            .arg("--version")
+            .output()
+            .map_err(Error::ProcessSpawnFailed)?;
+        let found = Version::parse(&String::from_utf8_lossy(&output.stdout))?;
+        if found.at_least(min) {
+            Ok(())
+        } else {
+            Err(Error::VersionTooOld {
+                found: found.runc_version.unwrap_or_default(),
+                required: min.runc_version.clone().unwrap_or_default(),
+            })
+        }
+    }
+
+    fn rootless_decision(&self) -> Option<RootlessDecision> {
+        if self.rootless.is_some() {
+            return None;
+        }
+        Some(match &self.rootless_probe {
+            Some(probe) => utils::rootless_decision(probe.as_ref()),
+            None => utils::should_use_rootless(),
+        })
+    }
+
     fn output(&self) -> Result<(PathBuf, Vec<String>), Error> {
         let path = self
             .command
             .clone()
             .unwrap_or_else(|| PathBuf::from("runc"));
 
-        let command = utils::binary_path(path).ok_or(Error::NotFound)?;
+        let command = match utils::binary_path(&path) {
+            Some(command) => command,
+            None if self.skip_validation => path,
+            None => return Err(Error::BinaryNotFound(path)),
+        };
+        if !self.skip_validation && !utils::is_executable(&command) {
+            return Err(Error::BinaryNotExecutable(command));
+        }
 
         let mut args = Vec::new();
 
@@ -234,16 +477,31 @@ impl GlobalOpts {
         args.push(LOG_FORMAT.into());
         args.push(self.log_format.to_string());
 
+        // Leave the mode to runc unless the detection says rootless, runc's own auto mode
+        // doesn't consider the systemd delegation.
+        let decision = self.rootless_decision().filter(|d| d.rootless);
+
         // --systemd-cgroup : Enable systemd cgroup support.
-        if self.systemd_cgroup {
+        if self.systemd_cgroup || decision.map_or(false, |d| d.systemd_cgroup) {
             args.push(SYSTEMD_CGROUP.into());
         }
 
         // --rootless true|false|auto : Enable or disable rootless mode.
-        if let Some(mode) = self.rootless {
+        if let Some(mode) = self.rootless.or_else(|| decision.map(|d| d.rootless)) {
             let arg = format!("{}={}", ROOTLESS, mode);
             args.push(arg);
         }
+
+        // --criu path : Path to the criu binary used for checkpoint and restore.
+        if let Some(criu) = &self.criu {
+            if !utils::is_executable(criu) {
+                return Err(Error::InvalidCriuPath(criu.clone()));
+            }
+            args.push(CRIU.into());
+            args.push(utils::abs_string(criu)?);
+        }
+
+        args.extend(self.extra_args.iter().cloned());
         Ok((command, args))
     }
 }
@@ -252,16 +510,45 @@ impl Args for GlobalOpts {
     type Output = Result<Runc, Error>;
 
     fn args(&self) -> Self::Output {
+        #[cfg(feature = "async")]
+        if self.max_concurrent_commands == Some(0) {
+            return Err(Error::NoConcurrentCommands);
+        }
         let (command, args) = self.output()?;
+        self.check_version(&command)?;
+        let json_log = match (&self.log, &self.log_format) {
+            (Some(log), LogFormat::Json) => Some(PathBuf::from(utils::abs_string(log)?)),
+            _ => None,
+        };
+        let max_output_bytes = self.max_output_bytes.unwrap_or(DEFAULT_MAX_OUTPUT_BYTES);
         let executor = if let Some(exec) = self.executor.clone() {
             exec
         } else {
-            Arc::new(DefaultExecutor {})
+            Arc::new(CappedExecutor::new(max_output_bytes))
         };
         Ok(Runc {
             command,
             args,
             spawner: executor,
+            timeout: self.timeout,
+            rlimit_nofile: self.rlimit_nofile,
+            json_log,
+            keep_spec_files: self.keep_spec_files,
+            spec_dir: self.spec_dir.clone(),
+            validate_bundles: self.validate_bundles,
+            env: self.env.clone(),
+            env_clear: self.env_clear,
+            cgroup_version: Default::default(),
+            rootless_decision: self.rootless_decision(),
+            max_output_bytes,
+            set_pgid: self.set_pgid,
+            command_hook: self.command_hook.clone(),
+            #[cfg(feature = "async")]
+            command_limit: self
+                .max_concurrent_commands
+                .map(|max| Arc::new(tokio::sync::Semaphore::new(max))),
+            #[cfg(feature = "async")]
+            in_flight: Default::default(),
         })
     }
 }
@@ -271,7 +558,7 @@ pub struct CreateOpts {
     pub io: Option<Arc<dyn Io>>,
     /// Path to where a pid file should be created.
     pub pid_file: Option<PathBuf>,
-    /// Path to where a console socket should be created.
+    /// Path to a socket runc sends the pty master to, see [`crate::console::ConsoleSocket`].
     pub console_socket: Option<PathBuf>,
     /// Detach from the container's process (only available for run)
     pub detach: bool,
@@ -279,6 +566,20 @@ pub struct CreateOpts {
     pub no_pivot: bool,
     /// A new session keyring for the container will not be created.
     pub no_new_keyring: bool,
+    /// Files passed to the container process in addition to stdio.
+    ///
+    /// They are inherited in order as fds 3, 4, ... 3+N-1, runc is told to keep them
+    /// with `--preserve-fds N`.
+    pub extra_files: Vec<Arc<File>>,
+    /// Use the systemd cgroup driver or not for this container, [`None`] keeps the client's
+    /// setting.
+    pub systemd_cgroup: Option<bool>,
+    /// Succeed if a container with the id was already created, e.g. when retrying a create.
+    ///
+    /// Only a container still in the `created` state from the same bundle is accepted, its pid
+    /// is the response's and written to the pid file.
+    /// Handled by the client, not passed to runc.
+    pub ignore_existing: bool,
 }
 
 impl Args for CreateOpts {
@@ -303,6 +604,10 @@ impl Args for CreateOpts {
         if self.detach {
             args.push(DETACH.to_string());
         }
+        if !self.extra_files.is_empty() {
+            args.push(PRESERVE_FDS.to_string());
+            args.push(self.extra_files.len().to_string());
+        }
         Ok(args)
     }
 }
@@ -347,6 +652,23 @@ impl CreateOpts {
         self.no_new_keyring = no_new_keyring;
         self
     }
+
+    /// Override the client's [`GlobalOpts::systemd_cgroup`] for this container.
+    pub fn systemd_cgroup(mut self, systemd_cgroup: Option<bool>) -> Self {
+        self.systemd_cgroup = systemd_cgroup;
+        self
+    }
+
+    /// Pass `files` to the container process as fds 3, 4, ... in order.
+    pub fn extra_files(mut self, files: Vec<File>) -> Self {
+        self.extra_files = files.into_iter().map(Arc::new).collect();
+        self
+    }
+
+    pub fn ignore_existing(mut self, ignore_existing: bool) -> Self {
+        self.ignore_existing = ignore_existing;
+        self
+    }
 }
 
 /// Container execution options
@@ -355,10 +677,17 @@ pub struct ExecOpts {
     pub io: Option<Arc<dyn Io>>,
     /// Path to where a pid file should be created.
     pub pid_file: Option<PathBuf>,
-    /// Path to where a console socket should be created.
+    /// Path to a socket runc sends the pty master to, see [`crate::console::ConsoleSocket`].
     pub console_socket: Option<PathBuf>,
     /// Detach from the container's process (only available for run)
     pub detach: bool,
+    /// Files passed to the container process in addition to stdio.
+    ///
+    /// They are inherited in order as fds 3, 4, ... 3+N-1, runc is told to keep them
+    /// with `--preserve-fds N`.
+    pub extra_files: Vec<Arc<File>>,
+    /// Capabilities added to the process, e.g. `CAP_NET_ADMIN`.
+    pub caps: Vec<String>,
 }
 
 impl Args for ExecOpts {
@@ -377,6 +706,14 @@ impl Args for ExecOpts {
         if self.detach {
             args.push(DETACH.to_string());
         }
+        if !self.extra_files.is_empty() {
+            args.push(PRESERVE_FDS.to_string());
+            args.push(self.extra_files.len().to_string());
+        }
+        for cap in &self.caps {
+            args.push(CAP.to_string());
+            args.push(cap.clone());
+        }
         Ok(args)
     }
 }
@@ -411,6 +748,17 @@ impl ExecOpts {
         self.detach = detach;
         self
     }
+
+    /// Pass `files` to the container process as fds 3, 4, ... in order.
+    pub fn extra_files(mut self, files: Vec<File>) -> Self {
+        self.extra_files = files.into_iter().map(Arc::new).collect();
+        self
+    }
+
+    pub fn cap(mut self, cap: impl Into<String>) -> Self {
+        self.caps.push(cap.into());
+        self
+    }
 }
 
 /// Container deletion options
@@ -418,6 +766,11 @@ impl ExecOpts {
 pub struct DeleteOpts {
     /// Forcibly delete the container if it is still running
     pub force: bool,
+    /// Check with `runc state` that the container is gone after the deletion
+    pub verify: bool,
+    /// Succeed if the container doesn't exist, making deletion idempotent, see
+    /// [`CommandErrorKind::NotFound`](crate::error::CommandErrorKind::NotFound)
+    pub ignore_not_found: bool,
 }
 
 impl Args for DeleteOpts {
@@ -441,13 +794,111 @@ impl DeleteOpts {
         self.force = force;
         self
     }
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    pub fn ignore_not_found(mut self, ignore_not_found: bool) -> Self {
+        self.ignore_not_found = ignore_not_found;
+        self
+    }
+}
+
+/// Retries of [`Runc::delete_force`](crate::Runc::delete_force)
+#[derive(Debug, Clone)]
+pub struct ForceDeleteOpts {
+    /// Time to wait for the container to stop after it was killed
+    pub stop_timeout: Duration,
+    /// Number of times the forced deletion is retried
+    pub retries: usize,
+    /// Delay before the first retry, doubled after each attempt
+    pub backoff: Duration,
+}
+
+impl Default for ForceDeleteOpts {
+    fn default() -> Self {
+        Self {
+            stop_timeout: Duration::from_secs(10),
+            retries: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl ForceDeleteOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stop_timeout(mut self, timeout: Duration) -> Self {
+        self.stop_timeout = timeout;
+        self
+    }
+
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+}
+
+/// Signal sent with [`Runc::kill`](crate::Runc::kill), parsed from a name like `SIGKILL` or
+/// `TERM`, or from a number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signal(u32);
+
+impl Signal {
+    pub fn number(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for Signal {
+    fn from(sig: u32) -> Self {
+        Self(sig)
+    }
+}
+
+impl FromStr for Signal {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(sig) = s.parse::<u32>() {
+            return Ok(Self(sig));
+        }
+        let name = s.to_ascii_uppercase();
+        let name = if name.starts_with("SIG") {
+            name
+        } else {
+            format!("SIG{}", name)
+        };
+        nix::sys::signal::Signal::from_str(&name)
+            .map(|sig| Self(sig as u32))
+            .map_err(|_| Error::InvalidSignal(s.to_string()))
+    }
 }
 
 /// Container killing options
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct KillOpts {
     /// Seng the kill signal to all the processes inside the container
     pub all: bool,
+    /// Succeed when the process has already exited, on by default like go-runc.
+    pub ignore_not_running: bool,
+}
+
+impl Default for KillOpts {
+    fn default() -> Self {
+        Self {
+            all: false,
+            ignore_not_running: true,
+        }
+    }
 }
 
 impl Args for KillOpts {
@@ -471,11 +922,171 @@ impl KillOpts {
         self.all = all;
         self
     }
+
+    /// Succeed when the process has already exited, default is on. Otherwise the error is
+    /// classified as [`crate::error::CommandErrorKind::NotRunning`].
+    pub fn ignore_not_running(mut self, ignore: bool) -> Self {
+        self.ignore_not_running = ignore;
+        self
+    }
+}
+
+/// Container checkpoint options
+///
+/// See <https://github.com/opencontainers/runc/blob/main/man/runc-checkpoint.8.md>
+#[derive(Debug, Clone, Default)]
+pub struct CheckpointOpts {
+    /// Directory the images are written to, runc defaults to `checkpoint` in its cwd.
+    pub image_path: Option<PathBuf>,
+    /// Directory of the criu logs and work files.
+    pub work_path: Option<PathBuf>,
+    /// Leave the container running after the checkpoint.
+    pub leave_running: bool,
+    /// Allow open tcp connections.
+    pub tcp_established: bool,
+    /// Allow external unix sockets.
+    pub ext_unix_sk: bool,
+    /// Allow shell jobs.
+    pub shell_job: bool,
+    /// Allow file locks.
+    pub file_locks: bool,
+    /// Only dump the memory, for the first rounds of an iterative migration.
+    pub pre_dump: bool,
+    /// Images of the previous pre-dump, relative to the image path.
+    pub parent_path: Option<PathBuf>,
+    /// Leave the memory to be fetched from a lazy pages daemon on restore.
+    pub lazy_pages: bool,
+    /// File criu writes to once the lazy pages are ready to be served.
+    ///
+    /// It's inherited by runc as fd 3, which is passed to `--status-fd`.
+    pub status_file: Option<Arc<File>>,
+    /// Send the pages to the criu page server at this address.
+    pub page_server: Option<SocketAddr>,
+    /// Namespaces to create empty on restore instead of checkpointing, e.g. `network`.
+    pub empty_namespaces: Vec<String>,
+}
+
+impl Args for CheckpointOpts {
+    type Output = Result<Vec<String>, Error>;
+
+    fn args(&self) -> Self::Output {
+        let mut args: Vec<String> = vec![];
+        if let Some(image_path) = &self.image_path {
+            args.push(IMAGE_PATH.to_string());
+            args.push(utils::abs_string(image_path)?);
+        }
+        if let Some(work_path) = &self.work_path {
+            args.push(WORK_PATH.to_string());
+            args.push(utils::abs_string(work_path)?);
+        }
+        let flags = [
+            (self.leave_running, LEAVE_RUNNING),
+            (self.tcp_established, TCP_ESTABLISHED),
+            (self.ext_unix_sk, EXT_UNIX_SK),
+            (self.shell_job, SHELL_JOB),
+            (self.file_locks, FILE_LOCKS),
+            (self.pre_dump, PRE_DUMP),
+            (self.lazy_pages, LAZY_PAGES),
+        ];
+        for (set, flag) in flags {
+            if set {
+                args.push(flag.to_string());
+            }
+        }
+        if let Some(parent_path) = &self.parent_path {
+            args.push(PARENT_PATH.to_string());
+            args.push(parent_path.to_string_lossy().to_string());
+        }
+        if self.status_file.is_some() {
+            args.push(STATUS_FD.to_string());
+            args.push("3".to_string());
+        }
+        if let Some(page_server) = &self.page_server {
+            args.push(PAGE_SERVER.to_string());
+            args.push(page_server.to_string());
+        }
+        for ns in &self.empty_namespaces {
+            args.push(EMPTY_NS.to_string());
+            args.push(ns.clone());
+        }
+        Ok(args)
+    }
+}
+
+impl CheckpointOpts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn image_path(mut self, image_path: impl AsRef<Path>) -> Self {
+        self.image_path = Some(image_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn work_path(mut self, work_path: impl AsRef<Path>) -> Self {
+        self.work_path = Some(work_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn leave_running(mut self, leave_running: bool) -> Self {
+        self.leave_running = leave_running;
+        self
+    }
+
+    pub fn tcp_established(mut self, tcp_established: bool) -> Self {
+        self.tcp_established = tcp_established;
+        self
+    }
+
+    pub fn ext_unix_sk(mut self, ext_unix_sk: bool) -> Self {
+        self.ext_unix_sk = ext_unix_sk;
+        self
+    }
+
+    pub fn shell_job(mut self, shell_job: bool) -> Self {
+        self.shell_job = shell_job;
+        self
+    }
+
+    pub fn file_locks(mut self, file_locks: bool) -> Self {
+        self.file_locks = file_locks;
+        self
+    }
+
+    pub fn pre_dump(mut self, pre_dump: bool) -> Self {
+        self.pre_dump = pre_dump;
+        self
+    }
+
+    pub fn parent_path(mut self, parent_path: impl AsRef<Path>) -> Self {
+        self.parent_path = Some(parent_path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn lazy_pages(mut self, lazy_pages: bool) -> Self {
+        self.lazy_pages = lazy_pages;
+        self
+    }
+
+    pub fn status_file(mut self, file: File) -> Self {
+        self.status_file = Some(Arc::new(file));
+        self
+    }
+
+    pub fn page_server(mut self, addr: SocketAddr) -> Self {
+        self.page_server = Some(addr);
+        self
+    }
+
+    pub fn empty_namespace(mut self, ns: impl Into<String>) -> Self {
+        self.empty_namespaces.push(ns.into());
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::env;
+    use std::{env, os::unix::fs::PermissionsExt};
 
     use super::*;
 
@@ -530,6 +1141,23 @@ mod tests {
                 "--detach".to_string(),
             ]
         );
+
+        assert_eq!(
+            CreateOpts::new()
+                .no_pivot(false)
+                .no_new_keyring(false)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![String::new(); 0]
+        );
+        assert_eq!(
+            CreateOpts::new()
+                .no_pivot(false)
+                .no_new_keyring(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec!["--no-new-keyring".to_string()]
+        );
     }
 
     #[test]
@@ -572,6 +1200,93 @@ mod tests {
             ExecOpts::new().detach(true).args().expect(ARGS_FAIL_MSG),
             vec!["--detach".to_string(),]
         );
+
+        assert_eq!(
+            ExecOpts::new()
+                .console_socket("/run/console.sock")
+                .cap("CAP_NET_ADMIN")
+                .cap("CAP_SYS_PTRACE")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--console-socket",
+                "/run/console.sock",
+                "--cap",
+                "CAP_NET_ADMIN",
+                "--cap",
+                "CAP_SYS_PTRACE",
+            ]
+        );
+
+        let files = vec![File::open("/dev/null").unwrap(), File::open("/").unwrap()];
+        assert_eq!(
+            ExecOpts::new()
+                .extra_files(files)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec!["--preserve-fds".to_string(), "2".to_string()]
+        );
+    }
+
+    #[test]
+    fn checkpoint_opts_test() {
+        assert_eq!(
+            CheckpointOpts::new().args().expect(ARGS_FAIL_MSG),
+            vec![String::new(); 0]
+        );
+
+        assert_eq!(
+            CheckpointOpts::new()
+                .image_path("/var/lib/images")
+                .work_path("/tmp/criu")
+                .leave_running(true)
+                .tcp_established(true)
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--image-path",
+                "/var/lib/images",
+                "--work-path",
+                "/tmp/criu",
+                "--leave-running",
+                "--tcp-established",
+            ]
+        );
+
+        assert_eq!(
+            CheckpointOpts::new()
+                .pre_dump(true)
+                .parent_path("../pre-dump-1")
+                .lazy_pages(true)
+                .status_file(File::open("/dev/null").unwrap())
+                .page_server("192.168.1.10:27".parse().unwrap())
+                .empty_namespace("network")
+                .empty_namespace("ipc")
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec![
+                "--pre-dump",
+                "--lazy-pages",
+                "--parent-path",
+                "../pre-dump-1",
+                "--status-fd",
+                "3",
+                "--page-server",
+                "192.168.1.10:27",
+                "--empty-ns",
+                "network",
+                "--empty-ns",
+                "ipc",
+            ]
+        );
+
+        assert_eq!(
+            CheckpointOpts::new()
+                .page_server("[::1]:27".parse().unwrap())
+                .args()
+                .expect(ARGS_FAIL_MSG),
+            vec!["--page-server", "[::1]:27"]
+        );
     }
 
     #[test]
@@ -585,6 +1300,16 @@ mod tests {
             DeleteOpts::new().force(true).args(),
             vec!["--force".to_string()],
         );
+
+        // Handled by the client, not passed to runc.
+        assert_eq!(
+            DeleteOpts::new()
+                .force(true)
+                .verify(true)
+                .ignore_not_found(true)
+                .args(),
+            vec!["--force".to_string()],
+        );
     }
 
     #[test]
@@ -592,19 +1317,200 @@ mod tests {
         assert_eq!(KillOpts::new().all(false).args(), vec![String::new(); 0]);
 
         assert_eq!(KillOpts::new().all(true).args(), vec!["--all".to_string()],);
+
+        assert!(KillOpts::new().ignore_not_running);
+        assert_eq!(
+            KillOpts::new().ignore_not_running(false).args(),
+            vec![String::new(); 0]
+        );
+    }
+
+    #[test]
+    fn signal_test() {
+        for (name, sig) in [
+            ("SIGHUP", libc::SIGHUP),
+            ("SIGINT", libc::SIGINT),
+            ("SIGKILL", libc::SIGKILL),
+            ("SIGUSR1", libc::SIGUSR1),
+            ("SIGTERM", libc::SIGTERM),
+            ("SIGSTOP", libc::SIGSTOP),
+            ("SIGCONT", libc::SIGCONT),
+            ("TERM", libc::SIGTERM),
+            ("kill", libc::SIGKILL),
+            ("SigUsr2", libc::SIGUSR2),
+            ("9", libc::SIGKILL),
+        ] {
+            assert_eq!(
+                name.parse::<Signal>().unwrap().number(),
+                sig as u32,
+                "{}",
+                name
+            );
+        }
+        assert_eq!(Signal::from(15).number(), 15);
+
+        for name in ["SIGFOO", "", "SIG", "-1"] {
+            match name.parse::<Signal>() {
+                Err(Error::InvalidSignal(s)) => assert_eq!(s, name),
+                r => panic!("unexpected result for {:?}: {:?}", name, r),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct FakeProbe(u32);
+
+    impl RootlessProbe for FakeProbe {
+        fn euid(&self) -> u32 {
+            self.0
+        }
+
+        fn uid_map(&self) -> Option<String> {
+            Some("0 0 4294967295".to_string())
+        }
+
+        fn cgroup2_delegated(&self, _euid: u32) -> bool {
+            true
+        }
+    }
+
+    /// Root inside a user namespace mapped to unprivileged host ids.
+    #[derive(Debug)]
+    struct UserNsProbe;
+
+    impl RootlessProbe for UserNsProbe {
+        fn euid(&self) -> u32 {
+            0
+        }
+
+        fn uid_map(&self) -> Option<String> {
+            Some("         0     100000      65536\n".to_string())
+        }
+
+        fn cgroup2_delegated(&self, _euid: u32) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn rootless_auto_test() {
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .rootless_auto()
+            .rootless_probe(Arc::new(UserNsProbe));
+        assert_eq!(cfg.is_rootless(), None);
+        let runc = cfg.build().unwrap();
+        assert!(runc.args.contains(&"--rootless=true".to_string()));
+        assert!(!runc.args.contains(&SYSTEMD_CGROUP.to_string()));
+        assert_eq!(runc.rootless_decision().map(|d| d.rootless), Some(true));
+
+        let cfg = GlobalOpts::default().command("true").rootless(false);
+        assert_eq!(cfg.is_rootless(), Some(false));
+    }
+
+    #[test]
+    fn criu_opts_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let criu = dir.path().join("criu");
+        std::fs::write(&criu, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&criu, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let runc = GlobalOpts::default()
+            .command("true")
+            .criu(&criu)
+            .build()
+            .unwrap();
+        let pos = runc.args.iter().position(|a| a == CRIU).unwrap();
+        assert_eq!(runc.args[pos + 1], criu.to_string_lossy());
+
+        std::fs::set_permissions(&criu, std::fs::Permissions::from_mode(0o644)).unwrap();
+        for path in [criu, dir.path().join("missing"), dir.path().to_path_buf()] {
+            match GlobalOpts::default().command("true").criu(&path).build() {
+                Err(Error::InvalidCriuPath(p)) => assert_eq!(p, path),
+                r => panic!("unexpected result for {}: {:?}", path.display(), r),
+            }
+        }
+    }
+
+    #[test]
+    fn validation_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        match GlobalOpts::default().command(&missing).build() {
+            Err(Error::BinaryNotFound(p)) => assert_eq!(p, missing),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        GlobalOpts::default()
+            .command(&missing)
+            .skip_validation()
+            .build()
+            .unwrap();
+
+        let runc = dir.path().join("runc");
+        std::fs::write(
+            &runc,
+            "#!/bin/sh\necho 'runc version 1.0.2'\necho 'spec: 1.0.2-dev'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&runc, std::fs::Permissions::from_mode(0o644)).unwrap();
+        match GlobalOpts::default().command(&runc).build() {
+            Err(Error::BinaryNotExecutable(p)) => assert_eq!(p, runc),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        std::fs::set_permissions(&runc, std::fs::Permissions::from_mode(0o755)).unwrap();
+        for min in ["1.0.0", "1.0.2", "1.0.2-rc.1", "0.9"] {
+            GlobalOpts::default()
+                .command(&runc)
+                .verify_version(Version::new(min))
+                .build()
+                .unwrap();
+        }
+        match GlobalOpts::default()
+            .command(&runc)
+            .verify_version(Version::new("1.1.0"))
+            .build()
+        {
+            Err(Error::VersionTooOld { found, required }) => {
+                assert_eq!(found, "1.0.2");
+                assert_eq!(required, "1.1.0");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        GlobalOpts::default()
+            .command(&runc)
+            .verify_version(Version::new("1.1.0"))
+            .skip_validation()
+            .build()
+            .unwrap();
+
+        let version = Version::parse(
+            "runc version 1.1.4\ncommit: v1.1.4-0-g5fd4c4d1\nspec: 1.0.2-dev\ngo: go1.18\n",
+        )
+        .unwrap();
+        assert_eq!(version.runc_version.as_deref(), Some("1.1.4"));
+        assert_eq!(version.commit.as_deref(), Some("v1.1.4-0-g5fd4c4d1"));
+        assert_eq!(version.spec_version.as_deref(), Some("1.0.2-dev"));
+        assert!(matches!(
+            Version::parse("crun version 1.5"),
+            Err(Error::InvalidVersion)
+        ));
     }
 
-    #[cfg(target_os = "linux")]
     #[test]
     fn global_opts_test() {
-        let cfg = GlobalOpts::default().command("true");
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .rootless_probe(Arc::new(FakeProbe(0)));
         let runc = cfg.build().unwrap();
         let args = &runc.args;
         assert_eq!(args.len(), 2);
         assert!(args.contains(&LOG_FORMAT.to_string()));
         assert!(args.contains(&TEXT.to_string()));
 
-        let cfg = GlobalOpts::default().command("/bin/true");
+        let cfg = GlobalOpts::default()
+            .command("/bin/true")
+            .rootless_probe(Arc::new(FakeProbe(0)));
         let runc = cfg.build().unwrap();
         assert_eq!(runc.args.len(), 2);
 
@@ -628,5 +1534,54 @@ mod tests {
         assert!(args.contains(&"--rootless=true".to_string()));
         assert!(args.contains(&SYSTEMD_CGROUP.to_string()));
         assert_eq!(args.len(), 9);
+
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .rootless_probe(Arc::new(FakeProbe(1000)));
+        let runc = cfg.build().unwrap();
+        assert!(runc.args.contains(&"--rootless=true".to_string()));
+        assert!(runc.args.contains(&SYSTEMD_CGROUP.to_string()));
+        assert_eq!(runc.rootless_decision().map(|d| d.rootless), Some(true));
+
+        let cfg = GlobalOpts::default()
+            .command("true")
+            .rootless(false)
+            .rootless_probe(Arc::new(FakeProbe(1000)));
+        let runc = cfg.build().unwrap();
+        assert!(runc.args.contains(&"--rootless=false".to_string()));
+        assert!(!runc.args.contains(&SYSTEMD_CGROUP.to_string()));
+        assert_eq!(runc.rootless_decision(), None);
+    }
+
+    #[test]
+    fn global_args_test() {
+        for debug in [false, true] {
+            for rootless in [false, true] {
+                for json in [false, true] {
+                    let runc = GlobalOpts::default()
+                        .command("true")
+                        .root("/run/fake")
+                        .debug(debug)
+                        .rootless(rootless)
+                        .log_format(if json {
+                            LogFormat::Json
+                        } else {
+                            LogFormat::Text
+                        })
+                        .build()
+                        .unwrap();
+
+                    let mut expected = vec![ROOT, "/run/fake"];
+                    if debug {
+                        expected.push(DEBUG);
+                    }
+                    expected.push(LOG_FORMAT);
+                    expected.push(if json { JSON } else { TEXT });
+                    let rootless = format!("{}={}", ROOTLESS, rootless);
+                    expected.push(&rootless);
+                    assert_eq!(runc.global_args(), expected.as_slice());
+                }
+            }
+        }
     }
 }
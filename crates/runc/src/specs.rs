@@ -0,0 +1,532 @@
+/*
+   Copyright The containerd Authors.
+
+   Licensed under the Apache License, Version 2.0 (the "License");
+   you may not use this file except in compliance with the License.
+   You may obtain a copy of the License at
+
+       http://www.apache.org/licenses/LICENSE-2.0
+
+   Unless required by applicable law or agreed to in writing, software
+   distributed under the License is distributed on an "AS IS" BASIS,
+   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+   See the License for the specific language governing permissions and
+   limitations under the License.
+*/
+
+//! Helpers to generate OCI runtime specs, e.g. for bundles created in tests.
+
+use std::path::Path;
+
+use oci_spec::runtime::{
+    Capability, LinuxBuilder, LinuxCapabilitiesBuilder, LinuxNamespaceBuilder, LinuxNamespaceType,
+    LinuxResources, Mount, MountBuilder, Process, ProcessBuilder as OciProcessBuilder, RootBuilder,
+    Spec, SpecBuilder, UserBuilder,
+};
+use serde_json::{Map, Value};
+
+use crate::error::Error;
+
+/// Version of the runtime spec the generated specs conform to.
+const OCI_VERSION: &str = "1.0.2";
+
+const DEFAULT_PATH_ENV: &str = "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Build the smallest spec runc accepts, running `args` as root in `/` of a read-only `rootfs`.
+///
+/// The container gets its own pid, ipc, uts, mount and network namespaces, and only
+/// `/proc`, `/dev`, `/dev/pts` and `/sys` are mounted.
+pub fn minimal_spec(args: &[&str], rootfs: &str) -> Spec {
+    let process = OciProcessBuilder::default()
+        .terminal(false)
+        .user(
+            UserBuilder::default()
+                .uid(0u32)
+                .gid(0u32)
+                .build()
+                .expect("build user"),
+        )
+        .args(args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+        .env(vec![DEFAULT_PATH_ENV.to_string()])
+        .cwd("/")
+        .no_new_privileges(true)
+        .build()
+        .expect("build process");
+
+    let root = RootBuilder::default()
+        .path(rootfs)
+        .readonly(true)
+        .build()
+        .expect("build root");
+
+    let namespaces = [
+        LinuxNamespaceType::Pid,
+        LinuxNamespaceType::Ipc,
+        LinuxNamespaceType::Uts,
+        LinuxNamespaceType::Mount,
+        LinuxNamespaceType::Network,
+    ]
+    .iter()
+    .map(|typ| {
+        LinuxNamespaceBuilder::default()
+            .typ(*typ)
+            .build()
+            .expect("build namespace")
+    })
+    .collect::<Vec<_>>();
+    let linux = LinuxBuilder::default()
+        .namespaces(namespaces)
+        .build()
+        .expect("build linux");
+
+    SpecBuilder::default()
+        .version(OCI_VERSION)
+        .hostname("runc")
+        .root(root)
+        .process(process)
+        .mounts(vec![
+            mount("/proc", "proc", "proc", &[]),
+            mount(
+                "/dev",
+                "tmpfs",
+                "tmpfs",
+                &["nosuid", "strictatime", "mode=755", "size=65536k"],
+            ),
+            mount(
+                "/dev/pts",
+                "devpts",
+                "devpts",
+                &[
+                    "nosuid",
+                    "noexec",
+                    "newinstance",
+                    "ptmxmode=0666",
+                    "mode=0620",
+                ],
+            ),
+            mount(
+                "/sys",
+                "sysfs",
+                "sysfs",
+                &["nosuid", "noexec", "nodev", "ro"],
+            ),
+        ])
+        .linux(linux)
+        .build()
+        .expect("build spec")
+}
+
+/// Builder of the [`Process`] passed to [`Runc::exec`](crate::Runc::exec).
+///
+/// By default the process runs as root in `/`, with `PATH` as only environment variable.
+#[derive(Debug, Clone)]
+pub struct ProcessBuilder {
+    args: Vec<String>,
+    env: Vec<String>,
+    cwd: String,
+    terminal: bool,
+    uid: u32,
+    gid: u32,
+    capabilities: Option<Vec<Capability>>,
+}
+
+impl ProcessBuilder {
+    pub fn new<I, S>(args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            args: args.into_iter().map(Into::into).collect(),
+            env: vec![DEFAULT_PATH_ENV.to_string()],
+            cwd: "/".to_string(),
+            terminal: false,
+            uid: 0,
+            gid: 0,
+            capabilities: None,
+        }
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set a variable, replacing any previous value of `key`.
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        let prefix = format!("{}=", key);
+        self.env.retain(|e| !e.starts_with(&prefix));
+        self.env.push(format!("{}{}", prefix, value));
+        self
+    }
+
+    pub fn cwd(mut self, cwd: impl Into<String>) -> Self {
+        self.cwd = cwd.into();
+        self
+    }
+
+    /// Allocate a pty, runc sends its master end to the console socket.
+    pub fn terminal(mut self, terminal: bool) -> Self {
+        self.terminal = terminal;
+        self
+    }
+
+    pub fn user(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Bounding, effective and permitted capabilities, the container's are kept if unset.
+    pub fn capabilities(mut self, capabilities: impl IntoIterator<Item = Capability>) -> Self {
+        self.capabilities = Some(capabilities.into_iter().collect());
+        self
+    }
+
+    pub fn build(self) -> Result<Process, Error> {
+        if self.args.is_empty() {
+            return Err(Error::InvalidSpec("process.args is empty".to_string()));
+        }
+        let invalid = |e: oci_spec::OciSpecError| Error::InvalidSpec(e.to_string());
+        let user = UserBuilder::default()
+            .uid(self.uid)
+            .gid(self.gid)
+            .build()
+            .map_err(invalid)?;
+        let mut builder = OciProcessBuilder::default()
+            .args(self.args)
+            .env(self.env)
+            .cwd(self.cwd)
+            .terminal(self.terminal)
+            .user(user);
+        if let Some(capabilities) = self.capabilities {
+            let set = capabilities
+                .into_iter()
+                .collect::<std::collections::HashSet<_>>();
+            let capabilities = LinuxCapabilitiesBuilder::default()
+                .bounding(set.clone())
+                .effective(set.clone())
+                .permitted(set)
+                .build()
+                .map_err(invalid)?;
+            builder = builder.capabilities(capabilities);
+        }
+        builder.build().map_err(invalid)
+    }
+}
+
+/// Check the fields runc requires are set in `spec`: `ociVersion`, a non-empty `process.args`
+/// and `root.path`.
+pub fn validate_spec(spec: &Spec) -> Result<(), Error> {
+    check_required_fields(&serde_json::to_value(spec)?)
+}
+
+/// Load the `config.json` of `bundle` and check it's a spec with the fields runc requires.
+pub fn validate_bundle(bundle: &Path) -> Result<(), Error> {
+    let content =
+        std::fs::read_to_string(bundle.join("config.json")).map_err(Error::FileSystemError)?;
+    let value: Value = serde_json::from_str(&content)?;
+    // Missing fields are reported first, they'd make the typed parsing fail less clearly.
+    check_required_fields(&value)?;
+    serde_json::from_value::<Spec>(value)?;
+    Ok(())
+}
+
+fn check_required_fields(spec: &Value) -> Result<(), Error> {
+    let non_empty_str = |pointer: &str| {
+        spec.pointer(pointer)
+            .and_then(Value::as_str)
+            .map_or(false, |s| !s.is_empty())
+    };
+    if !non_empty_str("/ociVersion") {
+        return Err(Error::InvalidSpec("ociVersion is missing".to_string()));
+    }
+    let args = spec.pointer("/process/args").and_then(Value::as_array);
+    if args.map_or(true, |args| args.is_empty()) {
+        return Err(Error::InvalidSpec("process.args is empty".to_string()));
+    }
+    if !non_empty_str("/root/path") {
+        return Err(Error::InvalidSpec("root.path is missing".to_string()));
+    }
+    Ok(())
+}
+
+/// Builder of the [`LinuxResources`] passed to [`Runc::update`](crate::Runc::update).
+///
+/// Only the fields which were set are serialized, runc leaves the other controllers untouched.
+/// Unlike `oci_spec::runtime::LinuxResourcesBuilder`, no defaults like a deny-all device
+/// rule are added.
+#[derive(Debug, Clone, Default)]
+pub struct LinuxResourcesBuilder {
+    memory: Map<String, Value>,
+    cpu: Map<String, Value>,
+    pids: Map<String, Value>,
+}
+
+impl LinuxResourcesBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Memory limit in bytes.
+    pub fn memory_limit(mut self, bytes: i64) -> Self {
+        self.memory.insert("limit".to_string(), bytes.into());
+        self
+    }
+
+    /// Limit of memory plus swap in bytes.
+    pub fn memory_swap(mut self, bytes: i64) -> Self {
+        self.memory.insert("swap".to_string(), bytes.into());
+        self
+    }
+
+    /// CPU time in microseconds the container may use per period.
+    pub fn cpu_quota(mut self, quota: i64) -> Self {
+        self.cpu.insert("quota".to_string(), quota.into());
+        self
+    }
+
+    /// Length of the CFS period in microseconds.
+    pub fn cpu_period(mut self, period: u64) -> Self {
+        self.cpu.insert("period".to_string(), period.into());
+        self
+    }
+
+    /// Relative CPU weight.
+    pub fn cpu_shares(mut self, shares: u64) -> Self {
+        self.cpu.insert("shares".to_string(), shares.into());
+        self
+    }
+
+    /// CPUs the container may run on, e.g. `0-3,6`.
+    pub fn cpuset_cpus(mut self, cpus: &str) -> Self {
+        self.cpu.insert("cpus".to_string(), cpus.into());
+        self
+    }
+
+    /// Maximum number of tasks, `-1` for unlimited.
+    pub fn pids_limit(mut self, limit: i64) -> Self {
+        self.pids.insert("limit".to_string(), limit.into());
+        self
+    }
+
+    pub fn build(self) -> Result<LinuxResources, Error> {
+        let mut resources = Map::new();
+        for (key, value) in [
+            ("memory", self.memory),
+            ("cpu", self.cpu),
+            ("pids", self.pids),
+        ] {
+            if !value.is_empty() {
+                resources.insert(key.to_string(), Value::Object(value));
+            }
+        }
+        serde_json::from_value(Value::Object(resources)).map_err(Error::JsonDeserializationFailed)
+    }
+}
+
+fn mount(destination: &str, typ: &str, source: &str, options: &[&str]) -> Mount {
+    MountBuilder::default()
+        .destination(destination)
+        .typ(typ)
+        .source(source)
+        .options(options.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+        .build()
+        .expect("build mount")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimal_spec() {
+        let spec = minimal_spec(&["sh", "-c", "echo hello"], "rootfs");
+        let value = serde_json::to_value(&spec).unwrap();
+
+        assert_eq!(value["ociVersion"], OCI_VERSION);
+        assert_eq!(value["root"]["path"], "rootfs");
+        assert_eq!(value["root"]["readonly"], true);
+        assert_eq!(
+            value["process"]["args"],
+            serde_json::json!(["sh", "-c", "echo hello"])
+        );
+        assert_eq!(value["process"]["cwd"], "/");
+        assert_eq!(value["process"]["env"][0], DEFAULT_PATH_ENV);
+        assert_eq!(value["process"]["user"]["uid"], 0);
+        assert!(value["linux"]["namespaces"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|ns| ns["type"] == "pid"));
+        assert!(value["mounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|m| m["destination"] == "/proc" && m["type"] == "proc"));
+
+        let decoded: Spec = serde_json::from_value(value).unwrap();
+        assert_eq!(decoded, spec);
+    }
+
+    #[test]
+    fn test_process_builder() {
+        let process = ProcessBuilder::new(["sh", "-c", "echo $GREETING"])
+            .env("GREETING", "hello")
+            .env("PATH", "/bin")
+            .cwd("/tmp")
+            .user(1000, 100)
+            .capabilities([Capability::Kill, Capability::NetBindService])
+            .build()
+            .unwrap();
+        let value = serde_json::to_value(&process).unwrap();
+        assert_eq!(
+            value["args"],
+            serde_json::json!(["sh", "-c", "echo $GREETING"])
+        );
+        assert_eq!(
+            value["env"],
+            serde_json::json!(["GREETING=hello", "PATH=/bin"])
+        );
+        assert_eq!(value["cwd"], "/tmp");
+        assert_eq!(value["user"]["uid"], 1000);
+        assert_eq!(value["user"]["gid"], 100);
+        assert_eq!(value["terminal"], false);
+        let mut effective: Vec<_> = value["capabilities"]["effective"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c.as_str().unwrap())
+            .collect();
+        effective.sort_unstable();
+        assert_eq!(effective, vec!["CAP_KILL", "CAP_NET_BIND_SERVICE"]);
+
+        let decoded: Process = serde_json::from_value(value).unwrap();
+        assert_eq!(decoded, process);
+
+        let process = ProcessBuilder::new(["true"]).build().unwrap();
+        assert_eq!(
+            process.env().as_ref().unwrap(),
+            &vec![DEFAULT_PATH_ENV.to_string()]
+        );
+        assert!(matches!(
+            ProcessBuilder::new(Vec::<String>::new()).build(),
+            Err(Error::InvalidSpec(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_bundle() {
+        let bundle = tempfile::tempdir().unwrap();
+        let config = bundle.path().join("config.json");
+        minimal_spec(&["true"], "rootfs").save(&config).unwrap();
+        validate_bundle(bundle.path()).unwrap();
+
+        let cases = [
+            ("/ociVersion", "ociVersion"),
+            ("/process/args", "process.args"),
+            ("/process", "process.args"),
+            ("/root", "root.path"),
+        ];
+        for (pointer, field) in cases {
+            let mut spec = serde_json::to_value(minimal_spec(&["true"], "rootfs")).unwrap();
+            let (parent, key) = pointer.rsplit_once('/').unwrap();
+            let parent = if parent.is_empty() {
+                &mut spec
+            } else {
+                spec.pointer_mut(parent).unwrap()
+            };
+            // An empty args array is as bad as a missing one.
+            if key == "args" {
+                parent[key] = serde_json::json!([]);
+            } else {
+                parent.as_object_mut().unwrap().remove(key);
+            }
+            std::fs::write(&config, spec.to_string()).unwrap();
+            match validate_bundle(bundle.path()) {
+                Err(Error::InvalidSpec(msg)) => assert!(msg.contains(field), "{}", msg),
+                r => panic!("unexpected result without {}: {:?}", pointer, r),
+            }
+        }
+
+        std::fs::write(&config, "{").unwrap();
+        assert!(matches!(
+            validate_bundle(bundle.path()),
+            Err(Error::JsonDeserializationFailed(_))
+        ));
+        std::fs::remove_file(&config).unwrap();
+        assert!(matches!(
+            validate_bundle(bundle.path()),
+            Err(Error::FileSystemError(_))
+        ));
+    }
+
+    #[test]
+    fn test_linux_resources_builder() {
+        let resources = LinuxResourcesBuilder::new()
+            .memory_limit(256 * 1024 * 1024)
+            .cpu_quota(50000)
+            .cpu_period(100000)
+            .pids_limit(64)
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&resources).unwrap(),
+            serde_json::json!({
+                "memory": {"limit": 268435456},
+                "cpu": {"quota": 50000, "period": 100000},
+                "pids": {"limit": 64}
+            })
+        );
+
+        let resources = LinuxResourcesBuilder::new()
+            .memory_swap(1024)
+            .cpu_shares(512)
+            .cpuset_cpus("0-3,6")
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&resources).unwrap(),
+            serde_json::json!({
+                "memory": {"swap": 1024},
+                "cpu": {"shares": 512, "cpus": "0-3,6"}
+            })
+        );
+
+        let resources = LinuxResourcesBuilder::new().build().unwrap();
+        assert_eq!(serde_json::to_string(&resources).unwrap(), "{}");
+    }
+
+    /// Creates a container from the spec with the runc found in `$PATH`, using the host's
+    /// root as rootfs. Skipped unless running as root with runc installed.
+    #[cfg(not(feature = "async"))]
+    #[test]
+    fn test_create_from_minimal_spec() {
+        use crate::{
+            options::{DeleteOpts, GlobalOpts},
+            utils,
+        };
+
+        if !nix::unistd::getuid().is_root() || utils::binary_path("runc").is_none() {
+            return;
+        }
+
+        let bundle = tempfile::tempdir().unwrap();
+        let state = tempfile::tempdir().unwrap();
+        minimal_spec(&["true"], "/")
+            .save(bundle.path().join("config.json"))
+            .unwrap();
+
+        let runc = GlobalOpts::new().root(state.path()).build().unwrap();
+        let id = "minimal-spec-test";
+        runc.create(id, bundle.path(), None).unwrap();
+        let container = runc.state(id).unwrap();
+        assert_eq!(container.status, "created");
+        runc.delete(id, Some(&DeleteOpts::new().force(true)))
+            .unwrap();
+    }
+}
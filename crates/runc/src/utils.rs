@@ -18,9 +18,12 @@
 use std::io::Write;
 use std::{
     env,
+    fs::DirBuilder,
+    os::unix::fs::DirBuilderExt,
     path::{Path, PathBuf},
 };
 
+use nix::unistd::{access, AccessFlags};
 use path_absolutize::*;
 use serde::Serialize;
 #[cfg(not(feature = "async"))]
@@ -64,49 +67,111 @@ where
 }
 
 /// Returns a temp dir. If the environment variable "XDG_RUNTIME_DIR" is set, return its value.
-/// Otherwise if `std::env::temp_dir()` failed, return current dir or return the temp dir depended on OS.
+/// Otherwise `/run` if it's writable, then the temp dir depended on OS, or the current dir.
 fn xdg_runtime_dir() -> String {
-    env::var("XDG_RUNTIME_DIR")
-        .unwrap_or_else(|_| abs_string(env::temp_dir()).unwrap_or_else(|_| ".".to_string()))
+    runtime_dir(env::var("XDG_RUNTIME_DIR").ok(), Path::new("/run"))
 }
 
-/// Write the serialized 'value' to a temp file
+fn runtime_dir(xdg_runtime_dir: Option<String>, run: &Path) -> String {
+    match xdg_runtime_dir {
+        Some(dir) if !dir.is_empty() => dir,
+        _ if access(run, AccessFlags::W_OK).is_ok() => run.display().to_string(),
+        _ => abs_string(env::temp_dir()).unwrap_or_else(|_| ".".to_string()),
+    }
+}
+
+/// Path of a new spec file in `dir`, defaulting to [`xdg_runtime_dir`].
+///
+/// The directory is created with mode 0700 if missing.
+fn spec_file_path(dir: Option<&Path>) -> Result<String, Error> {
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from(xdg_runtime_dir()),
+    };
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|source| Error::SpecFileCreationFailed {
+            path: dir.clone(),
+            source,
+        })?;
+    Ok(format!("{}/runc-process-{}", dir.display(), Uuid::new_v4()))
+}
+
+fn spec_file_error(path: &str) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    move |source| Error::SpecFileCreationFailed {
+        path: PathBuf::from(path),
+        source,
+    }
+}
+
+/// Write the serialized 'value' to a temp file in `dir`
+#[cfg(not(feature = "async"))]
+pub fn write_value_to_temp_file<T: Serialize>(
+    dir: Option<&Path>,
+    value: &T,
+) -> Result<(NamedTempFile, String), Error> {
+    let spec_json = serde_json::to_string(value).map_err(Error::JsonDeserializationFailed)?;
+    write_str_to_temp_file(dir, &spec_json)
+}
+
+/// Write `contents` as is to a temp file in `dir`
 #[cfg(not(feature = "async"))]
-pub fn write_value_to_temp_file<T: Serialize>(value: &T) -> Result<(NamedTempFile, String), Error> {
-    let filename = format!("{}/runc-process-{}", xdg_runtime_dir(), Uuid::new_v4());
+pub fn write_str_to_temp_file(
+    dir: Option<&Path>,
+    contents: &str,
+) -> Result<(NamedTempFile, String), Error> {
+    let filename = spec_file_path(dir)?;
     let mut temp_file = Builder::new()
         .prefix(&filename)
         .rand_bytes(0)
         .tempfile()
-        .map_err(Error::SpecFileCreationFailed)?;
+        .map_err(spec_file_error(&filename))?;
     let f = temp_file.as_file_mut();
-    let spec_json = serde_json::to_string(value).map_err(Error::JsonDeserializationFailed)?;
-    f.write(spec_json.as_bytes())
-        .map_err(Error::SpecFileCreationFailed)?;
-    f.flush().map_err(Error::SpecFileCreationFailed)?;
+    f.write_all(contents.as_bytes())
+        .map_err(spec_file_error(&filename))?;
+    f.flush().map_err(spec_file_error(&filename))?;
     Ok((temp_file, filename))
 }
 
-/// Write the serialized 'value' to a temp file
+/// Write the serialized 'value' to a temp file in `dir`
 /// Unlike the same function in non-async feature,
 /// it returns the filename, without the NamedTempFile object,
 /// which implements Drop trait to remove the file if it goes out of scope.
 /// the async Drop is still not supported in rust,
 /// in async context, the created file should be removed by the caller
 #[cfg(feature = "async")]
-pub async fn write_value_to_temp_file<T: Serialize>(value: &T) -> Result<String, Error> {
-    let filename = format!("{}/runc-process-{}", xdg_runtime_dir(), Uuid::new_v4());
+pub async fn write_value_to_temp_file<T: Serialize>(
+    dir: Option<&Path>,
+    value: &T,
+) -> Result<String, Error> {
+    let spec_json = serde_json::to_string(value).map_err(Error::JsonDeserializationFailed)?;
+    write_str_to_temp_file(dir, &spec_json).await
+}
+
+/// Write `contents` as is to a temp file in `dir`, to be removed by the caller
+///
+/// The file is only readable by us, it's removed here if it can't be written.
+#[cfg(feature = "async")]
+pub async fn write_str_to_temp_file(dir: Option<&Path>, contents: &str) -> Result<String, Error> {
+    let filename = spec_file_path(dir)?;
     let mut f = tokio::fs::OpenOptions::new()
-        .create(true)
+        .create_new(true)
         .write(true)
+        .mode(0o600)
         .open(&filename)
         .await
-        .map_err(Error::FileSystemError)?;
-    let spec_json = serde_json::to_string(value).map_err(Error::JsonDeserializationFailed)?;
-    f.write_all(spec_json.as_bytes())
-        .await
-        .map_err(Error::SpecFileCreationFailed)?;
-    f.flush().await.map_err(Error::SpecFileCreationFailed)?;
+        .map_err(spec_file_error(&filename))?;
+    let written = async {
+        f.write_all(contents.as_bytes()).await?;
+        f.flush().await
+    }
+    .await;
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(&filename).await;
+        return Err(spec_file_error(&filename)(e));
+    }
     Ok(filename)
 }
 
@@ -130,3 +195,304 @@ where
         })
     })
 }
+
+/// Whether `path` is a regular file with an execute bit set.
+pub fn is_executable(path: impl AsRef<Path>) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.as_ref()
+        .metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Read the pid runc wrote to a `--pid-file`.
+pub fn read_pid_file(path: impl AsRef<Path>) -> std::io::Result<u32> {
+    let content = std::fs::read_to_string(path)?;
+    content
+        .trim()
+        .parse()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Check `id` is a container id runc accepts: letters, digits and `_+-.`, but not `.` or `..`.
+///
+/// Ids are used as directory names in runc's state dir, this rejects ids escaping it.
+pub fn validate_container_id(id: &str) -> Result<(), Error> {
+    let valid = !id.is_empty()
+        && id != "."
+        && id != ".."
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidIdError(id.to_string()))
+    }
+}
+
+/// Whether the mount at `mount_point` in `mountinfo`, the content of `/proc/<pid>/mountinfo`,
+/// is read-only, `None` if nothing is mounted there.
+///
+/// The last mount wins when there are several at the same place. A mount is read-only if
+/// either the mount or its superblock is, a remount of a read-only bind mount may leave the
+/// mount writable.
+pub fn mount_readonly(mountinfo: &str, mount_point: &str) -> Option<bool> {
+    let is_ro = |options: &str| options.split(',').any(|o| o == "ro");
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, sb) = line.split_once(" - ")?;
+            let mut fields = mount.split_whitespace().skip(4);
+            if fields.next()? != mount_point {
+                return None;
+            }
+            let mount_options = fields.next()?;
+            let sb_options = sb.split_whitespace().nth(2).unwrap_or_default();
+            Some(is_ro(mount_options) || is_ro(sb_options))
+        })
+        .last()
+}
+
+/// Cgroup hierarchy mounted on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupVersion {
+    /// Only cgroup v1 controllers are mounted.
+    V1,
+    /// cgroup v1 controllers with the v2 hierarchy mounted at `unified`, controllers are
+    /// still managed by v1.
+    Hybrid,
+    /// The unified cgroup v2 hierarchy.
+    V2,
+}
+
+/// Detect the cgroup version from the layout of the cgroup mount, usually `/sys/fs/cgroup`.
+pub fn cgroup_version(mount: impl AsRef<Path>) -> CgroupVersion {
+    let mount = mount.as_ref();
+    if mount.join("cgroup.controllers").exists() {
+        CgroupVersion::V2
+    } else if mount.join("unified").join("cgroup.controllers").exists() {
+        CgroupVersion::Hybrid
+    } else {
+        CgroupVersion::V1
+    }
+}
+
+/// Facts about the current process deciding whether runc has to run in rootless mode.
+///
+/// [`HostProbe`] reads them from the host, tests can substitute their own.
+pub trait RootlessProbe: std::fmt::Debug + Send + Sync {
+    /// Effective uid of the current process.
+    fn euid(&self) -> u32;
+    /// Content of `/proc/self/uid_map`, [`None`] if it can't be read.
+    fn uid_map(&self) -> Option<String>;
+    /// Whether a cgroup v2 subtree writable by `euid` was delegated by systemd.
+    fn cgroup2_delegated(&self, euid: u32) -> bool;
+}
+
+#[derive(Debug, Default)]
+pub struct HostProbe;
+
+impl RootlessProbe for HostProbe {
+    fn euid(&self) -> u32 {
+        nix::unistd::geteuid().as_raw()
+    }
+
+    fn uid_map(&self) -> Option<String> {
+        std::fs::read_to_string("/proc/self/uid_map").ok()
+    }
+
+    fn cgroup2_delegated(&self, euid: u32) -> bool {
+        let mount = Path::new("/sys/fs/cgroup");
+        if cgroup_version(mount) != CgroupVersion::V2 {
+            return false;
+        }
+        let service = mount.join(format!(
+            "user.slice/user-{uid}.slice/user@{uid}.service",
+            uid = euid
+        ));
+        nix::unistd::access(service.as_path(), nix::unistd::AccessFlags::W_OK).is_ok()
+    }
+}
+
+/// Outcome of the rootless auto detection, see [`should_use_rootless`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RootlessDecision {
+    /// Pass `--rootless=true`.
+    pub rootless: bool,
+    /// Pass `--systemd-cgroup`, cgroups can only be managed through systemd's delegation.
+    pub systemd_cgroup: bool,
+}
+
+/// Whether runc has to run rootless: when not root, or root inside a user namespace.
+pub fn should_use_rootless() -> RootlessDecision {
+    rootless_decision(&HostProbe)
+}
+
+pub fn rootless_decision(probe: &dyn RootlessProbe) -> RootlessDecision {
+    let euid = probe.euid();
+    // The initial user namespace maps the whole uid range, "0 0 4294967295".
+    let in_user_ns = probe
+        .uid_map()
+        .map(|m| m.split_whitespace().collect::<Vec<_>>() != ["0", "0", "4294967295"])
+        .unwrap_or(false);
+    let rootless = euid != 0 || in_user_ns;
+    RootlessDecision {
+        rootless,
+        systemd_cgroup: rootless && probe.cgroup2_delegated(euid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_cgroup_version() {
+        let v1 = tempfile::tempdir().unwrap();
+        fs::create_dir_all(v1.path().join("memory")).unwrap();
+        fs::create_dir_all(v1.path().join("cpu,cpuacct")).unwrap();
+        assert_eq!(cgroup_version(v1.path()), CgroupVersion::V1);
+
+        let hybrid = tempfile::tempdir().unwrap();
+        fs::create_dir_all(hybrid.path().join("memory")).unwrap();
+        fs::create_dir_all(hybrid.path().join("unified")).unwrap();
+        fs::write(hybrid.path().join("unified/cgroup.controllers"), "").unwrap();
+        assert_eq!(cgroup_version(hybrid.path()), CgroupVersion::Hybrid);
+
+        let v2 = tempfile::tempdir().unwrap();
+        fs::write(v2.path().join("cgroup.controllers"), "cpu io memory pids").unwrap();
+        assert_eq!(cgroup_version(v2.path()), CgroupVersion::V2);
+    }
+
+    #[test]
+    fn test_runtime_dir() {
+        let run = tempfile::tempdir().unwrap();
+        assert_eq!(
+            runtime_dir(Some("/run/user/1000".to_string()), run.path()),
+            "/run/user/1000"
+        );
+        assert_eq!(
+            runtime_dir(Some(String::new()), run.path()),
+            run.path().display().to_string()
+        );
+        assert_eq!(
+            runtime_dir(None, &run.path().join("missing")),
+            abs_string(env::temp_dir()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_read_pid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pid");
+        fs::write(&path, "1234").unwrap();
+        assert_eq!(read_pid_file(&path).unwrap(), 1234);
+        fs::write(&path, "1234\n").unwrap();
+        assert_eq!(read_pid_file(&path).unwrap(), 1234);
+        fs::write(&path, "").unwrap();
+        assert_eq!(
+            read_pid_file(&path).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            read_pid_file(dir.path().join("missing"))
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_validate_container_id() {
+        for id in ["foo", "my-container_1.2", "a+b", "0123456789abcdef", "..."] {
+            assert!(validate_container_id(id).is_ok(), "{}", id);
+        }
+        for id in [
+            "",
+            ".",
+            "..",
+            "a/b",
+            "/abs",
+            "../escape",
+            "with space",
+            "tab\t",
+            "ünï",
+        ] {
+            assert!(
+                matches!(validate_container_id(id), Err(Error::InvalidIdError(_))),
+                "{}",
+                id
+            );
+        }
+    }
+
+    #[derive(Debug)]
+    struct FakeProbe {
+        euid: u32,
+        uid_map: Option<&'static str>,
+        delegated: bool,
+    }
+
+    impl RootlessProbe for FakeProbe {
+        fn euid(&self) -> u32 {
+            self.euid
+        }
+
+        fn uid_map(&self) -> Option<String> {
+            self.uid_map.map(String::from)
+        }
+
+        fn cgroup2_delegated(&self, _euid: u32) -> bool {
+            self.delegated
+        }
+    }
+
+    #[test]
+    fn test_mount_readonly() {
+        let mountinfo = "\
+1 0 253:1 /var/lib/bundle/rootfs / ro,relatime master:1 - ext4 /dev/vda1 rw
+2 1 0:5 / /proc rw,nosuid,nodev,noexec,relatime - proc proc rw
+3 1 0:6 / /dev rw,nosuid - tmpfs tmpfs rw,size=65536k,mode=755
+4 1 253:2 / /data rw,relatime - ext4 /dev/vdb ro
+";
+        assert_eq!(mount_readonly(mountinfo, "/"), Some(true));
+        assert_eq!(mount_readonly(mountinfo, "/proc"), Some(false));
+        assert_eq!(mount_readonly(mountinfo, "/data"), Some(true));
+        assert_eq!(mount_readonly(mountinfo, "/sys"), None);
+
+        // The read-only flag got lost when the rootfs was remounted.
+        let remounted = format!(
+            "{}5 1 253:1 /var/lib/bundle/rootfs / rw,relatime master:1 - ext4 /dev/vda1 rw\n",
+            mountinfo
+        );
+        assert_eq!(mount_readonly(&remounted, "/"), Some(false));
+    }
+
+    #[test]
+    fn test_rootless_decision() {
+        let host_map = Some("         0          0 4294967295\n");
+        let decide = |euid, uid_map, delegated| {
+            rootless_decision(&FakeProbe {
+                euid,
+                uid_map,
+                delegated,
+            })
+        };
+
+        // Real root.
+        let d = decide(0, host_map, true);
+        assert!(!d.rootless && !d.systemd_cgroup);
+        // Root in a user namespace.
+        let d = decide(0, Some("0 1000 1\n"), false);
+        assert!(d.rootless && !d.systemd_cgroup);
+        // Unprivileged user, with and without a delegated cgroup.
+        assert!(decide(1000, host_map, false).rootless);
+        assert!(decide(1000, host_map, true).systemd_cgroup);
+        // Unknown uid map, e.g. without procfs.
+        assert!(!decide(0, None, true).rootless);
+    }
+}
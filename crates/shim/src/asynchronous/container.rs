@@ -43,12 +43,20 @@ pub trait Container {
         exec_id_opt: Option<&str>,
     ) -> Result<(i32, i32, Option<OffsetDateTime>)>;
     async fn exec(&mut self, req: ExecProcessRequest) -> Result<()>;
+    async fn pause(&mut self) -> Result<()>;
+    async fn resume(&mut self) -> Result<()>;
     async fn resize_pty(&mut self, exec_id: Option<&str>, height: u32, width: u32) -> Result<()>;
+    async fn close_io(&mut self, exec_id: Option<&str>) -> Result<()>;
     async fn pid(&self) -> i32;
     async fn id(&self) -> String;
     async fn update(&mut self, resources: &LinuxResources) -> Result<()>;
     async fn stats(&self) -> Result<Metrics>;
     async fn all_processes(&self) -> Result<Vec<ProcessInfo>>;
+
+    /// Whether `pid` is the init or an exec process of this container, used to route exits.
+    async fn has_pid(&self, pid: i32) -> bool {
+        self.pid().await == pid
+    }
 }
 
 #[async_trait]
@@ -141,16 +149,35 @@ where
 
     async fn exec(&mut self, req: ExecProcessRequest) -> Result<()> {
         let exec_id = req.exec_id.to_string();
+        if self.processes.contains_key(&exec_id) {
+            return Err(Error::AlreadyExistsError(format!(
+                "exec process {}",
+                exec_id
+            )));
+        }
         let exec_process = self.process_factory.create(&req).await?;
         self.processes.insert(exec_id, exec_process);
         Ok(())
     }
 
+    async fn pause(&mut self) -> Result<()> {
+        self.init.pause().await
+    }
+
+    async fn resume(&mut self) -> Result<()> {
+        self.init.resume().await
+    }
+
     async fn resize_pty(&mut self, exec_id: Option<&str>, height: u32, width: u32) -> Result<()> {
         let process = self.get_mut_process(exec_id)?;
         process.resize_pty(height, width).await
     }
 
+    async fn close_io(&mut self, exec_id: Option<&str>) -> Result<()> {
+        let process = self.get_mut_process(exec_id)?;
+        process.close_io().await
+    }
+
     async fn pid(&self) -> i32 {
         self.init.pid().await
     }
@@ -182,6 +209,18 @@ where
     async fn all_processes(&self) -> Result<Vec<ProcessInfo>> {
         self.init.ps().await
     }
+
+    async fn has_pid(&self, pid: i32) -> bool {
+        if self.init.pid().await == pid {
+            return true;
+        }
+        for p in self.processes.values() {
+            if p.pid().await == pid {
+                return true;
+            }
+        }
+        false
+    }
 }
 
 impl<T, E, P> ContainerTemplate<T, E, P>
@@ -216,3 +255,95 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        asynchronous::processes::{ProcessLifecycle, ProcessTemplate},
+        io::Stdio,
+    };
+
+    struct FakeLifecycle;
+
+    #[async_trait]
+    impl ProcessLifecycle<FakeProcess> for FakeLifecycle {
+        async fn start(&self, _p: &mut FakeProcess) -> Result<()> {
+            Err(Error::Unimplemented("start".to_string()))
+        }
+
+        async fn kill(&self, _p: &mut FakeProcess, _signal: u32, _all: bool) -> Result<()> {
+            Err(Error::Unimplemented("kill".to_string()))
+        }
+
+        async fn delete(&self, _p: &mut FakeProcess) -> Result<()> {
+            Err(Error::Unimplemented("delete".to_string()))
+        }
+
+        async fn pause(&self, _p: &mut FakeProcess) -> Result<()> {
+            Err(Error::Unimplemented("pause".to_string()))
+        }
+
+        async fn resume(&self, _p: &mut FakeProcess) -> Result<()> {
+            Err(Error::Unimplemented("resume".to_string()))
+        }
+
+        async fn close_io(&self, _p: &mut FakeProcess) -> Result<()> {
+            Err(Error::Unimplemented("close_io".to_string()))
+        }
+
+        async fn update(&self, _p: &mut FakeProcess, _resources: &LinuxResources) -> Result<()> {
+            Err(Error::Unimplemented("update".to_string()))
+        }
+
+        async fn stats(&self, _p: &FakeProcess) -> Result<Metrics> {
+            Err(Error::Unimplemented("stats".to_string()))
+        }
+
+        async fn ps(&self, _p: &FakeProcess) -> Result<Vec<ProcessInfo>> {
+            Err(Error::Unimplemented("ps".to_string()))
+        }
+    }
+
+    type FakeProcess = ProcessTemplate<FakeLifecycle>;
+
+    struct FakeFactory;
+
+    #[async_trait]
+    impl ProcessFactory<FakeProcess> for FakeFactory {
+        async fn create(&self, _req: &ExecProcessRequest) -> Result<FakeProcess> {
+            Err(Error::Unimplemented("create".to_string()))
+        }
+    }
+
+    fn process(id: &str, pid: i32) -> FakeProcess {
+        let mut p = ProcessTemplate::new(id, Stdio::new("", "", "", false), FakeLifecycle);
+        p.pid = pid;
+        p
+    }
+
+    #[tokio::test]
+    async fn test_has_pid() {
+        let mut container = ContainerTemplate {
+            id: "fake".to_string(),
+            bundle: "/bundle".to_string(),
+            init: process("fake", 100),
+            process_factory: FakeFactory,
+            processes: HashMap::new(),
+        };
+        container
+            .processes
+            .insert("exec-1".to_string(), process("exec-1", 200));
+        container
+            .processes
+            .insert("exec-2".to_string(), process("exec-2", 300));
+
+        assert!(container.has_pid(100).await);
+        assert!(container.has_pid(200).await);
+        assert!(container.has_pid(300).await);
+        assert!(!container.has_pid(400).await);
+
+        container.processes.remove("exec-1");
+        assert!(!container.has_pid(200).await);
+    }
+}
@@ -26,7 +26,7 @@ use oci_spec::runtime::LinuxResources;
 use time::OffsetDateTime;
 use tokio::sync::oneshot::{channel, Receiver, Sender};
 
-use crate::{io::Stdio, ioctl_set_winsz, util::asyncify, Console};
+use crate::{error::Error, io::Stdio, ioctl_set_winsz, util::asyncify, Console};
 
 #[async_trait]
 pub trait Process {
@@ -36,10 +36,13 @@ pub trait Process {
     async fn state(&self) -> crate::Result<StateResponse>;
     async fn kill(&mut self, signal: u32, all: bool) -> crate::Result<()>;
     async fn delete(&mut self) -> crate::Result<()>;
+    async fn pause(&mut self) -> crate::Result<()>;
+    async fn resume(&mut self) -> crate::Result<()>;
     async fn wait_channel(&mut self) -> crate::Result<Receiver<()>>;
     async fn exit_code(&self) -> i32;
     async fn exited_at(&self) -> Option<OffsetDateTime>;
     async fn resize_pty(&mut self, height: u32, width: u32) -> crate::Result<()>;
+    async fn close_io(&mut self) -> crate::Result<()>;
     async fn update(&mut self, resources: &LinuxResources) -> crate::Result<()>;
     async fn stats(&self) -> crate::Result<Metrics>;
     async fn ps(&self) -> crate::Result<Vec<ProcessInfo>>;
@@ -50,6 +53,9 @@ pub trait ProcessLifecycle<P: Process> {
     async fn start(&self, p: &mut P) -> crate::Result<()>;
     async fn kill(&self, p: &mut P, signal: u32, all: bool) -> crate::Result<()>;
     async fn delete(&self, p: &mut P) -> crate::Result<()>;
+    async fn pause(&self, p: &mut P) -> crate::Result<()>;
+    async fn resume(&self, p: &mut P) -> crate::Result<()>;
+    async fn close_io(&self, p: &mut P) -> crate::Result<()>;
     async fn update(&self, p: &mut P, resources: &LinuxResources) -> crate::Result<()>;
     async fn stats(&self, p: &P) -> crate::Result<Metrics>;
     async fn ps(&self, p: &P) -> crate::Result<Vec<ProcessInfo>>;
@@ -132,6 +138,14 @@ where
         self.lifecycle.clone().delete(self).await
     }
 
+    async fn pause(&mut self) -> crate::Result<()> {
+        self.lifecycle.clone().pause(self).await
+    }
+
+    async fn resume(&mut self) -> crate::Result<()> {
+        self.lifecycle.clone().resume(self).await
+    }
+
     async fn wait_channel(&mut self) -> crate::Result<Receiver<()>> {
         let (tx, rx) = channel::<()>();
         if self.state != Status::STOPPED {
@@ -149,20 +163,24 @@ where
     }
 
     async fn resize_pty(&mut self, height: u32, width: u32) -> crate::Result<()> {
-        if let Some(console) = self.console.as_ref() {
-            let w = libc::winsize {
-                ws_row: height as u16,
-                ws_col: width as u16,
-                ws_xpixel: 0,
-                ws_ypixel: 0,
-            };
-            let fd = console.file.as_raw_fd();
-            asyncify(move || -> crate::Result<()> {
-                unsafe { ioctl_set_winsz(fd, &w).map(|_x| ()).map_err(Into::into) }
-            })
-            .await?;
-        }
-        Ok(())
+        let console = self.console.as_ref().ok_or_else(|| {
+            Error::FailedPreconditionError(format!("process {} has no console", self.id))
+        })?;
+        let w = libc::winsize {
+            ws_row: height as u16,
+            ws_col: width as u16,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let fd = console.file.as_raw_fd();
+        asyncify(move || -> crate::Result<()> {
+            unsafe { ioctl_set_winsz(fd, &w).map(|_x| ()).map_err(Into::into) }
+        })
+        .await
+    }
+
+    async fn close_io(&mut self) -> crate::Result<()> {
+        self.lifecycle.clone().close_io(self).await
     }
 
     async fn update(&mut self, resources: &LinuxResources) -> crate::Result<()> {
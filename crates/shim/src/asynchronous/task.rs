@@ -19,10 +19,13 @@ use std::{collections::HashMap, sync::Arc};
 use async_trait::async_trait;
 use containerd_shim_protos::{
     api::{
-        CloseIORequest, ConnectRequest, ConnectResponse, DeleteResponse, PidsRequest, PidsResponse,
-        StatsRequest, StatsResponse, UpdateTaskRequest,
+        CloseIORequest, ConnectRequest, ConnectResponse, DeleteResponse, PauseRequest, PidsRequest,
+        PidsResponse, ResumeRequest, StatsRequest, StatsResponse, UpdateTaskRequest,
+    },
+    events::task::{
+        TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskIO, TaskPaused, TaskResumed,
+        TaskStart,
     },
-    events::task::{TaskCreate, TaskDelete, TaskExecAdded, TaskExecStarted, TaskIO, TaskStart},
     protobuf::MessageDyn,
     shim_async::Task,
     ttrpc,
@@ -76,6 +79,10 @@ where
 }
 
 impl<F, C> TaskService<F, C> {
+    /// Lock the container `id`.
+    ///
+    /// The guard locks all the containers, it must not be held while waiting for something
+    /// another request does, like the exit of a process in `wait()`.
     pub async fn get_container(&self, id: &str) -> TtrpcResult<MappedMutexGuard<'_, C>> {
         let mut containers = self.containers.lock().await;
         containers.get_mut(id).ok_or_else(|| {
@@ -257,6 +264,30 @@ where
         Ok(Empty::new())
     }
 
+    async fn pause(&self, _ctx: &TtrpcContext, req: PauseRequest) -> TtrpcResult<Empty> {
+        info!("Pause request for {:?}", req);
+        let mut container = self.get_container(req.id()).await?;
+        container.pause().await?;
+        self.send_event(TaskPaused {
+            container_id: req.id.to_string(),
+            ..Default::default()
+        })
+        .await;
+        Ok(Empty::new())
+    }
+
+    async fn resume(&self, _ctx: &TtrpcContext, req: ResumeRequest) -> TtrpcResult<Empty> {
+        info!("Resume request for {:?}", req);
+        let mut container = self.get_container(req.id()).await?;
+        container.resume().await?;
+        self.send_event(TaskResumed {
+            container_id: req.id.to_string(),
+            ..Default::default()
+        })
+        .await;
+        Ok(Empty::new())
+    }
+
     async fn resize_pty(&self, _ctx: &TtrpcContext, req: ResizePtyRequest) -> TtrpcResult<Empty> {
         debug!(
             "Resize pty request for container {}, exec_id: {}",
@@ -269,8 +300,16 @@ where
         Ok(Empty::new())
     }
 
-    async fn close_io(&self, _ctx: &TtrpcContext, _req: CloseIORequest) -> TtrpcResult<Empty> {
-        // TODO call close_io of container
+    async fn close_io(&self, _ctx: &TtrpcContext, req: CloseIORequest) -> TtrpcResult<Empty> {
+        debug!(
+            "Close io request for container {}, exec_id: {}",
+            &req.id, &req.exec_id
+        );
+        if !req.stdin {
+            return Ok(Empty::new());
+        }
+        let mut container = self.get_container(req.id()).await?;
+        container.close_io(req.exec_id().as_option()).await?;
         Ok(Empty::new())
     }
 
@@ -360,3 +399,192 @@ where
         Ok(Empty::default())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use containerd_shim_protos::{
+        api::ProcessInfo, cgroups::metrics::Metrics, ttrpc::MessageHeader,
+    };
+    use time::OffsetDateTime;
+    use tokio::sync::oneshot::{channel, Receiver, Sender};
+
+    use super::*;
+    use crate::{error::Result, Error};
+
+    #[derive(Default)]
+    struct FakeContainer {
+        exited: bool,
+        waiters: Vec<Sender<()>>,
+    }
+
+    #[async_trait]
+    impl Container for FakeContainer {
+        async fn start(&mut self, _exec_id: Option<&str>) -> Result<i32> {
+            Ok(1)
+        }
+
+        async fn state(&self, _exec_id: Option<&str>) -> Result<StateResponse> {
+            // Give other requests a chance to run while the container is locked.
+            tokio::task::yield_now().await;
+            let mut resp = StateResponse::new();
+            resp.set_status(if self.exited {
+                Status::STOPPED
+            } else {
+                Status::RUNNING
+            });
+            Ok(resp)
+        }
+
+        async fn kill(&mut self, _exec_id: Option<&str>, _signal: u32, _all: bool) -> Result<()> {
+            self.exited = true;
+            for tx in self.waiters.drain(..) {
+                tx.send(()).unwrap_or_default();
+            }
+            Ok(())
+        }
+
+        async fn wait_channel(&mut self, _exec_id: Option<&str>) -> Result<Receiver<()>> {
+            let (tx, rx) = channel();
+            self.waiters.push(tx);
+            Ok(rx)
+        }
+
+        async fn get_exit_info(
+            &self,
+            _exec_id: Option<&str>,
+        ) -> Result<(i32, i32, Option<OffsetDateTime>)> {
+            Ok((1, 137, Some(OffsetDateTime::now_utc())))
+        }
+
+        async fn delete(
+            &mut self,
+            _exec_id_opt: Option<&str>,
+        ) -> Result<(i32, i32, Option<OffsetDateTime>)> {
+            Err(Error::Unimplemented("delete".to_string()))
+        }
+
+        async fn exec(&mut self, _req: ExecProcessRequest) -> Result<()> {
+            Err(Error::Unimplemented("exec".to_string()))
+        }
+
+        async fn pause(&mut self) -> Result<()> {
+            Err(Error::Unimplemented("pause".to_string()))
+        }
+
+        async fn resume(&mut self) -> Result<()> {
+            Err(Error::Unimplemented("resume".to_string()))
+        }
+
+        async fn resize_pty(
+            &mut self,
+            _exec_id: Option<&str>,
+            _height: u32,
+            _width: u32,
+        ) -> Result<()> {
+            Err(Error::Unimplemented("resize_pty".to_string()))
+        }
+
+        async fn close_io(&mut self, _exec_id: Option<&str>) -> Result<()> {
+            Err(Error::Unimplemented("close_io".to_string()))
+        }
+
+        async fn pid(&self) -> i32 {
+            1
+        }
+
+        async fn id(&self) -> String {
+            "fake".to_string()
+        }
+
+        async fn update(&mut self, _resources: &LinuxResources) -> Result<()> {
+            Err(Error::Unimplemented("update".to_string()))
+        }
+
+        async fn stats(&self) -> Result<Metrics> {
+            Err(Error::Unimplemented("stats".to_string()))
+        }
+
+        async fn all_processes(&self) -> Result<Vec<ProcessInfo>> {
+            Err(Error::Unimplemented("all_processes".to_string()))
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeFactory;
+
+    #[async_trait]
+    impl ContainerFactory<FakeContainer> for FakeFactory {
+        async fn create(&self, _ns: &str, _req: &CreateTaskRequest) -> Result<FakeContainer> {
+            Ok(FakeContainer::default())
+        }
+
+        async fn cleanup(&self, _ns: &str, _c: &FakeContainer) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn ctx() -> TtrpcContext {
+        TtrpcContext {
+            fd: -1,
+            mh: MessageHeader::default(),
+            metadata: HashMap::new(),
+            timeout_nano: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_wait_kill_state() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(16);
+        let service: Arc<TaskService<FakeFactory, FakeContainer>> = Arc::new(TaskService::new(
+            "test",
+            Arc::new(ExitSignal::default()),
+            tx,
+        ));
+        service
+            .containers
+            .lock()
+            .await
+            .insert("fake".to_string(), FakeContainer::default());
+
+        let waits: Vec<_> = (0..4)
+            .map(|_| {
+                let service = service.clone();
+                tokio::spawn(async move {
+                    let mut req = WaitRequest::new();
+                    req.set_id("fake".to_string());
+                    service.wait(&ctx(), req).await
+                })
+            })
+            .collect();
+        let states: Vec<_> = (0..4)
+            .map(|_| {
+                let service = service.clone();
+                tokio::spawn(async move {
+                    let mut req = StateRequest::new();
+                    req.set_id("fake".to_string());
+                    service.state(&ctx(), req).await
+                })
+            })
+            .collect();
+        // Let the waiters register before the process "exits".
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut req = KillRequest::new();
+        req.set_id("fake".to_string());
+        req.signal = 9;
+        service.kill(&ctx(), req).await.unwrap();
+
+        let all = async {
+            for wait in waits {
+                assert_eq!(wait.await.unwrap().unwrap().exit_status, 137);
+            }
+            for state in states {
+                state.await.unwrap().unwrap();
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), all)
+            .await
+            .expect("requests deadlocked on the containers lock");
+    }
+}
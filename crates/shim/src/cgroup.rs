@@ -27,6 +27,7 @@ use containerd_shim_protos::{
     shim::oci::Options,
 };
 use oci_spec::runtime::LinuxResources;
+use serde_json::Value;
 
 use crate::error::{Error, Result};
 
@@ -217,12 +218,86 @@ pub fn update_resources(pid: u32, resources: &LinuxResources) -> Result<()> {
     Ok(())
 }
 
+/// A single field which differs between two [`LinuxResources`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceChange {
+    /// Dotted path of the field, e.g. `memory.limit`.
+    pub field: String,
+    /// Value on the left hand side of the diff, [`None`] if unset.
+    pub requested: Option<Value>,
+    /// Value on the right hand side of the diff, [`None`] if unset.
+    pub applied: Option<Value>,
+}
+
+/// Fields which differ between requested and applied resources, sorted by path.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResourceDiff {
+    pub changes: Vec<ResourceChange>,
+}
+
+impl ResourceDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.changes.iter().map(|c| c.field.as_str())
+    }
+}
+
+/// Compare [`LinuxResources`], e.g. the ones requested by `update` and the
+/// limits effectively applied to the cgroup.
+pub trait ResourcesDiff {
+    fn diff(&self, other: &LinuxResources) -> ResourceDiff;
+}
+
+impl ResourcesDiff for LinuxResources {
+    fn diff(&self, other: &LinuxResources) -> ResourceDiff {
+        let requested = serde_json::to_value(self).unwrap_or(Value::Null);
+        let applied = serde_json::to_value(other).unwrap_or(Value::Null);
+        let mut diff = ResourceDiff::default();
+        diff_values("", &requested, &applied, &mut diff.changes);
+        diff
+    }
+}
+
+fn diff_values(path: &str, requested: &Value, applied: &Value, changes: &mut Vec<ResourceChange>) {
+    if let (Value::Object(r), Value::Object(a)) = (requested, applied) {
+        let mut keys: Vec<&String> = r.keys().chain(a.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let field = if path.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", path, key)
+            };
+            diff_values(
+                &field,
+                r.get(key).unwrap_or(&Value::Null),
+                a.get(key).unwrap_or(&Value::Null),
+                changes,
+            );
+        }
+    } else if requested != applied {
+        let some = |v: &Value| (!v.is_null()).then(|| v.clone());
+        changes.push(ResourceChange {
+            field: path.to_string(),
+            requested: some(requested),
+            applied: some(applied),
+        });
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use cgroups_rs::{hierarchies, Cgroup, CgroupPid};
+    use oci_spec::runtime::LinuxResources;
+    use serde_json::json;
 
     use crate::cgroup::{
-        add_task_to_cgroup, adjust_oom_score, read_process_oom_score, OOM_SCORE_ADJ_MAX,
+        add_task_to_cgroup, adjust_oom_score, read_process_oom_score, ResourcesDiff,
+        OOM_SCORE_ADJ_MAX,
     };
 
     #[test]
@@ -256,4 +331,31 @@ mod tests {
             assert_eq!(new, OOM_SCORE_ADJ_MAX)
         }
     }
+
+    #[test]
+    fn test_resources_diff() {
+        let requested: LinuxResources = serde_json::from_value(json!({
+            "memory": {"limit": 536870912, "swap": 1073741824},
+            "cpu": {"shares": 1024, "cpus": "0-1"},
+            "pids": {"limit": 100}
+        }))
+        .unwrap();
+        let applied: LinuxResources = serde_json::from_value(json!({
+            "memory": {"limit": 268435456, "swap": 1073741824},
+            "cpu": {"shares": 1024}
+        }))
+        .unwrap();
+
+        assert!(requested.diff(&requested).is_empty());
+
+        let diff = requested.diff(&applied);
+        assert_eq!(
+            diff.fields().collect::<Vec<_>>(),
+            vec!["cpu.cpus", "memory.limit", "pids"]
+        );
+        let memory = &diff.changes[1];
+        assert_eq!(memory.requested, Some(json!(536870912)));
+        assert_eq!(memory.applied, Some(json!(268435456)));
+        assert_eq!(diff.changes[2].applied, None);
+    }
 }
@@ -65,6 +65,9 @@ pub enum Error {
     #[error("Failed pre condition: {0}")]
     FailedPreconditionError(String),
 
+    #[error("Already exists: {0}")]
+    AlreadyExistsError(String),
+
     #[error("{context} error: {err}")]
     MountError {
         context: String,
@@ -100,6 +103,9 @@ impl From<Error> for ttrpc::Error {
             Error::FailedPreconditionError(ref s) => {
                 ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::FAILED_PRECONDITION, s))
             }
+            Error::AlreadyExistsError(ref s) => {
+                ttrpc::Error::RpcStatus(ttrpc::get_status(ttrpc::Code::ALREADY_EXISTS, s))
+            }
             Error::Ttrpc(e) => e,
             _ => ttrpc::Error::Others(e.to_string()),
         }
@@ -24,9 +24,13 @@ use std::{
 };
 
 use log::{Metadata, Record};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 use crate::error::Error;
 
+/// Environment variable naming a file the shim writes its debug log to.
+pub const DEBUG_LOG_ENV: &str = "SHIM_DEBUG_LOG";
+
 pub struct FifoLogger {
     file: Mutex<File>,
 }
@@ -74,7 +78,74 @@ impl log::Log for FifoLogger {
     }
 }
 
+/// Logger writing timestamped lines to a file for field debugging, or stderr if the file
+/// can't be opened.
+pub struct DebugLogger {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl DebugLogger {
+    pub fn with_path<P: AsRef<Path>>(path: P) -> Result<DebugLogger, io::Error> {
+        let f = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::with_writer(Box::new(f)))
+    }
+
+    pub fn stderr() -> DebugLogger {
+        Self::with_writer(Box::new(io::stderr()))
+    }
+
+    fn with_writer(out: Box<dyn Write + Send>) -> DebugLogger {
+        DebugLogger {
+            out: Mutex::new(out),
+        }
+    }
+}
+
+impl log::Log for DebugLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let now = OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default();
+            let mut guard = self.out.lock().unwrap();
+            let _ = writeln!(
+                guard.borrow_mut(),
+                "{} [{}] {}",
+                now,
+                record.level(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        let _ = self.out.lock().unwrap().flush();
+    }
+}
+
 pub fn init(debug: bool) -> Result<(), Error> {
+    // A debug log file takes precedence over the fifo and always gets debug messages.
+    if let Some(path) = std::env::var_os(DEBUG_LOG_ENV).filter(|p| !p.is_empty()) {
+        let (logger, open_err) = match DebugLogger::with_path(&path) {
+            Ok(logger) => (logger, None),
+            Err(e) => (DebugLogger::stderr(), Some(e)),
+        };
+        log::set_boxed_logger(Box::new(logger))?;
+        log::set_max_level(log::LevelFilter::Debug);
+        if let Some(e) = open_err {
+            log::error!(
+                "failed to open debug log {}: {}, logging to stderr",
+                Path::new(&path).display(),
+                e
+            );
+        }
+        return Ok(());
+    }
+
     let logger = FifoLogger::new().map_err(io_error!(e, "failed to init logger"))?;
     let level = if debug {
         log::LevelFilter::Debug
@@ -125,4 +196,39 @@ mod tests {
         logger.log(&record);
         logger.flush();
     }
+
+    #[test]
+    fn test_debug_log_file() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("shim-debug.log");
+
+        let logger = DebugLogger::with_path(&path).unwrap();
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Debug)
+                .args(format_args!("first line"))
+                .build(),
+        );
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Warn)
+                .args(format_args!("second line"))
+                .build(),
+        );
+        logger.flush();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("[DEBUG] first line"), "{}", lines[0]);
+        assert!(lines[1].ends_with("[WARN] second line"), "{}", lines[1]);
+        let (timestamp, _) = lines[0].split_once(' ').unwrap();
+        assert!(
+            timestamp.contains('T') && timestamp.ends_with('Z'),
+            "{}",
+            timestamp
+        );
+
+        assert!(DebugLogger::with_path(tmpdir.path().join("missing/shim-debug.log")).is_err());
+    }
 }
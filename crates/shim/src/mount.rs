@@ -418,6 +418,39 @@ impl From<MountExitCode> for Result<()> {
     }
 }
 
+/// Tmpfs options are passed in the data argument of mount(2), validate the ones we know
+/// up front so that a typo gets a clear error instead of EINVAL from the kernel.
+#[cfg(target_os = "linux")]
+fn validate_tmpfs_options(data: &[&str]) -> Result<()> {
+    fn is_size(v: &str, allow_percent: bool) -> bool {
+        let digits =
+            v.trim_end_matches(|c: char| "kKmMgGtTpPeE".contains(c) || (allow_percent && c == '%'));
+        // at most one suffix is allowed
+        v.len() - digits.len() <= 1 && digits.parse::<u64>().is_ok()
+    }
+
+    for opt in data {
+        let (key, value) = match opt.split_once('=') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let valid = match key {
+            "size" | "nr_blocks" => is_size(value, true),
+            "nr_inodes" => is_size(value, false),
+            "mode" => u32::from_str_radix(value, 8).map_or(false, |m| m <= 0o7777),
+            "uid" | "gid" => value.parse::<u32>().is_ok(),
+            _ => true,
+        };
+        if !valid {
+            return Err(Error::InvalidArgument(format!(
+                "invalid tmpfs option {}",
+                opt
+            )));
+        }
+    }
+    Ok(())
+}
+
 #[cfg(not(feature = "async"))]
 #[cfg(target_os = "linux")]
 pub fn mount_rootfs(
@@ -452,6 +485,10 @@ pub fn mount_rootfs(
         }
     });
 
+    if fs_type == Some("tmpfs") {
+        validate_tmpfs_options(&data)?;
+    }
+
     let opt = data.join(",");
     if opt.len() > max_size {
         return Err(other!("mount option is too long"));
@@ -556,6 +593,9 @@ pub fn mount_rootfs(
             data.push(x.as_str())
         }
     });
+    if fs_type == Some("tmpfs") {
+        validate_tmpfs_options(&data)?;
+    }
     let opt = data.join(",");
 
     let data = if !data.is_empty() {
@@ -717,4 +757,55 @@ mod tests {
             assert_eq!(options, expected_options);
         }
     }
+
+    #[test]
+    fn test_validate_tmpfs_options() {
+        for valid in [
+            vec!["size=64m", "mode=1777"],
+            vec!["size=50%", "nr_inodes=1k", "uid=1000", "gid=1000"],
+            vec!["size=65536", "mpol=default"],
+        ] {
+            validate_tmpfs_options(&valid).unwrap();
+        }
+        for invalid in [
+            vec!["size=64mb"],
+            vec!["size=big"],
+            vec!["nr_inodes=10%"],
+            vec!["mode=1778"],
+            vec!["mode=17777"],
+            vec!["uid=-1"],
+        ] {
+            assert!(matches!(
+                validate_tmpfs_options(&invalid),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+    }
+
+    // The sync version forks and waits for the reaper, which only runs in a shim.
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_mount_sized_tmpfs() {
+        if !nix::unistd::getuid().is_root() {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let options = vec![
+            "nosuid".to_string(),
+            "size=1m".to_string(),
+            "mode=1777".to_string(),
+        ];
+        mount_rootfs(Some("tmpfs"), Some("tmpfs"), &options, dir.path()).unwrap();
+
+        let stat = nix::sys::statvfs::statvfs(dir.path()).unwrap();
+        let meta = std::fs::metadata(dir.path()).unwrap();
+        nix::mount::umount(dir.path()).unwrap();
+
+        assert_eq!(stat.blocks() as u64 * stat.fragment_size() as u64, 1 << 20);
+        assert!(stat.flags().contains(nix::sys::statvfs::FsFlags::ST_NOSUID));
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&meta.permissions()) & 0o7777,
+            0o1777
+        );
+    }
 }